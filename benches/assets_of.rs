@@ -0,0 +1,49 @@
+//! Reading only the assets of a stored output, as `UtxoIndexer::assets_of` does, against
+//! deserializing the whole output. Run with `cargo bench --bench assets_of`.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use hydrant::primitives::{Asset, Hash, TxOutput};
+use rkyv::rancor::Error;
+
+type ArchivedTxOutput = <TxOutput as rkyv::Archive>::Archived;
+
+fn output(assets: usize) -> TxOutput {
+    TxOutput {
+        address: vec![1; 57],
+        lovelace: 2_000_000,
+        assets: (0..assets)
+            .map(|i| Asset {
+                policy: Hash([(i % 7) as u8; 28]),
+                name: format!("token {i}").into_bytes(),
+                quantity: i as u64 + 1,
+            })
+            .collect(),
+        datum_hash: Some(Hash([2; 32])),
+        script_ref: None,
+    }
+}
+
+fn bench_assets_of(c: &mut Criterion) {
+    for assets in [1, 10, 100, 1000] {
+        let bytes = rkyv::to_bytes::<Error>(&output(assets)).unwrap();
+        let mut group = c.benchmark_group(format!("{assets} assets"));
+        group.bench_function("full output", |b| {
+            b.iter(|| {
+                let archived = rkyv::access::<ArchivedTxOutput, Error>(black_box(&bytes)).unwrap();
+                rkyv::deserialize::<TxOutput, Error>(archived).unwrap()
+            })
+        });
+        group.bench_function("assets only", |b| {
+            b.iter(|| {
+                let archived = rkyv::access::<ArchivedTxOutput, Error>(black_box(&bytes)).unwrap();
+                rkyv::deserialize::<Vec<Asset>, Error>(&archived.assets).unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_assets_of);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Encoding a tx with `RkyvCodec`, which reuses its buffers and copies the result out, against
+//! `rkyv::to_bytes`, which allocates both afresh, and against the copy alone. Run with
+//! `cargo bench --bench encode`.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use heed::BytesEncode;
+use hydrant::db::RkyvCodec;
+use hydrant::primitives::{Governance, Hash, Tx, TxOutput, TxOutputPointer};
+use rkyv::rancor::Error;
+
+fn tx(outputs: usize) -> Tx {
+    Tx {
+        hash: Hash([1; 32]),
+        inputs: vec![TxOutputPointer::new(Hash([2; 32]), 0)],
+        outputs: (0..outputs)
+            .map(|i| TxOutput {
+                address: vec![3; 57],
+                lovelace: i as u64,
+                assets: vec![],
+                datum_hash: None,
+                script_ref: None,
+            })
+            .collect(),
+        collateral: vec![],
+        collateral_return: None,
+        reference_inputs: vec![],
+        mints: vec![],
+        withdrawals: vec![],
+        scripts: vec![],
+        native_scripts: vec![],
+        metadata: vec![],
+        governance: Governance::default(),
+        stake_certs: vec![],
+        valid: true,
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    for outputs in [10, 5000] {
+        let tx = tx(outputs);
+        let encoded = RkyvCodec::<Tx>::bytes_encode(&tx).unwrap().into_owned();
+        let mut group = c.benchmark_group(format!("tx with {outputs} outputs"));
+        group.bench_function("rkyv::to_bytes", |b| {
+            b.iter(|| rkyv::to_bytes::<Error>(black_box(&tx)).unwrap())
+        });
+        group.bench_function("RkyvCodec", |b| {
+            b.iter(|| RkyvCodec::<Tx>::bytes_encode(black_box(&tx)).unwrap())
+        });
+        group.bench_function("copy only", |b| b.iter(|| black_box(&encoded).to_vec()));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use hydrant::{Db, Network, UtxoIndexerBuilder};
+use hydrant_demo::{MAX_ROLLBACK_BLOCKS, init_tracing, sync_until_shutdown};
+use tracing::info;
+
+const DB_PATH: &str = "../db/hydrant-preprod";
+const NODE_HOST: &str = "preprod-node.play.dev.cardano.org:3001";
+const NETWORK: Network = Network::Preprod;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_tracing();
+    info!(version = env!("CARGO_PKG_VERSION"), network = %NETWORK, "Starting...");
+
+    let db = Db::new(DB_PATH, MAX_ROLLBACK_BLOCKS)?;
+    let indexer = UtxoIndexerBuilder::new("utxo").build(&db.env)?;
+    let indexer = Arc::new(Mutex::new(indexer));
+
+    sync_until_shutdown(&db, vec![indexer], NODE_HOST, NETWORK).await
+}
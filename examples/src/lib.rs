@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use hydrant::{Db, Indexer, Network, Sync};
+use pallas::network::facades::PeerClient;
+use tokio::signal;
+use tracing::{Level, error, info};
+use tracing_subscriber::FmtSubscriber;
+
+pub const MAX_ROLLBACK_BLOCKS: usize = 2160;
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn init_tracing() {
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+}
+
+/// Syncs the indexers from the node until Ctrl+C, SIGTERM or an error, then stops the writer
+/// and persists the database
+pub async fn sync_until_shutdown(
+    db: &Db,
+    indexers: Vec<Arc<Mutex<dyn Indexer + Send>>>,
+    node_host: &str,
+    network: Network,
+) -> Result<()> {
+    info!("Connecting to node...");
+    let node = PeerClient::connect(node_host, network.magic())
+        .await
+        .context("failed to connect to node")?;
+
+    // Listen for chain-sync events until shutdown or error
+    info!("Starting sync...");
+    let mut sync = Sync::new(node, network, db, &indexers).await?;
+    let sync_result = tokio::select! {
+        res = sync.run() => res,
+        res = shutdown_signal() => {
+            tracing::info!("Received shutdown signal");
+            res
+        }
+    };
+    if let Err(error) = sync_result {
+        error!(?error, "Error while syncing");
+    }
+
+    info!("Stopping sync...");
+    if let Err(error) = sync.stop_timeout(SHUTDOWN_TIMEOUT).await {
+        error!(?error, "Error while writing");
+    }
+
+    info!("Persisting database...");
+    db.persist()?;
+
+    Ok(())
+}
+
+async fn shutdown_signal() -> Result<()> {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .context("failed to install Ctrl+C handler")
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .context("failed to install signal handler")?
+            .recv()
+            .await;
+        Ok(())
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<Result<()>>();
+
+    tokio::select! {
+        res = ctrl_c => res,
+        res = terminate => res,
+    }
+}
@@ -0,0 +1,316 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use pallas::network::miniprotocols::Point;
+
+use crate::primitives::Slot;
+
+const TAG_ROLL_FORWARD: u8 = 0;
+const TAG_ROLL_BACKWARD: u8 = 1;
+const TAG_ROLL_BACKWARD_ORIGIN: u8 = 2;
+
+/// When [`ArchiveSink`] starts a new segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveRotation {
+    /// Everything goes to a single segment
+    Never,
+    /// Once the segment holds at least this many bytes
+    Bytes(u64),
+    /// Once a block is at least this many slots past the first block of the segment
+    Slots(u64),
+}
+
+/// Entry of an archive, in the order the writer applied them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveRecord {
+    RollForward {
+        slot: Slot,
+        cbor: Vec<u8>,
+    },
+    /// Blocks archived after the point are no longer part of the chain
+    RollBackward(Point),
+}
+
+impl ArchiveRecord {
+    /// Reads the next record of a segment, or `None` at its end.
+    ///
+    /// A crash while appending may leave the last record of a segment cut short. Such a record
+    /// was never flushed, so the database can't be past it, and it's treated as the end of the
+    /// segment with a warning: the node serves its block again once sync resumes.
+    pub fn read(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut tag = [0];
+        if reader.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        match Self::read_body(tag[0], reader) {
+            Err(err)
+                if err
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == std::io::ErrorKind::UnexpectedEof) =>
+            {
+                tracing::warn!(
+                    tag = tag[0],
+                    "Ignoring a truncated record at the end of an archive segment"
+                );
+                Ok(None)
+            }
+            res => res.map(Some),
+        }
+    }
+
+    fn read_body(tag: u8, reader: &mut impl Read) -> Result<Self> {
+        let record = match tag {
+            TAG_ROLL_FORWARD => {
+                let slot = read_u64(reader)?;
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                let mut cbor = vec![0; u32::from_be_bytes(len) as usize];
+                reader.read_exact(&mut cbor)?;
+                Self::RollForward {
+                    slot: Slot(slot),
+                    cbor,
+                }
+            }
+            TAG_ROLL_BACKWARD => {
+                let slot = read_u64(reader)?;
+                let mut hash = vec![0; 32];
+                reader.read_exact(&mut hash)?;
+                Self::RollBackward(Point::Specific(slot, hash))
+            }
+            TAG_ROLL_BACKWARD_ORIGIN => Self::RollBackward(Point::Origin),
+            tag => anyhow::bail!("unknown archive record tag {tag}"),
+        };
+        Ok(record)
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+type OpenSegment = Box<dyn FnMut(u64) -> std::io::Result<Box<dyn Write + Send>> + Send>;
+
+/// Appends the raw CBOR of every block the writer applies, and a marker for every rollback, to
+/// a series of segments, for rebuilding the chain offline or seeding other indexers without
+/// downloading it again. Read segments back in order with [`ArchiveRecord::read`], undoing the
+/// blocks after each rollback marker.
+///
+/// The archive is flushed right before each time the database is persisted, so it's always
+/// at least as far along as the durable state of the database. After a crash it may hold
+/// blocks the database lost, which the node serves again once sync resumes. To keep replays
+/// consistent, a rollback marker to the database's tip is written whenever the writer starts,
+/// discarding whatever was archived past it.
+pub struct ArchiveSink {
+    open: OpenSegment,
+    rotation: ArchiveRotation,
+    segment: Option<Box<dyn Write + Send>>,
+    /// Index of the next segment to open
+    next_segment: u64,
+    segment_bytes: u64,
+    segment_start_slot: Option<u64>,
+}
+
+impl ArchiveSink {
+    /// Archives to the writers returned by `open`, which is passed the index of each segment,
+    /// counting up from `first_segment`. `flush` is called on them in lockstep with the
+    /// database being persisted, so it should make the data durable, e.g. by fsyncing.
+    pub fn new(
+        first_segment: u64,
+        rotation: ArchiveRotation,
+        open: impl FnMut(u64) -> std::io::Result<Box<dyn Write + Send>> + Send + 'static,
+    ) -> Self {
+        Self {
+            open: Box::new(open),
+            rotation,
+            segment: None,
+            next_segment: first_segment,
+            segment_bytes: 0,
+            segment_start_slot: None,
+        }
+    }
+
+    /// Archives to numbered files in `dir`, continuing after the last segment already there
+    pub fn files(dir: impl AsRef<Path>, rotation: ArchiveRotation) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut first_segment = 0;
+        for entry in std::fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let index = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".archive"))
+                .and_then(|index| index.parse::<u64>().ok());
+            if let Some(index) = index {
+                first_segment = first_segment.max(index + 1);
+            }
+        }
+        Ok(Self::new(first_segment, rotation, move |index| {
+            let file = std::fs::File::create_new(segment_path(&dir, index))?;
+            Ok(Box::new(SyncedFile(std::io::BufWriter::new(file))) as Box<dyn Write + Send>)
+        }))
+    }
+
+    pub(crate) fn roll_forward(&mut self, slot: u64, cbor: &[u8]) -> Result<()> {
+        let rotate = match (self.rotation, self.segment_start_slot) {
+            (ArchiveRotation::Bytes(bytes), _) => self.segment_bytes >= bytes,
+            (ArchiveRotation::Slots(slots), Some(start)) => slot.saturating_sub(start) >= slots,
+            _ => false,
+        };
+        if rotate {
+            self.flush()?;
+            self.segment = None;
+            self.segment_start_slot = None;
+        }
+        self.segment_start_slot.get_or_insert(slot);
+
+        let len = u32::try_from(cbor.len()).context("block too large to archive")?;
+        let mut record = Vec::with_capacity(13 + cbor.len());
+        record.push(TAG_ROLL_FORWARD);
+        record.extend_from_slice(&slot.to_be_bytes());
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(cbor);
+        self.write(&record)
+    }
+
+    pub(crate) fn roll_backward(&mut self, point: &Point) -> Result<()> {
+        let mut record = vec![];
+        match point {
+            Point::Origin => record.push(TAG_ROLL_BACKWARD_ORIGIN),
+            Point::Specific(slot, hash) => {
+                anyhow::ensure!(hash.len() == 32, "invalid block hash length {}", hash.len());
+                record.push(TAG_ROLL_BACKWARD);
+                record.extend_from_slice(&slot.to_be_bytes());
+                record.extend_from_slice(hash);
+            }
+        }
+        self.write(&record)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        if let Some(segment) = self.segment.as_mut() {
+            segment.flush().context("failed to flush archive")?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<()> {
+        let segment = match self.segment.as_mut() {
+            Some(segment) => segment,
+            None => {
+                let segment = (self.open)(self.next_segment).with_context(|| {
+                    format!("failed to open archive segment {}", self.next_segment)
+                })?;
+                self.next_segment += 1;
+                self.segment_bytes = 0;
+                self.segment.insert(segment)
+            }
+        };
+        segment
+            .write_all(record)
+            .context("failed to write archive")?;
+        self.segment_bytes += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Path of a segment written by [`ArchiveSink::files`]
+pub fn segment_path(dir: impl AsRef<Path>, index: u64) -> PathBuf {
+    dir.as_ref().join(format!("{index:08}.archive"))
+}
+
+/// File that's fsynced when flushed
+struct SyncedFile(std::io::BufWriter<std::fs::File>);
+
+impl Write for SyncedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::hash;
+
+    #[test]
+    fn test_archive_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ArchiveSink::files(dir.path(), ArchiveRotation::Bytes(200)).unwrap();
+        sink.roll_backward(&Point::Origin).unwrap();
+        sink.roll_forward(10, &[1; 100]).unwrap();
+        sink.roll_forward(20, &[2; 100]).unwrap();
+        let rollback = Point::Specific(10, hash::<32>(1).to_vec());
+        sink.roll_backward(&rollback).unwrap();
+        // Past 200 bytes, so the block starts a new segment
+        sink.roll_forward(30, &[3; 100]).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+
+        let read = |index| {
+            let mut file = std::fs::File::open(segment_path(dir.path(), index)).unwrap();
+            let mut records = vec![];
+            while let Some(record) = ArchiveRecord::read(&mut file).unwrap() {
+                records.push(record);
+            }
+            records
+        };
+        let block = |slot, byte| ArchiveRecord::RollForward {
+            slot: Slot(slot),
+            cbor: vec![byte; 100],
+        };
+        assert_eq!(
+            read(0),
+            [
+                ArchiveRecord::RollBackward(Point::Origin),
+                block(10, 1),
+                block(20, 2),
+                ArchiveRecord::RollBackward(rollback),
+            ]
+        );
+        assert_eq!(read(1), [block(30, 3)]);
+
+        // Continues after the existing segments
+        let mut sink = ArchiveSink::files(dir.path(), ArchiveRotation::Never).unwrap();
+        sink.roll_forward(40, &[4; 100]).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(read(2), [block(40, 4)]);
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = ArchiveSink::files(dir.path(), ArchiveRotation::Never).unwrap();
+        sink.roll_forward(10, &[1; 100]).unwrap();
+        sink.roll_forward(20, &[2; 100]).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+
+        // As if the process crashed halfway through writing the second block
+        let path = segment_path(dir.path(), 0);
+        let len = std::fs::metadata(&path).unwrap().len();
+        for cut in [50, 110] {
+            let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_len(len - cut).unwrap();
+            drop(file);
+
+            let mut file = std::fs::File::open(&path).unwrap();
+            let first = ArchiveRecord::read(&mut file).unwrap();
+            assert_eq!(
+                first,
+                Some(ArchiveRecord::RollForward {
+                    slot: Slot(10),
+                    cbor: vec![1; 100],
+                })
+            );
+            assert_eq!(ArchiveRecord::read(&mut file).unwrap(), None);
+        }
+    }
+}
@@ -1,14 +1,41 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use anyhow::Result;
 use heed::{BytesDecode, BytesEncode};
-use rkyv::api::high::{HighSerializer, HighValidator};
+use rkyv::api::high::{HighDeserializer, HighSerializer, HighValidator};
 use rkyv::bytecheck::CheckBytes;
 use rkyv::rancor::Error;
-use rkyv::ser::allocator::ArenaHandle;
+use rkyv::ser::allocator::{Arena, ArenaHandle};
 use rkyv::util::AlignedVec;
 use rkyv::{Archive, Serialize};
 
+/// Enough for typical blocks and txs, see [`set_encode_capacity`]
+const DEFAULT_ENCODE_CAPACITY: usize = 64 * 1024;
+
+static ENCODE_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_ENCODE_CAPACITY);
+
+thread_local! {
+    /// Output buffer and scratch arena reused across encodes, so they only grow once per thread
+    static ENCODE_BUFFERS: RefCell<(AlignedVec, Arena)> = RefCell::new((
+        AlignedVec::with_capacity(ENCODE_CAPACITY.load(Ordering::Relaxed)),
+        Arena::with_capacity(ENCODE_CAPACITY.load(Ordering::Relaxed)),
+    ));
+}
+
+/// Pre-sizes the output buffer and scratch arena [`RkyvCodec`] serializes with, which are kept
+/// per thread and grow to the largest value encoded so far. Raising it to the largest expected
+/// value (e.g. a block with thousands of outputs) spares the first encodes of such values from
+/// reallocating. Applies to buffers and arenas smaller than `bytes` on their next use.
+pub fn set_encode_capacity(bytes: usize) {
+    ENCODE_CAPACITY.store(bytes, Ordering::Relaxed);
+}
+
 /// Wrapper for using Rkyv serialization/access with Heed
 /// for zero-copy access to the database
+///
+/// The archived layout is specific to rkyv and the Rust types, so only this crate can read the
+/// tables. Use [`CborCodec`] for tables other processes read directly.
 pub struct RkyvCodec<T>(std::marker::PhantomData<T>);
 
 impl<'a, T> BytesEncode<'a> for RkyvCodec<T>
@@ -23,8 +50,31 @@ where
         std::borrow::Cow<'a, [u8]>,
         Box<dyn std::error::Error + std::marker::Send + std::marker::Sync>,
     > {
-        let bytes = rkyv::to_bytes::<Error>(item).map_err(|e| Box::new(e) as Box<_>)?;
-        Ok(std::borrow::Cow::Owned(bytes.to_vec()))
+        let capacity = ENCODE_CAPACITY.load(Ordering::Relaxed);
+        let bytes = ENCODE_BUFFERS.with_borrow_mut(|(buffer, arena)| {
+            buffer.clear();
+            buffer.reserve(capacity);
+            if arena.capacity() < capacity {
+                *arena = Arena::with_capacity(capacity);
+            }
+            // Taken rather than borrowed since the serializer owns its writer. An error drops
+            // the buffer, which is then regrown on the next encode.
+            let writer = std::mem::take(buffer);
+            let writer = rkyv::api::high::to_bytes_in_with_alloc::<_, _, Error>(
+                item,
+                writer,
+                arena.acquire(),
+            )?;
+            // Copied out rather than borrowed: heed may encode a key and its value with this
+            // codec before writing either, and the second encode would overwrite the first. The
+            // copy is the one allocation left per encode, see `benches/encode.rs`.
+            let bytes = writer.to_vec();
+            *buffer = writer;
+            Ok::<_, Error>(bytes)
+        });
+        Ok(std::borrow::Cow::Owned(
+            bytes.map_err(|e| Box::new(e) as Box<_>)?,
+        ))
     }
 }
 
@@ -42,3 +92,112 @@ where
         Ok(rkyv::access::<T::Archived, _>(bytes)?)
     }
 }
+
+/// Deserializes a value written with `rkyv::to_bytes` from bytes that may not be aligned for
+/// rkyv, like block scratch data, by copying them into an aligned buffer first
+pub(crate) fn from_unaligned_bytes<T>(bytes: &[u8]) -> Result<T>
+where
+    T: Archive,
+    T::Archived: for<'b> CheckBytes<HighValidator<'b, Error>>
+        + rkyv::Deserialize<T, HighDeserializer<Error>>,
+{
+    let mut aligned = AlignedVec::<16>::new();
+    aligned.extend_from_slice(bytes);
+    Ok(rkyv::from_bytes::<T, Error>(&aligned)?)
+}
+
+/// Stores values as CBOR with minicbor, for tables read directly by other processes or
+/// languages. Tables pick their codec through their `Database` type, so an indexer can use it
+/// for some tables and [`RkyvCodec`] for the rest. Unlike rkyv, values are decoded into owned
+/// copies on every read, which is noticeably slower for large values like txs, and the CBOR
+/// layout is whatever the type's `minicbor::Encode` impl writes, so it's on the indexer to keep
+/// it stable.
+pub struct CborCodec<T>(std::marker::PhantomData<T>);
+
+impl<'a, T> BytesEncode<'a> for CborCodec<T>
+where
+    T: minicbor::Encode<()> + 'a,
+{
+    type EItem = T;
+
+    fn bytes_encode(
+        item: &'a Self::EItem,
+    ) -> Result<
+        std::borrow::Cow<'a, [u8]>,
+        Box<dyn std::error::Error + std::marker::Send + std::marker::Sync>,
+    > {
+        Ok(std::borrow::Cow::Owned(minicbor::to_vec(item)?))
+    }
+}
+
+impl<'a, T> BytesDecode<'a> for CborCodec<T>
+where
+    T: minicbor::Decode<'a, ()>,
+{
+    type DItem = T;
+
+    fn bytes_decode(
+        bytes: &'a [u8],
+    ) -> Result<Self::DItem, Box<dyn std::error::Error + std::marker::Send + std::marker::Sync>>
+    {
+        Ok(minicbor::decode(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::Tx;
+    use crate::test_utils::{output, tx};
+
+    #[test]
+    fn test_encode_reuses_buffer() {
+        let outputs = (0..5000).map(|i| output(b"addr", i)).collect();
+        let large = tx(1, vec![], outputs);
+        let bytes = RkyvCodec::<Tx>::bytes_encode(&large).unwrap();
+        assert!(bytes.len() > DEFAULT_ENCODE_CAPACITY);
+        let decoded = RkyvCodec::<Tx>::bytes_decode(&bytes).unwrap();
+        assert_eq!(decoded.outputs.len(), 5000);
+
+        // The buffer kept its grown capacity, so encoding another value doesn't shrink it
+        let small = RkyvCodec::<Tx>::bytes_encode(&tx(2, vec![], vec![])).unwrap();
+        assert!(small.len() < DEFAULT_ENCODE_CAPACITY);
+        let capacity = ENCODE_BUFFERS.with_borrow(|(buffer, _)| buffer.capacity());
+        assert!(capacity >= bytes.len());
+    }
+
+    #[test]
+    fn test_encode_capacity_resizes_arena() {
+        // On a thread of its own, so other tests keep their buffers
+        std::thread::spawn(|| {
+            RkyvCodec::<Tx>::bytes_encode(&tx(1, vec![], vec![])).unwrap();
+            set_encode_capacity(4 * DEFAULT_ENCODE_CAPACITY);
+            RkyvCodec::<Tx>::bytes_encode(&tx(1, vec![], vec![])).unwrap();
+            set_encode_capacity(DEFAULT_ENCODE_CAPACITY);
+            ENCODE_BUFFERS.with_borrow(|(buffer, arena)| {
+                assert!(buffer.capacity() >= 4 * DEFAULT_ENCODE_CAPACITY);
+                assert!(arena.capacity() >= 4 * DEFAULT_ENCODE_CAPACITY);
+            });
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_cbor_table() {
+        let (_dir, db) = crate::test_utils::temp_db();
+        let mut wtxn = db.env.write_txn().unwrap();
+        let table: heed::Database<heed::types::Str, CborCodec<Vec<(u64, String)>>> =
+            db.env.create_database(&mut wtxn, "cbor").unwrap();
+        let value = vec![(1, "a".to_string()), (2, "b".to_string())];
+        table.put(&mut wtxn, "key", &value).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = db.env.read_txn().unwrap();
+        assert_eq!(table.get(&rtxn, "key").unwrap(), Some(value.clone()));
+        // Plain CBOR, readable without this crate: an array of 2 arrays
+        let raw = table.remap_data_type::<heed::types::Bytes>();
+        let raw = raw.get(&rtxn, "key").unwrap().unwrap();
+        assert_eq!(raw[..3], [0x82, 0x82, 0x01]);
+    }
+}
@@ -1,14 +1,23 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 
+use heed::types::{DecodeIgnore, Str};
 use heed::{Database, WithTls};
 use tracing::debug;
 
+use super::options::ResizePolicy;
+
 /// Wrapper around LMDB to provide safe resizing, error on duplicate database names, and snapshotting
 #[derive(Debug, Clone)]
 pub struct Env {
     env: heed::Env<WithTls>,
     db_names: Arc<Mutex<Vec<String>>>,
+    /// Number of writers running, see [`Env::seal_tables`]
+    tables_sealed: Arc<AtomicUsize>,
     resize_lock: Arc<RwLock<()>>,
+    resize_policy: ResizePolicy,
+    /// Free space below which the map is grown
+    min_free_space: usize,
     page_size: usize,
 }
 
@@ -17,13 +26,35 @@ impl From<heed::Env> for Env {
         Self {
             env,
             db_names: Arc::new(Mutex::new(vec![])),
+            tables_sealed: Arc::new(AtomicUsize::new(0)),
             resize_lock: Arc::new(RwLock::new(())),
+            resize_policy: ResizePolicy::default(),
+            min_free_space: 1024 * 1024 * 1024, // 1GB
             page_size: page_size::get(),
         }
     }
 }
 
 impl Env {
+    pub(crate) fn with_resize_policy(mut self, resize_policy: ResizePolicy) -> Self {
+        self.resize_policy = resize_policy;
+        self
+    }
+
+    pub(crate) fn with_min_free_space(mut self, min_free_space: usize) -> Self {
+        self.min_free_space = min_free_space;
+        self
+    }
+
+    /// Opens the table, creating it if it doesn't exist yet. This claims the name for the
+    /// caller: creating the same name twice errors with [`Error::DatabaseExists`], which catches
+    /// two indexers accidentally writing to the same table. Use it for the tables an indexer
+    /// owns, whether or not they were created by a previous run.
+    ///
+    /// Tables must be created before sync starts, i.e. indexers are constructed before being
+    /// handed to [`Sync`](crate::Sync): creating one while the writer is running errors with
+    /// [`Error::TableCreationDuringSync`], since the write txn would race with the writer's
+    /// resizes.
     pub fn create_database<KC, DC>(
         &self,
         wtxn: &mut heed::RwTxn,
@@ -33,6 +64,9 @@ impl Env {
         KC: 'static,
         DC: 'static,
     {
+        if self.tables_sealed.load(Ordering::SeqCst) > 0 {
+            return Err(Error::TableCreationDuringSync(name.to_string()));
+        }
         let mut db_names = self.db_names.lock().expect("db_names mutex poisoned");
         if db_names.contains(&name.to_string()) {
             return Err(Error::DatabaseExists(name.to_string()));
@@ -41,6 +75,7 @@ impl Env {
         Ok(self.env.create_database(wtxn, Some(name))?)
     }
 
+    /// Like [`Env::create_database`], with LMDB flags such as `DUP_SORT`
     pub fn create_database_with_flags<KC, DC>(
         &self,
         wtxn: &mut heed::RwTxn,
@@ -51,6 +86,9 @@ impl Env {
         KC: 'static,
         DC: 'static,
     {
+        if self.tables_sealed.load(Ordering::SeqCst) > 0 {
+            return Err(Error::TableCreationDuringSync(name.to_string()));
+        }
         let mut db_names = self.db_names.lock().expect("db_names mutex poisoned");
         if db_names.contains(&name.to_string()) {
             return Err(Error::DatabaseExists(name.to_string()));
@@ -65,11 +103,42 @@ impl Env {
             .create(wtxn)?)
     }
 
+    /// Opens an existing table without claiming its name, or `None` if no previous run created
+    /// it. Meant for attaching to tables owned by another indexer, e.g. reading them from a
+    /// query helper or checking whether an indexer being added already has data to backfill
+    /// from. Never write to a table opened this way while its owner is running.
+    ///
+    /// The table is opened in a read txn of its own, which is committed so the handle stays
+    /// valid in every later txn. Like [`Env::read_txn`], don't call it on a thread that already
+    /// holds a txn.
+    pub fn open_database<KC, DC>(&self, name: &str) -> Result<Option<Database<KC, DC>>>
+    where
+        KC: 'static,
+        DC: 'static,
+    {
+        let RoTxn { txn, _guard } = self.read_txn()?;
+        let database = self.env.open_database(&txn, Some(name))?;
+        txn.commit()?;
+        Ok(database)
+    }
+
+    /// Forbids creating tables until the returned guard is dropped. Held by the writer task
+    /// for as long as it runs. Counted, so with several writers on the same environment
+    /// tables stay sealed until the last one stops.
+    pub(crate) fn seal_tables(&self) -> TableSeal {
+        self.tables_sealed.fetch_add(1, Ordering::SeqCst);
+        TableSeal(self.tables_sealed.clone())
+    }
+
     pub fn write_txn(&self) -> Result<RwTxn<'_>> {
         let _guard = self.resize_lock.read().expect("resize lock poisoned");
         let txn = self.env.write_txn()?;
         Ok(RwTxn { txn, _guard })
     }
+    /// Opens a read txn, which holds the resize read-lock until dropped. Resizing waits for all
+    /// open txns, so queries should open a txn, read what they need and drop it, rather than
+    /// keeping it open while the caller consumes the results. Opening a second txn on a thread
+    /// that already holds one can deadlock with a pending resize.
     pub fn read_txn(&self) -> Result<RoTxn<'_>> {
         let _guard = self.resize_lock.read().expect("resize lock poisoned");
         let txn = self.env.read_txn()?;
@@ -79,33 +148,84 @@ impl Env {
         Ok(self.env.force_sync()?)
     }
 
-    pub(crate) fn resize(&self) -> Result<()> {
-        let info = self.env.info();
+    /// Bytes of the map in use, up to the last page written
+    pub(crate) fn used_size(&self) -> usize {
+        self.page_size * self.env.info().last_page_number
+    }
+
+    pub(crate) fn info(&self) -> heed::EnvInfo {
+        self.env.info()
+    }
+
+    /// Every table in the environment along with its number of entries, ordered by name
+    pub(crate) fn table_sizes(&self, rtxn: &heed::RoTxn) -> Result<Vec<(String, u64)>> {
+        // The unnamed table lists the names of the others
+        let Some(tables) = self.env.open_database::<Str, DecodeIgnore>(rtxn, None)? else {
+            return Ok(vec![]);
+        };
+        let names = tables
+            .iter(rtxn)?
+            .map(|res| Ok(res?.0.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut sizes = Vec::with_capacity(names.len());
+        for name in names {
+            let table = self
+                .env
+                .open_database::<DecodeIgnore, DecodeIgnore>(rtxn, Some(&name))?;
+            if let Some(table) = table {
+                sizes.push((name, table.len(rtxn)?));
+            }
+        }
+        Ok(sizes)
+    }
 
-        let used_size = self.page_size * info.last_page_number;
-        let current_size = info.map_size;
+    pub(crate) fn resize(&self) -> Result<()> {
+        let used_size = self.used_size();
+        let current_size = self.env.info().map_size;
         let free_size = current_size - used_size;
-        let minimum_free_space = 1024 * 1024 * 1024; // 1GB
-
-        if free_size < minimum_free_space || free_size > minimum_free_space * 2 {
-            let new_size = current_size + minimum_free_space;
-            let new_size = new_size + new_size % self.page_size; // Round up to next page
-
-            let lock = self.resize_lock.write().unwrap();
-            self.env.clear_stale_readers()?;
-            if self.env.info().number_of_readers != 0 {
-                return Err(Error::ActiveReadersOnResize(
-                    self.env.info().number_of_readers,
-                ));
+
+        if free_size < self.min_free_space {
+            self.grow_to(self.min_free_space)?;
+        }
+
+        Ok(())
+    }
+
+    /// Grows the map by one step of the resize policy. Waits for every txn of this process to
+    /// close, which is all LMDB requires: other processes pick up the new size when they begin
+    /// their next txn.
+    pub(crate) fn grow(&self) -> Result<()> {
+        self.grow_to(0)
+    }
+
+    /// Grows the map by as many steps of the resize policy as it takes to leave `free_size`
+    /// bytes free, at least one, so a step smaller than the minimum free space still resizes
+    /// only once
+    fn grow_to(&self, free_size: usize) -> Result<()> {
+        let lock = self.resize_lock.write().expect("resize lock poisoned");
+        let used_size = self.used_size();
+        let current_size = self.env.info().map_size;
+        let mut new_size = current_size;
+        loop {
+            new_size += self.resize_policy.increment(new_size);
+            if new_size.saturating_sub(used_size) >= free_size {
+                break;
             }
-            unsafe { self.env.resize(new_size)? }
-            debug!(?current_size, ?new_size, "Resized database");
-            drop(lock)
         }
+        let new_size = new_size.div_ceil(self.page_size) * self.page_size; // Round up to next page
 
+        unsafe { self.env.resize(new_size)? }
+        debug!(?current_size, ?new_size, "Resized database");
+        drop(lock);
         Ok(())
     }
 
+    /// Copies the environment to a file. Both the file and its directory entry are fsynced
+    /// before returning, so the snapshot survives a crash once this returns `Ok`.
+    ///
+    /// The copy is written to a `.tmp` sibling and renamed into place once complete, so a crash
+    /// mid-snapshot never leaves a partial file at `path`. A stale temp file from a previous
+    /// attempt is overwritten.
     pub(crate) fn snapshot(
         &self,
         path: impl AsRef<std::path::Path>,
@@ -115,15 +235,60 @@ impl Env {
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        let mut file = if overwrite {
-            std::fs::File::create(path)
-        } else {
-            std::fs::File::create_new(path)
-        }?;
+        if !overwrite && path.try_exists()? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("snapshot already exists: {}", path.display()),
+            )
+            .into());
+        }
 
-        Ok(self
-            .env
-            .copy_to_file(&mut file, heed::CompactionOption::Enabled)?)
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = std::path::PathBuf::from(tmp_path);
+        let res = self.copy_to_path(&tmp_path).and_then(|()| {
+            std::fs::rename(&tmp_path, path)?;
+            Ok(sync_parent_dir(path)?)
+        });
+        if res.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        res
+    }
+
+    fn copy_to_path(&self, path: &std::path::Path) -> Result<()> {
+        // The copy runs in a read txn of its own, which must not be open while resizing
+        let _guard = self.resize_lock.read().expect("resize lock poisoned");
+        let mut file = std::fs::File::create(path)?;
+        self.env
+            .copy_to_file(&mut file, heed::CompactionOption::Enabled)?;
+        Ok(file.sync_all()?)
+    }
+}
+
+/// Fsyncs the directory containing the path, persisting the creation/rename of the entry
+fn sync_parent_dir(path: &std::path::Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+        std::fs::File::open(parent)?.sync_all()?;
+    }
+    // Directories can't be opened as files on other platforms
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Allows creating tables again once it and every other seal are dropped, see
+/// [`Env::seal_tables`]
+pub(crate) struct TableSeal(Arc<AtomicUsize>);
+
+impl Drop for TableSeal {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -171,11 +336,17 @@ pub enum Error {
     #[error("database name already in use: {0}")]
     DatabaseExists(String),
 
-    /// Readers were active while resizing the environment. This usually means someone is holding a
-    /// read transaction in a separate process.
+    /// Readers were active while resizing the environment. No longer returned: resizing waits
+    /// for the txns of this process instead, and LMDB doesn't require other processes to close
+    /// theirs.
+    #[deprecated(note = "resizing waits for open txns instead of failing")]
     #[error("cannot resize while readers are active; is another process accessing the database?")]
     ActiveReadersOnResize(u32),
 
+    /// A table was created while sync is running
+    #[error("cannot create table {0} while sync is running, create it before starting sync")]
+    TableCreationDuringSync(String),
+
     /// I/O error: can come from the standard library or be a rewrapped [`MdbError`].
     #[error("{0}")]
     Io(#[from] std::io::Error),
@@ -211,3 +382,59 @@ impl From<heed::Error> for Error {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use heed::types::Str;
+
+    use crate::test_utils::temp_db;
+
+    #[test]
+    fn test_open_existing_database() {
+        let (_dir, db) = temp_db();
+        let open = || db.env.open_database::<Str, Str>("table").unwrap().is_some();
+        assert!(!open());
+
+        let mut wtxn = db.env.write_txn().unwrap();
+        let table = db
+            .env
+            .create_database::<Str, Str>(&mut wtxn, "table")
+            .unwrap();
+        table.put(&mut wtxn, "key", "value").unwrap();
+        wtxn.commit().unwrap();
+        assert!(open());
+
+        // The handle outlives the txn it was opened in, and opening leaves the name claimed by
+        // its owner alone
+        let table = db.env.open_database::<Str, Str>("table").unwrap().unwrap();
+        let rtxn = db.env.read_txn().unwrap();
+        assert_eq!(table.get(&rtxn, "key").unwrap(), Some("value"));
+        drop(rtxn);
+        let mut wtxn = db.env.write_txn().unwrap();
+        assert!(
+            db.env
+                .create_database::<Str, Str>(&mut wtxn, "table")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_seal_tables() {
+        let (_dir, db) = temp_db();
+        let create = |name| {
+            let mut wtxn = db.env.write_txn().unwrap();
+            let res = db.env.create_database::<Str, Str>(&mut wtxn, name);
+            wtxn.commit().unwrap();
+            res.is_ok()
+        };
+        let first = db.env.seal_tables();
+        let second = db.env.seal_tables();
+        assert!(!create("a"));
+
+        // Still sealed while another writer runs
+        drop(first);
+        assert!(!create("a"));
+        drop(second);
+        assert!(create("a"));
+    }
+}
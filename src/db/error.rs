@@ -0,0 +1,79 @@
+use crate::network::Network;
+
+/// Errors callers may want to handle, returned wrapped in an [`anyhow::Error`] so they can be
+/// matched with `downcast_ref`
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The database was synced against a different network than the node is on. Syncing would
+    /// mix blocks from both chains into the indexes.
+    #[error("network mismatch. database: {database}, node: {node}")]
+    NetworkMismatch { database: Network, node: Network },
+
+    /// A query matched more results than allowed by the indexer's
+    /// [`QueryLimits`](crate::QueryLimits). Use a paginated query instead.
+    #[error("query matched {found} results, more than the limit of {limit}")]
+    ResultTooLarge { found: usize, limit: usize },
+
+    /// The rollback target is older than the volatile window, so the blocks after it can't be
+    /// undone. `oldest_slot` is the furthest back the database can currently go.
+    #[error(
+        "cannot roll back to slot {requested_slot}, the oldest reachable slot is {oldest_slot}"
+    )]
+    RollbackTooDeep {
+        requested_slot: u64,
+        oldest_slot: u64,
+    },
+
+    /// The node served a block that's already in the volatile window, at `existing_slot`. Only
+    /// returned with [`DbOptions::reject_duplicate_blocks`](super::DbOptions), otherwise the
+    /// block is skipped with a warning.
+    #[error("block {hash} at slot {slot} was already applied at slot {existing_slot}")]
+    DuplicateBlock {
+        hash: String,
+        slot: u64,
+        existing_slot: u64,
+    },
+
+    /// The indexer's [`config_fingerprint`](crate::Indexer::config_fingerprint) differs from
+    /// the one it was registered with, e.g. a `UtxoIndexer` reopened with a different asset
+    /// whitelist. Its tables were built under the old configuration, so the database has to be
+    /// resynced from the origin, or the indexer given a new id.
+    #[error(
+        "indexer {id} was configured differently when the database was synced, resync or use a new id"
+    )]
+    IndexerConfigChanged { id: String },
+
+    /// The indexer's [`schema_version`](crate::Indexer::schema_version) differs from the one
+    /// its tables were built with, and it has no migration from it. Only this indexer's tables
+    /// are affected, but they have to be rebuilt by resyncing from the origin, or the indexer
+    /// given a migration.
+    #[error(
+        "indexer {id} has schema version {indexer} but its tables were built with version {database}"
+    )]
+    IndexerSchemaChanged {
+        id: String,
+        database: u32,
+        indexer: u32,
+    },
+
+    /// Some of the blocks a disabled indexer missed already left the volatile window, so it
+    /// can't be backfilled. The database has to be resynced for it to catch up.
+    #[error(
+        "indexer {id} missed blocks from slot {from_slot}, some of which already left the volatile window"
+    )]
+    BackfillTooFar { id: String, from_slot: u64 },
+
+    /// The database was created on a system with a different memory page size, e.g. restored
+    /// from a backup taken on another OS. The map is resized in multiples of the page size, so
+    /// it would be sized wrongly; open the database on a system with the same page size.
+    #[error("database was created with a page size of {database} bytes, this system's is {system}")]
+    PageSizeMismatch { database: u64, system: u64 },
+
+    /// The database was written by a version of this crate that stores volatile blocks or txs
+    /// in another layout, which can't be read back. It has to be resynced from the origin
+    /// with this version.
+    #[error(
+        "database has format version {database}, this version of the crate reads {current}, resync required"
+    )]
+    FormatVersionMismatch { database: u32, current: u32 },
+}
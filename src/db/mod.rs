@@ -1,67 +1,399 @@
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
 use anyhow::{Context, Result};
 use heed::byteorder::BigEndian;
-use heed::types::{Str, U64, Unit};
+use heed::types::{Bytes, DecodeIgnore, Str, U64};
 use heed::{Database, EnvOpenOptions};
-use pallas::ledger::traverse::MultiEraBlock;
 use pallas::network::miniprotocols::Point;
 use tracing::info;
 
-use crate::indexer::IndexerList;
+use crate::indexer::{Indexer, IndexerList};
+use crate::metrics::{RollbackSummary, SyncMetrics, SyncPhase};
+use crate::network::Network;
 use crate::primitives::{
-    BlockHash, DatumHash, Tx, TxHash, TxOutput, TxOutputPointer, VolatileBlock,
+    Block, BlockHash, BlockNumber, Datum, DatumHash, Script, Slot, Tx, TxHash, TxOutput,
+    TxOutputPointer, VolatileBlock, VolatileDatum, u64_bounds,
 };
 
 mod codec;
 mod env;
+mod error;
+mod options;
+#[cfg(feature = "serde")]
+mod status;
 
-pub use codec::RkyvCodec;
+pub(crate) use codec::from_unaligned_bytes;
+pub use codec::{CborCodec, RkyvCodec, set_encode_capacity};
 pub use env::Env;
+pub use error::Error;
+pub use options::{BlockValidator, DbOptions, ResizePolicy, WriteRateLimit};
 
 #[derive(Clone)]
 pub struct Db {
     pub max_rollback_blocks: usize,
+    pub commit_interval: usize,
+    pub write_buffer_bytes: usize,
+    pub write_rate_limit: Option<WriteRateLimit>,
+    /// Error on duplicate blocks rather than skipping them, see [`Error::DuplicateBlock`]
+    pub reject_duplicate_blocks: bool,
     pub env: Env,
+    metrics: Arc<SyncMetrics>,
+    timings: bool,
+    block_validator: Option<BlockValidator>,
 
     // big endian ints are lexicographically ordered
     slots: Database<U64<BigEndian>, RkyvCodec<BlockHash>>,
+    /// Block number -> block hash, kept alongside `slots` since slots have gaps
+    numbers: Database<U64<BigEndian>, RkyvCodec<BlockHash>>,
     volatile_tx: Database<RkyvCodec<TxHash>, RkyvCodec<Tx>>,
     volatile_block: Database<RkyvCodec<BlockHash>, RkyvCodec<VolatileBlock>>,
-    indexer_ids: Database<Str, Unit>,
+    volatile_datum: Database<RkyvCodec<DatumHash>, RkyvCodec<VolatileDatum>>,
+    /// Block hash followed by indexer id -> data, see [`Db::put_block_scratch`]
+    block_scratch: Database<Bytes, Bytes>,
+    /// Registered indexer ids -> their config fingerprints
+    indexer_ids: Database<Str, Bytes>,
+    meta: Database<Str, Bytes>,
+}
+
+const META_NETWORK_MAGIC: &str = "network_magic";
+/// Present once the indexer ids have been registered, which may be an empty set
+const META_INDEXERS_REGISTERED: &str = "indexers_registered";
+const META_TOTAL_TXS: &str = "total_txs";
+/// Page size of the system the database was created on, see [`Error::PageSizeMismatch`]
+const META_PAGE_SIZE: &str = "page_size";
+/// Format version the database was created with, see [`FORMAT_VERSION`]. Absent for databases
+/// created before it was recorded, which count as version 0 unless they're still empty.
+const META_FORMAT_VERSION: &str = "format_version";
+
+/// Version of the layout of the values the database stores itself, such as volatile blocks and
+/// txs, to bump whenever one of them changes. rkyv values can't be read with another layout,
+/// so a database with a different version is refused with [`Error::FormatVersionMismatch`].
+/// Databases synced before the version was recorded hold an older layout and count as 0.
+const FORMAT_VERSION: u32 = 1;
+/// Followed by an indexer id -> first slot the disabled indexer missed, see
+/// [`Db::disable_indexer`]
+const META_DISABLED_PREFIX: &str = "disabled_indexer:";
+/// Followed by an indexer id -> schema version its tables were built with, see
+/// [`Indexer::schema_version`]
+const META_SCHEMA_VERSION_PREFIX: &str = "schema_version:";
+/// Block scratch id under which blocks are kept in full while an indexer is disabled. The
+/// leading nul keeps it apart from indexer ids.
+const BACKFILL_SCRATCH_ID: &str = "\0backfill";
+
+/// Size of the current chain: rolled back blocks are subtracted, while blocks trimmed from the
+/// volatile window still count, since they're still part of the chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainStats {
+    pub tip: Point,
+    pub total_blocks: u64,
+    /// Txs of every block, whether or not an indexer stored them. Counted from the origin,
+    /// since databases of older format versions, which didn't count them, are refused.
+    pub total_txs: u64,
+}
+
+/// Block hashes [`BlockHashIter`] reads per read txn
+const BLOCK_HASH_ITER_BATCH: usize = 1024;
+
+/// Iterator over the block hashes within a slot range, see [`Db::block_hashes`]
+pub struct BlockHashIter {
+    env: Env,
+    slots: Database<U64<BigEndian>, RkyvCodec<BlockHash>>,
+    end: Bound<u64>,
+    batch_size: usize,
+    batch: std::vec::IntoIter<(Slot, BlockHash)>,
+    /// Where the next batch starts, `None` once the range is exhausted
+    next: Option<Bound<u64>>,
+}
+
+impl BlockHashIter {
+    fn read_batch(&mut self) -> Result<()> {
+        let Some(start) = self.next.take() else {
+            return Ok(());
+        };
+        let rtxn = self.env.read_txn()?;
+        let mut batch = Vec::new();
+        for res in self
+            .slots
+            .range(&rtxn, &(start, self.end))?
+            .take(self.batch_size)
+        {
+            let (slot, block_hash) = res?;
+            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+            batch.push((Slot(slot), block_hash));
+        }
+        if batch.len() == self.batch_size {
+            self.next = batch.last().map(|(slot, _)| Bound::Excluded(slot.0));
+        }
+        self.batch = batch.into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for BlockHashIter {
+    type Item = Result<(Slot, BlockHash)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(block) = self.batch.next() {
+            return Some(Ok(block));
+        }
+        // Errors end the iteration, as `next` was taken
+        if let Err(err) = self.read_batch() {
+            return Some(Err(err));
+        }
+        self.batch.next().map(Ok)
+    }
+}
+
+/// A tx along with everything it refers to, for debugging it, see [`Db::tx_context`]
+#[derive(Debug, Clone)]
+pub struct TxContext {
+    pub tx: Tx,
+    /// Outputs spent by each of the tx's inputs, in the same order. `None` when the tx that
+    /// created the output isn't retained in the volatile window.
+    pub resolved_inputs: Vec<Option<TxOutput>>,
+    /// Datums the resolved inputs and the outputs refer to by hash, each listed once, with
+    /// `None` if the datum isn't retained
+    pub datums: Vec<(DatumHash, Option<Datum>)>,
+    pub scripts: Vec<Script>,
+}
+
+/// Entries [`Db::repair`] removed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Slot index entries of blocks missing from the volatile db
+    pub slots: Vec<(Slot, BlockHash)>,
+    /// Block number index entries of blocks missing from the volatile db
+    pub numbers: Vec<(BlockNumber, BlockHash)>,
+}
+
+impl RepairReport {
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty() && self.numbers.is_empty()
+    }
+}
+
+/// Overview of a database for operators, see [`Db::describe`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbDescription {
+    pub stats: ChainStats,
+    pub network: Option<Network>,
+    pub indexer_ids: Vec<String>,
+    pub volatile_blocks: u64,
+    pub volatile_txs: u64,
+    /// Bytes in use by the database, excluding the unused part of the map
+    pub size: u64,
+}
+
+impl std::fmt::Display for DbDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.stats.tip {
+            Point::Origin => writeln!(f, "tip: origin")?,
+            Point::Specific(slot, hash) => writeln!(f, "tip: {slot} {}", hex::encode(hash))?,
+        }
+        match self.network {
+            Some(network) => writeln!(f, "network: {network:?}")?,
+            None => writeln!(f, "network: not recorded")?,
+        }
+        writeln!(f, "indexers: {}", self.indexer_ids.join(", "))?;
+        writeln!(
+            f,
+            "blocks: {} ({} volatile)",
+            self.stats.total_blocks, self.volatile_blocks
+        )?;
+        writeln!(
+            f,
+            "txs: {} ({} retained)",
+            self.stats.total_txs, self.volatile_txs
+        )?;
+        write!(f, "size: {:.2} MiB", self.size as f64 / (1024. * 1024.))
+    }
 }
 
 impl Db {
     pub fn new(path: &str, max_rollback_blocks: usize) -> Result<Self> {
+        Self::with_options(path, DbOptions::new(max_rollback_blocks))
+    }
+
+    pub fn with_options(path: &str, options: DbOptions) -> Result<Self> {
+        anyhow::ensure!(
+            options.max_rollback_blocks > 0,
+            "max_rollback_blocks must be at least 1 to roll back at all"
+        );
+        anyhow::ensure!(
+            options.resize_policy.is_valid(),
+            "resize_policy must grow the map by at least one byte per step, got {:?}",
+            options.resize_policy
+        );
         info!(?path, "Creating/opening database...");
         std::fs::create_dir_all(path)?;
         let env = unsafe {
             EnvOpenOptions::new()
                 .max_dbs(64)
-                .flags(
-                    heed::EnvFlags::NO_SYNC // manually fsync data
-                    | heed::EnvFlags::NO_META_SYNC // manually fsync metadata
-                    | heed::EnvFlags::WRITE_MAP, // assume no memory unsafety in this program
-                )
-                .map_size(1024 * 1024 * 1024 * 2) // 2GB
+                .flags(options.env_flags)
+                .map_size(options.map_size)
                 .open(path)?
         };
 
         let mut wtxn = env.write_txn()?;
         let slots = env.create_database(&mut wtxn, Some("slots"))?;
+        let numbers = env.create_database(&mut wtxn, Some("numbers"))?;
         let volatile_tx = env.create_database(&mut wtxn, Some("volatile_tx"))?;
         let volatile_block = env.create_database(&mut wtxn, Some("volatile_block"))?;
+        let volatile_datum = env.create_database(&mut wtxn, Some("volatile_datum"))?;
+        let block_scratch = env.create_database(&mut wtxn, Some("block_scratch"))?;
         let indexer_ids = env.create_database(&mut wtxn, Some("indexer_ids"))?;
+        let meta: Database<Str, Bytes> = env.create_database(&mut wtxn, Some("meta"))?;
+        match meta.get(&wtxn, META_PAGE_SIZE)? {
+            Some(recorded) => check_page_size(recorded)?,
+            None => meta.put(
+                &mut wtxn,
+                META_PAGE_SIZE,
+                &(page_size::get() as u64).to_be_bytes(),
+            )?,
+        }
+        match format_version(&wtxn, &meta, &slots)? {
+            Some(database) => check_format_version(database)?,
+            None => meta.put(
+                &mut wtxn,
+                META_FORMAT_VERSION,
+                &FORMAT_VERSION.to_be_bytes(),
+            )?,
+        }
         wtxn.commit()?;
 
+        let db = Self {
+            max_rollback_blocks: options.max_rollback_blocks,
+            commit_interval: options.commit_interval,
+            write_buffer_bytes: options.write_buffer_bytes,
+            write_rate_limit: options.write_rate_limit,
+            reject_duplicate_blocks: options.reject_duplicate_blocks,
+            env: Env::from(env)
+                .with_resize_policy(options.resize_policy)
+                .with_min_free_space(options.min_free_space),
+            metrics: Default::default(),
+            timings: options.timings,
+            block_validator: options.block_validator,
+            slots,
+            numbers,
+            volatile_tx,
+            volatile_block,
+            volatile_datum,
+            block_scratch,
+            indexer_ids,
+            meta,
+        };
+        if options.repair_on_open {
+            db.repair()?;
+        }
+        Ok(db)
+    }
+
+    /// Opens an existing database without write access, e.g. to inspect it while another
+    /// process syncs it. No tables are created, so it errors if `path` isn't a database written
+    /// by this crate. Anything that writes, like syncing or registering indexers, fails.
+    pub fn open_read_only(path: &str) -> Result<Self> {
+        info!(?path, "Opening database read-only...");
+        // The map size is taken from the existing database
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .max_dbs(64)
+                .flags(heed::EnvFlags::READ_ONLY)
+                .open(path)?
+        };
+
+        let rtxn = env.read_txn()?;
+        let slots = open_table(&env, &rtxn, "slots")?;
+        let numbers = open_table(&env, &rtxn, "numbers")?;
+        let volatile_tx = open_table(&env, &rtxn, "volatile_tx")?;
+        let volatile_block = open_table(&env, &rtxn, "volatile_block")?;
+        let volatile_datum = open_table(&env, &rtxn, "volatile_datum")?;
+        let block_scratch = open_table(&env, &rtxn, "block_scratch")?;
+        let indexer_ids = open_table(&env, &rtxn, "indexer_ids")?;
+        let meta: Database<Str, Bytes> = open_table(&env, &rtxn, "meta")?;
+        if let Some(recorded) = meta.get(&rtxn, META_PAGE_SIZE)? {
+            check_page_size(recorded)?;
+        }
+        if let Some(database) = format_version(&rtxn, &meta, &slots)? {
+            check_format_version(database)?;
+        }
+        rtxn.commit()?;
+
+        let options = DbOptions::new(0);
         Ok(Self {
-            max_rollback_blocks,
-            env: env.into(),
+            max_rollback_blocks: options.max_rollback_blocks,
+            commit_interval: options.commit_interval,
+            write_buffer_bytes: options.write_buffer_bytes,
+            write_rate_limit: options.write_rate_limit,
+            reject_duplicate_blocks: options.reject_duplicate_blocks,
+            env: Env::from(env),
+            metrics: Default::default(),
+            timings: options.timings,
+            block_validator: options.block_validator,
             slots,
+            numbers,
             volatile_tx,
             volatile_block,
+            volatile_datum,
+            block_scratch,
             indexer_ids,
+            meta,
         })
     }
 
+    /// Ids of the indexers the database at `path` was synced with, sorted, or none if it hasn't
+    /// been synced yet. Syncing it again requires indexers with exactly these ids, in any order,
+    /// so tooling can check its configuration before starting a run. Opens it read-only, like
+    /// [`Db::open_read_only`], so the database must not be open in this process already.
+    pub fn registered_indexer_ids(path: &str) -> Result<Vec<String>> {
+        let db = Self::open_read_only(path)?;
+        let rtxn = db.env.read_txn()?;
+        if db.meta.get(&rtxn, META_INDEXERS_REGISTERED)?.is_none() {
+            return Ok(vec![]);
+        }
+        db.indexer_ids
+            .iter(&rtxn)?
+            .map(|res| Ok(res?.0.to_string()))
+            .collect()
+    }
+
+    /// Opens a database at `path` seeded from a file written by [`Db::snapshot`]. Refuses to
+    /// overwrite an existing database. Syncing resumes from the snapshot's tip, as long as the
+    /// same indexers (by id) are recreated on top of it.
+    pub fn restore_from_snapshot(
+        snapshot: impl AsRef<std::path::Path>,
+        path: &str,
+        options: DbOptions,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(path)?;
+        let data = std::path::Path::new(path).join("data.mdb");
+        anyhow::ensure!(
+            !data.try_exists()?,
+            "database already exists at {}",
+            data.display()
+        );
+
+        // Nothing is left behind on failure, so restoring can be retried
+        let tmp = data.with_extension("mdb.tmp");
+        let res = std::fs::copy(snapshot, &tmp)
+            .context("failed to copy snapshot")
+            .and_then(|_| {
+                std::fs::File::open(&tmp)?.sync_all()?;
+                Ok(std::fs::rename(&tmp, &data)?)
+            });
+        if res.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        res?;
+        let res = Self::with_options(path, options);
+        if res.is_err() {
+            let _ = std::fs::remove_file(&data);
+        }
+        res
+    }
+
     pub fn get_volatile_block(
         &self,
         rtxn: &heed::RoTxn,
@@ -77,6 +409,55 @@ impl Db {
             .transpose()
     }
 
+    /// Volatile blocks after the slot, newest first: the blocks to undo to get back to the state
+    /// as of `slot`. Errors with [`Error::RollbackTooDeep`] if some of them were already trimmed.
+    pub fn volatile_blocks_after(
+        &self,
+        rtxn: &heed::RoTxn,
+        slot: Slot,
+    ) -> Result<Vec<VolatileBlock>> {
+        let mut blocks = vec![];
+        let range = (Bound::Excluded(slot.0), Bound::Unbounded);
+        for res in self.slots.rev_range(rtxn, &range)? {
+            let (block_slot, block_hash) = res?;
+            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+            let Some(block) = self.get_volatile_block(rtxn, &block_hash)? else {
+                return Err(Error::RollbackTooDeep {
+                    requested_slot: slot.0,
+                    oldest_slot: block_slot,
+                }
+                .into());
+            };
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Volatile block along with its full txs, in block order, or `None` once the block left the
+    /// volatile window.
+    ///
+    /// Full tx data only exists for txs that some indexer retained (by returning `true` from
+    /// `insert_tx`), so the txs are those listed in [`VolatileBlock::txs`]: compare their count
+    /// against [`VolatileBlock::tx_count`] to tell whether the block is complete.
+    pub fn block_with_txs(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<(VolatileBlock, Vec<Tx>)>> {
+        let rtxn = self.env.read_txn()?;
+        let Some(block) = self.get_volatile_block(&rtxn, block_hash)? else {
+            return Ok(None);
+        };
+        let txs = block
+            .txs
+            .iter()
+            .map(|tx_hash| {
+                self.get_volatile_tx(&rtxn, tx_hash)?
+                    .with_context(|| format!("missing volatile tx {tx_hash} of block {block_hash}"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some((block, txs)))
+    }
+
     pub fn get_volatile_tx(&self, rtxn: &heed::RoTxn, tx_hash: &TxHash) -> Result<Option<Tx>> {
         self.volatile_tx
             .get(rtxn, tx_hash)?
@@ -102,6 +483,127 @@ impl Db {
             .transpose()
     }
 
+    /// The tx with its inputs resolved and the datums and scripts it refers to, all read in a
+    /// single txn from the volatile window. `None` if the tx isn't retained.
+    pub fn tx_context(&self, tx_hash: &TxHash) -> Result<Option<TxContext>> {
+        let rtxn = self.env.read_txn()?;
+        let Some(tx) = self.get_volatile_tx(&rtxn, tx_hash)? else {
+            return Ok(None);
+        };
+        let resolved_inputs = tx
+            .inputs
+            .iter()
+            .map(|input| self.get_volatile_tx_output(&rtxn, input))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut datums: Vec<(DatumHash, Option<Datum>)> = vec![];
+        let datum_hashes = resolved_inputs
+            .iter()
+            .flatten()
+            .chain(tx.outputs.iter())
+            .filter_map(|output| output.datum_hash.as_ref());
+        for hash in datum_hashes {
+            if datums.iter().any(|(seen, _)| seen == hash) {
+                continue;
+            }
+            let datum = self
+                .volatile_datum
+                .get(&rtxn, hash)?
+                .map(|datum| {
+                    let datum = rkyv::deserialize::<VolatileDatum, rkyv::rancor::Error>(datum)?;
+                    anyhow::Ok(datum.datum)
+                })
+                .transpose()?;
+            datums.push((hash.clone(), datum));
+        }
+
+        let scripts = tx.scripts.clone();
+        Ok(Some(TxContext {
+            tx,
+            resolved_inputs,
+            datums,
+            scripts,
+        }))
+    }
+
+    /// Iterates the txs retained in the volatile window. Only txs that some indexer chose to
+    /// store (by returning `true` from `insert_tx`) are present, not every tx of the retained
+    /// blocks.
+    ///
+    /// The iterator borrows `rtxn`, which holds the resize read-lock, so resizes (and therefore
+    /// sync) wait until it's dropped. See [`Env::read_txn`].
+    pub fn volatile_txs_iter<'t>(
+        &self,
+        rtxn: &'t heed::RoTxn,
+    ) -> Result<impl Iterator<Item = Result<Tx>> + 't> {
+        Ok(self.volatile_tx.iter(rtxn)?.map(|res| {
+            let (_, tx) = res?;
+            Ok(rkyv::deserialize::<Tx, rkyv::rancor::Error>(tx)?)
+        }))
+    }
+
+    /// Number of txs retained in the volatile window, see [`Db::volatile_txs_iter`]
+    pub fn volatile_tx_count(&self, rtxn: &heed::RoTxn) -> Result<u64> {
+        Ok(self.volatile_tx.len(rtxn)?)
+    }
+
+    /// Stores per-block bookkeeping for an indexer, e.g. a running counter or checkpoint, which
+    /// rolls back with the block: it's deleted after the indexers' `delete_block` (which can
+    /// still read it) and when the block is trimmed from the volatile window.
+    ///
+    /// Meant for small values, since one is kept for each of the last `max_rollback_blocks`
+    /// blocks. Don't rely on it for anything older than the volatile window.
+    pub fn put_block_scratch(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        indexer_id: &str,
+        block_hash: &BlockHash,
+        data: &[u8],
+    ) -> Result<()> {
+        let key = [block_hash.as_slice(), indexer_id.as_bytes()].concat();
+        Ok(self.block_scratch.put(wtxn, &key, data)?)
+    }
+
+    /// Data stored with [`Db::put_block_scratch`], if the block is still in the volatile window
+    pub fn get_block_scratch(
+        &self,
+        rtxn: &heed::RoTxn,
+        indexer_id: &str,
+        block_hash: &BlockHash,
+    ) -> Result<Option<Vec<u8>>> {
+        let key = [block_hash.as_slice(), indexer_id.as_bytes()].concat();
+        Ok(self.block_scratch.get(rtxn, &key)?.map(<[u8]>::to_vec))
+    }
+
+    /// Datum witnessed by a block within the volatile window, regardless of whether any indexer
+    /// stored it
+    pub fn datum(&self, hash: &DatumHash) -> Result<Option<Datum>> {
+        let rtxn = self.env.read_txn()?;
+        self.volatile_datum
+            .get(&rtxn, hash)?
+            .map(|res| {
+                let datum = rkyv::deserialize::<VolatileDatum, rkyv::rancor::Error>(res)?;
+                Ok(datum.datum)
+            })
+            .transpose()
+    }
+
+    /// Iterates the hashes of the datums within the volatile window, see [`Db::datum`]. Only
+    /// the keys are read. Holds the resize read-lock through `rtxn`, see [`Env::read_txn`].
+    pub fn datum_hashes_iter<'t>(
+        &self,
+        rtxn: &'t heed::RoTxn,
+    ) -> Result<impl Iterator<Item = Result<DatumHash>> + 't> {
+        Ok(self
+            .volatile_datum
+            .remap_data_type::<DecodeIgnore>()
+            .iter(rtxn)?
+            .map(|res| {
+                let (hash, _) = res?;
+                Ok(rkyv::deserialize::<DatumHash, rkyv::rancor::Error>(hash)?)
+            }))
+    }
+
     pub fn tip(&self) -> Result<Point> {
         let rtxn = self.env.read_txn()?;
         if let Some((slot, block_hash)) = self.slots.rev_range(&rtxn, &(0..))?.next().transpose()? {
@@ -112,147 +614,740 @@ impl Db {
         }
     }
 
-    pub fn persist(&self) -> Result<()> {
-        Ok(self.env.persist()?)
+    /// `(slot, block hash)` of every block within the slot range, ordered by slot. Only reads
+    /// the slot index, which covers the whole chain rather than just the volatile window, so
+    /// comparing the output of two instances finds the slot they diverged at.
+    ///
+    /// Read lazily in batches of 1024 with a read txn each, so even the whole chain can be
+    /// walked with flat memory use and no txn kept open while the caller consumes it. Blocks
+    /// committed or rolled back while iterating show up in the batches read after them.
+    pub fn block_hashes(&self, range: impl RangeBounds<Slot>) -> BlockHashIter {
+        self.block_hashes_batched(range, BLOCK_HASH_ITER_BATCH)
     }
 
-    pub fn snapshot(&self, path: impl AsRef<std::path::Path>, overwrite: bool) -> Result<()> {
-        Ok(self.env.snapshot(path, overwrite)?)
+    fn block_hashes_batched(
+        &self,
+        range: impl RangeBounds<Slot>,
+        batch_size: usize,
+    ) -> BlockHashIter {
+        let (start, end) = u64_bounds(&range);
+        BlockHashIter {
+            env: self.env.clone(),
+            slots: self.slots,
+            end,
+            batch_size,
+            batch: Vec::new().into_iter(),
+            next: Some(start),
+        }
     }
 
-    // -------------
-    // Internal API
-
-    pub(crate) fn roll_forward(&self, indexers: &IndexerList, block: &MultiEraBlock) -> Result<()> {
-        let indexers = indexers
-            .iter()
-            .map(|i| i.lock().expect("indexer mutex poisoned"))
-            .collect::<Vec<_>>();
-        {
-            let rtxn = self.env.read_txn()?;
-            // Ensure the indexers didn't change
-            let indexer_ids = indexers.iter().map(|i| i.id()).collect::<Vec<_>>();
-            self.assert_indexer_ids(&rtxn, &indexer_ids)?;
-        }
-        let mut wtxn = self.env.write_txn()?;
+    /// `(slot, block hash)` of the `n` most recent blocks, newest first. See [`Db::block_hashes`].
+    pub fn recent_block_hashes(&self, n: usize) -> Result<Vec<(Slot, BlockHash)>> {
+        let rtxn = self.env.read_txn()?;
+        self.slots
+            .rev_iter(&rtxn)?
+            .take(n)
+            .map(|res| {
+                let (slot, block_hash) = res?;
+                let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+                Ok((Slot(slot), block_hash))
+            })
+            .collect()
+    }
 
-        // Pass datums + txs to each indexer, storing the hashes of those that got inserted
-        let mut tx_hashes = vec![];
-        let mut datum_hashes = vec![];
-        for raw_tx in block.txs().iter() {
-            let (tx, datums) = Tx::parse(raw_tx);
+    /// Volatile block at the slot, or `None` if there's no block at the slot or it already left
+    /// the volatile window
+    pub fn block_by_slot(&self, slot: Slot) -> Result<Option<VolatileBlock>> {
+        let rtxn = self.env.read_txn()?;
+        let Some(block_hash) = self.slots.get(&rtxn, &slot.0)? else {
+            return Ok(None);
+        };
+        let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+        self.get_volatile_block(&rtxn, &block_hash)
+    }
 
-            let did_insert_tx = indexers.iter().try_fold(false, |acc, i| {
-                i.insert_tx(self, &mut wtxn, &tx).map(|b| acc || b)
-            })?;
-            if did_insert_tx {
-                tx_hashes.push(tx.hash.clone());
-                self.volatile_tx.put(&mut wtxn, &tx.hash, &tx)?;
-            }
+    /// Volatile block with the number, or `None` if there's no such block or it already left the
+    /// volatile window
+    pub fn block_by_number(&self, number: BlockNumber) -> Result<Option<VolatileBlock>> {
+        let rtxn = self.env.read_txn()?;
+        let Some(block_hash) = self.numbers.get(&rtxn, &number.0)? else {
+            return Ok(None);
+        };
+        let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+        self.get_volatile_block(&rtxn, &block_hash)
+    }
 
-            for (datum_hash, datum) in datums.iter() {
-                let did_insert_datum = indexers.iter().try_fold(false, |acc, i| {
-                    i.insert_datum(self, &mut wtxn, datum_hash, datum)
-                        .map(|b| acc || b)
-                })?;
-                if did_insert_datum {
-                    datum_hashes.push(datum_hash.clone());
-                }
+    /// Volatile blocks within the number range, ordered by number. Blocks that already left the
+    /// volatile window are omitted.
+    pub fn blocks_by_number_range(
+        &self,
+        range: impl RangeBounds<BlockNumber>,
+    ) -> Result<Vec<VolatileBlock>> {
+        let rtxn = self.env.read_txn()?;
+        let mut blocks = vec![];
+        for res in self.numbers.range(&rtxn, &u64_bounds(&range))? {
+            let (_, block_hash) = res?;
+            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+            if let Some(block) = self.get_volatile_block(&rtxn, &block_hash)? {
+                blocks.push(block);
             }
         }
+        Ok(blocks)
+    }
 
-        // Block Hash -> Block
-        let block = VolatileBlock::parse(block, tx_hashes, datum_hashes);
-        self.volatile_block.put(&mut wtxn, &block.hash, &block)?;
+    pub fn chain_stats(&self) -> Result<ChainStats> {
+        let tip = self.tip()?;
+        let rtxn = self.env.read_txn()?;
+        Ok(ChainStats {
+            tip,
+            // Every block since the origin is kept in the slot index
+            total_blocks: self.slots.len(&rtxn)?,
+            total_txs: self.total_txs(&rtxn)?,
+        })
+    }
 
-        // Slot -> Block Hash
-        self.slots.put(&mut wtxn, &block.slot, &block.hash)?;
+    /// Absent until the first block is applied, as the format version rules out databases synced
+    /// before the total was kept
+    fn total_txs(&self, rtxn: &heed::RoTxn) -> Result<u64> {
+        let Some(total) = self.meta.get(rtxn, META_TOTAL_TXS)? else {
+            return Ok(0);
+        };
+        Ok(u64::from_be_bytes(
+            total.try_into().context("invalid total txs")?,
+        ))
+    }
 
-        wtxn.commit()?;
-        Ok(self.env.resize()?)
+    fn put_total_txs(&self, wtxn: &mut heed::RwTxn, total: u64) -> Result<()> {
+        Ok(self.meta.put(wtxn, META_TOTAL_TXS, &total.to_be_bytes())?)
     }
 
-    pub(crate) fn roll_backward(&self, indexers: &IndexerList, point: &Point) -> Result<()> {
-        // TODO: error when rolling back too far
-        let slot = match point {
-            Point::Origin => return self.clear(indexers),
-            Point::Specific(slot, _) => *slot + 1,
+    /// Removes entries at the tail of the slot and block number indexes whose block is missing
+    /// from the volatile db, logging each, so the first rollback after opening doesn't fail to
+    /// find them. Blocks are written to all three in the same txn, so this only finds anything
+    /// in a corrupted database. Only the last `max_rollback_blocks` entries are checked, older
+    /// ones are expected to be trimmed. Run it before syncing, or on every open with
+    /// [`DbOptions::repair_on_open`].
+    pub fn repair(&self) -> Result<RepairReport> {
+        let mut wtxn = self.env.write_txn()?;
+        let report = RepairReport {
+            slots: self
+                .remove_dangling_tail(&mut wtxn, &self.slots)?
+                .into_iter()
+                .map(|(slot, block_hash)| (Slot(slot), block_hash))
+                .collect(),
+            numbers: self
+                .remove_dangling_tail(&mut wtxn, &self.numbers)?
+                .into_iter()
+                .map(|(number, block_hash)| (BlockNumber(number), block_hash))
+                .collect(),
         };
+        wtxn.commit()?;
+        if !report.is_empty() {
+            info!(
+                slots = report.slots.len(),
+                numbers = report.numbers.len(),
+                "Repaired database"
+            );
+        }
+        Ok(report)
+    }
 
-        let indexers = indexers
-            .iter()
-            .map(|i| i.lock().expect("indexer mutex poisoned"))
-            .collect::<Vec<_>>();
+    fn remove_dangling_tail(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        table: &Database<U64<BigEndian>, RkyvCodec<BlockHash>>,
+    ) -> Result<Vec<(u64, BlockHash)>> {
+        let tail = table
+            .rev_iter(wtxn)?
+            .take(self.max_rollback_blocks)
+            .map(|res| {
+                let (key, block_hash) = res?;
+                let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+                Ok((key, block_hash))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut removed = vec![];
+        for (key, block_hash) in tail {
+            if self.volatile_block.get(wtxn, &block_hash)?.is_some() {
+                break;
+            }
+            tracing::warn!(key, %block_hash, "Removing index entry of a missing block");
+            table.delete(wtxn, &key)?;
+            removed.push((key, block_hash));
+        }
+        Ok(removed)
+    }
+
+    /// Tip, network, registered indexers, counts and size of the database, for status output
+    pub fn describe(&self) -> Result<DbDescription> {
+        let stats = self.chain_stats()?;
+        let network = self.network()?;
         let rtxn = self.env.read_txn()?;
+        let indexer_ids = self
+            .indexer_ids
+            .iter(&rtxn)?
+            .map(|res| Ok(res?.0.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(DbDescription {
+            stats,
+            network,
+            indexer_ids,
+            volatile_blocks: self.volatile_block.len(&rtxn)?,
+            volatile_txs: self.volatile_tx.len(&rtxn)?,
+            size: self.env.used_size() as u64,
+        })
+    }
 
-        // Ensure the indexers didn't change
-        let indexer_ids = indexers.iter().map(|i| i.id()).collect::<Vec<_>>();
-        self.assert_indexer_ids(&rtxn, &indexer_ids)?;
+    /// Stops feeding the indexer blocks while keeping what it stored so far, e.g. to hold off
+    /// an expensive indexer during a staged rollout. The blocks it misses are kept in full
+    /// while they're in the volatile window, for [`Db::backfill_indexer`] to catch it up from.
+    /// It has to be backfilled before `max_rollback_blocks` blocks pass, or the database
+    /// resynced. While syncing, pause the writer with a [`WriterHandle`](crate::WriterHandle)
+    /// around disabling and backfilling.
+    pub fn disable_indexer(&self, id: &str) -> Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        anyhow::ensure!(
+            self.indexer_ids.get(&wtxn, id)?.is_some(),
+            "indexer {id} isn't registered"
+        );
+        if self.disabled_indexers(&wtxn)?.contains_key(id) {
+            return Ok(());
+        }
+        let from_slot = match self.slots.last(&wtxn)? {
+            Some((slot, _)) => slot + 1,
+            None => 0,
+        };
+        self.meta
+            .put(&mut wtxn, &disabled_key(id), &from_slot.to_be_bytes())?;
+        Ok(wtxn.commit()?)
+    }
 
-        for res in self.slots.rev_range(&rtxn, &(slot..))? {
-            let (slot, block_hash) = res?;
-            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+    /// First slot the indexer missed since being disabled, or `None` if it's enabled
+    pub fn indexer_disabled_from(&self, id: &str) -> Result<Option<Slot>> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self.disabled_indexers(&rtxn)?.remove(id).map(Slot))
+    }
+
+    /// Passes the blocks a disabled indexer missed to it and enables it again, returning the
+    /// number of blocks backfilled. Errors with [`Error::BackfillTooFar`] if some of them already
+    /// left the volatile window.
+    pub fn backfill_indexer(&self, indexer: &Mutex<dyn Indexer + Send>) -> Result<usize> {
+        let indexer = indexer.lock().expect("indexer mutex poisoned");
+        let id = indexer.id().to_string();
+        let mut wtxn = self.env.write_txn()?;
+        let Some(from_slot) = self.disabled_indexers(&wtxn)?.remove(&id) else {
+            anyhow::bail!("indexer {id} isn't disabled");
+        };
+        let block_hashes = self
+            .slots
+            .range(&wtxn, &(from_slot..))?
+            .map(|res| {
+                let (_, block_hash) = res?;
+                Ok(rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(
+                    block_hash,
+                )?)
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-            let block = self
-                .volatile_block
-                .get(&rtxn, &block_hash)?
-                .with_context(|| {
-                    format!("block not found while rolling back, the db could be corrupt or rolled back further than max_rollback_blocks: {}", block_hash)
-                })?;
+        let indexers = [indexer];
+        for block_hash in block_hashes.iter() {
+            let Some(bytes) = self.get_block_scratch(&wtxn, BACKFILL_SCRATCH_ID, block_hash)?
+            else {
+                return Err(Error::BackfillTooFar { id, from_slot }.into());
+            };
+            // Kept for backfilling, see `Db::disable_indexer`
+            let block = from_unaligned_bytes::<Block>(&bytes)?;
+            let (tx_hashes, datum_hashes) = self.index_block(&indexers, &mut wtxn, &block)?;
 
-            // NOTE: reverse order because a tx may spend outputs from a previous tx
-            // in the same block
-            let mut wtxn = self.env.write_txn()?;
-            for tx_hash in block.txs.iter().rev() {
-                let tx_hash = rkyv::deserialize::<TxHash, rkyv::rancor::Error>(tx_hash)?;
-                let tx = self.volatile_tx.get(&rtxn, &tx_hash)?.with_context(|| {
-                    format!(
-                        "tx not found while rolling back, the db could be corrupt: {}",
-                        tx_hash
-                    )
-                })?;
-                let tx = rkyv::deserialize::<Tx, rkyv::rancor::Error>(tx)?;
-                for indexer in indexers.iter() {
-                    indexer.delete_tx(self, &mut wtxn, &tx)?;
+            // Record what the indexer inserted, so rolling back the block undoes it
+            let mut volatile = self
+                .get_volatile_block(&wtxn, block_hash)?
+                .with_context(|| format!("missing volatile block {block_hash}"))?;
+            volatile.txs = block
+                .txs
+                .iter()
+                .map(|tx| &tx.hash)
+                .filter(|hash| volatile.txs.contains(hash) || tx_hashes.contains(hash))
+                .cloned()
+                .collect();
+            for datum_hash in datum_hashes {
+                if !volatile.datums.contains(&datum_hash) {
+                    volatile.datums.push(datum_hash);
                 }
             }
-            for datum_hash in block.datums.iter().rev() {
-                let datum_hash = rkyv::deserialize::<DatumHash, rkyv::rancor::Error>(datum_hash)?;
-                for indexer in indexers.iter() {
-                    indexer.delete_datum(self, &mut wtxn, &datum_hash)?;
+            // Blocks applied without an indexer wanting datums took no reference to them
+            if volatile.witnessed_datums.is_empty() && indexers[0].wants_datums() {
+                for (datum_hash, datum) in block.datums.iter() {
+                    self.retain_datum(&mut wtxn, datum_hash, datum)?;
+                    volatile.witnessed_datums.push(datum_hash.clone());
                 }
             }
-
-            self.slots.delete(&mut wtxn, &slot)?;
-            self.volatile_block.delete(&mut wtxn, &block_hash)?;
-            wtxn.commit()?;
+            self.volatile_block.put(&mut wtxn, block_hash, &volatile)?;
         }
 
-        Ok(self.env.resize()?)
+        self.meta.delete(&mut wtxn, &disabled_key(&id))?;
+        wtxn.commit()?;
+        indexers[0].on_commit()?;
+        self.env.resize()?;
+        Ok(block_hashes.len())
     }
 
-    pub(crate) fn trim_volatile(&self) -> Result<()> {
+    /// Network the database was synced against, if it has been recorded yet
+    pub fn network(&self) -> Result<Option<Network>> {
         let rtxn = self.env.read_txn()?;
-        let mut wtxn = self.env.write_txn()?;
+        let Some(magic) = self.meta.get(&rtxn, META_NETWORK_MAGIC)? else {
+            return Ok(None);
+        };
+        let magic = u64::from_be_bytes(magic.try_into().context("invalid network magic")?);
+        Network::from_magic(magic)
+            .with_context(|| format!("unknown network magic: {magic}"))
+            .map(Some)
+    }
 
-        for slot in self
-            .slots
-            .rev_range(&rtxn, &(0..))?
-            .skip(self.max_rollback_blocks)
-        {
-            let (_, block_hash) = slot?;
-            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+    /// Records the network on first use and errors with [`Error::NetworkMismatch`] if the
+    /// database was previously synced against a different network
+    pub fn assert_network(&self, network: Network) -> Result<()> {
+        match self.network()? {
+            Some(recorded) if recorded != network => {
+                return Err(Error::NetworkMismatch {
+                    database: recorded,
+                    node: network,
+                }
+                .into());
+            }
+            Some(_) => {}
+            None => {
+                let mut wtxn = self.env.write_txn()?;
+                self.meta.put(
+                    &mut wtxn,
+                    META_NETWORK_MAGIC,
+                    &network.magic().to_be_bytes(),
+                )?;
+                wtxn.commit()?;
+            }
+        }
+        Ok(())
+    }
 
-            // If we can't find the block, we've already trimmed it
-            let Some(block) = self.volatile_block.get(&rtxn, &block_hash)? else {
-                break;
-            };
-            let block = rkyv::deserialize::<VolatileBlock, rkyv::rancor::Error>(block)?;
+    /// Sync statistics since the database was opened. Phase timings are only recorded when
+    /// enabled with [`DbOptions::timings()`].
+    pub fn metrics(&self) -> &SyncMetrics {
+        &self.metrics
+    }
+
+    /// Fsyncs every commit so far. Commits aren't fsynced on their own with the default
+    /// [`DbOptions::env_flags()`], which trades crash safety for write speed:
+    ///
+    /// - If the process is killed, e.g. by a `SIGKILL` from a container orchestrator, the OS
+    ///   still writes out everything committed, and the database reopens at the last commit.
+    ///   Only the blocks of a batch the writer hadn't committed yet are fetched again.
+    /// - If the OS crashes or the machine loses power, LMDB gives no guarantees with these
+    ///   flags: the database may be corrupted, not just behind, and opening it may fail or
+    ///   succeed with broken tables. Restore it from a snapshot taken with [`Db::snapshot`], or
+    ///   resync it from the origin. Opening with [`DbOptions::env_flags()`] that fsync on commit
+    ///   avoids this at the cost of write speed.
+    ///
+    /// The writer persists at every checkpoint, and should be persisted once more after
+    /// stopping sync.
+    pub fn persist(&self) -> Result<()> {
+        Ok(self.env.persist()?)
+    }
+
+    /// Writes a compacted copy of the database to `path`. The copy is fsynced before returning,
+    /// so it's safe to delete a previous snapshot once this returns `Ok`. It's written to a
+    /// `.tmp` sibling first, so a failed or interrupted snapshot can simply be retried.
+    ///
+    /// The copy holds the state as of the last commit, so it never contains a partially applied
+    /// batch of blocks. Anything indexers keep outside the database may be ahead of it though,
+    /// and the writer is blocked from resizing while the copy runs. While syncing, snapshot
+    /// through [`WriterHandle::snapshot`](crate::WriterHandle::snapshot) instead, which waits
+    /// until the writer is quiescent between commits.
+    pub fn snapshot(&self, path: impl AsRef<std::path::Path>, overwrite: bool) -> Result<()> {
+        Ok(self.env.snapshot(path, overwrite)?)
+    }
+
+    // -------------
+    // Internal API
+
+    /// Applies the blocks to the volatile db and indexers in a single write transaction
+    pub(crate) fn roll_forward(&self, indexers: &IndexerList, blocks: &[Block]) -> Result<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let indexers = indexers
+            .iter()
+            .map(|i| i.lock().expect("indexer mutex poisoned"))
+            .collect::<Vec<_>>();
+        let disabled = {
+            let rtxn = self.env.read_txn()?;
+            // Ensure the indexers didn't change
+            let configs = indexer_configs(&indexers);
+            self.assert_indexer_ids(&rtxn, &configs)?;
+            self.assert_schema_versions(&rtxn, &indexers)?;
+            self.disabled_indexers(&rtxn)?
+        };
+        let (indexers, disabled): (Vec<_>, Vec<_>) = indexers
+            .into_iter()
+            .partition(|i| !disabled.contains_key(i.id()));
+        let keep_for_backfill = !disabled.is_empty();
+        let mut wtxn = self.env.write_txn()?;
+        let mut txs = 0;
+        for block in blocks.iter() {
+            if self.is_duplicate(&wtxn, block)? {
+                continue;
+            }
+            self.timed(SyncPhase::Indexers, || {
+                self.apply_block(&indexers, &mut wtxn, block, keep_for_backfill)
+            })?;
+            txs += block.txs.len() as u64;
+        }
+        let total_txs = self.total_txs(&wtxn)?;
+        self.put_total_txs(&mut wtxn, total_txs + txs)?;
+        self.timed(SyncPhase::Commit, || wtxn.commit())?;
+        for indexer in indexers.iter() {
+            indexer.on_commit()?;
+        }
+        Ok(self.timed(SyncPhase::Resize, || self.env.resize())?)
+    }
+
+    /// Runs `f`, recording its duration if timings are enabled
+    pub(crate) fn timed<T>(&self, phase: SyncPhase, f: impl FnOnce() -> T) -> T {
+        if !self.timings {
+            return f();
+        }
+        let start = Instant::now();
+        let res = f();
+        self.metrics.record(phase, start.elapsed());
+        res
+    }
+
+    /// Runs the [`BlockValidator`], if any, logging a failed check rather than rejecting the
+    /// block
+    pub(crate) fn validate_block(&self, block: &Block) {
+        let Some(validator) = &self.block_validator else {
+            return;
+        };
+        if let Err(e) = validator.validate(block) {
+            tracing::warn!(
+                block = block.number,
+                slot = block.slot,
+                error = ?e,
+                "Block failed validation"
+            );
+        }
+    }
+
+    /// Whether the block was already applied, which means the node served it twice, e.g. due to
+    /// a bug or a misbehaving peer. Only detected within the volatile window.
+    fn is_duplicate(&self, rtxn: &heed::RoTxn, block: &Block) -> Result<bool> {
+        let Some(existing) = self.volatile_block.get(rtxn, &block.hash)? else {
+            return Ok(false);
+        };
+        let existing_slot = existing.slot.to_native();
+        if self.reject_duplicate_blocks {
+            return Err(Error::DuplicateBlock {
+                hash: block.hash.to_string(),
+                slot: block.slot,
+                existing_slot,
+            }
+            .into());
+        }
+        tracing::warn!(
+            hash = %block.hash,
+            slot = block.slot,
+            existing_slot,
+            "Skipping block that was already applied"
+        );
+        Ok(true)
+    }
+
+    fn apply_block(
+        &self,
+        indexers: &[MutexGuard<'_, dyn Indexer + Send>],
+        wtxn: &mut heed::RwTxn,
+        block: &Block,
+        keep_for_backfill: bool,
+    ) -> Result<()> {
+        // Only the datums retained here are released again, so a disabled indexer that wants
+        // datums can't make a rollback or trim release a reference that was never taken
+        let mut witnessed_datums = vec![];
+        if indexers.iter().any(|i| i.wants_datums()) {
+            for (datum_hash, datum) in block.datums.iter() {
+                self.retain_datum(wtxn, datum_hash, datum)?;
+                witnessed_datums.push(datum_hash.clone());
+            }
+        }
+        let (tx_hashes, datum_hashes) = self.index_block(indexers, wtxn, block)?;
+        if keep_for_backfill {
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(block)?;
+            self.put_block_scratch(wtxn, BACKFILL_SCRATCH_ID, &block.hash, &bytes)?;
+        }
+
+        // Block Hash -> Block
+        let block = VolatileBlock::parse(block, tx_hashes, datum_hashes, witnessed_datums);
+        self.volatile_block.put(wtxn, &block.hash, &block)?;
+
+        // Slot -> Block Hash
+        self.slots.put(wtxn, &block.slot, &block.hash)?;
+        self.numbers.put(wtxn, &block.number, &block.hash)?;
+
+        Ok(())
+    }
+
+    /// Passes the block's txs and datums to the indexers, storing the txs some indexer
+    /// inserted. Returns the hashes of the txs and datums that got inserted.
+    fn index_block(
+        &self,
+        indexers: &[MutexGuard<'_, dyn Indexer + Send>],
+        wtxn: &mut heed::RwTxn,
+        block: &Block,
+    ) -> Result<(Vec<TxHash>, Vec<DatumHash>)> {
+        let mut tx_hashes = vec![];
+        for tx in block.txs.iter() {
+            let did_insert_tx = indexers.iter().try_fold(false, |acc, i| {
+                i.insert_tx(self, wtxn, tx).map(|b| acc || b)
+            })?;
+            if did_insert_tx {
+                tx_hashes.push(tx.hash.clone());
+                self.volatile_tx.put(wtxn, &tx.hash, tx)?;
+            }
+        }
+
+        let mut datum_hashes = vec![];
+        let wants_datums = indexers.iter().any(|i| i.wants_datums());
+        for (datum_hash, datum) in block.datums.iter().filter(|_| wants_datums) {
+            let did_insert_datum = indexers.iter().try_fold(false, |acc, i| {
+                i.insert_datum(self, wtxn, datum_hash, datum)
+                    .map(|b| acc || b)
+            })?;
+            if did_insert_datum {
+                datum_hashes.push(datum_hash.clone());
+            }
+        }
+
+        for indexer in indexers.iter() {
+            indexer.insert_block(self, wtxn, block)?;
+        }
+        Ok((tx_hashes, datum_hashes))
+    }
+
+    /// Undoes the blocks after `point`. Rolling back to the origin clears the database instead,
+    /// including the registered indexer ids (but not the network), which leaves it as if freshly
+    /// created: the ids are registered again by the next roll forward. The node's chain-sync
+    /// continues from the origin after such a rollback, and a restarted [`Sync`](crate::Sync)
+    /// intersects at the origin since there's no tip.
+    ///
+    /// Errors with [`Error::RollbackTooDeep`] before undoing anything if some of the blocks
+    /// after `point` already left the volatile window.
+    pub(crate) fn roll_backward(
+        &self,
+        indexers: &IndexerList,
+        point: &Point,
+    ) -> Result<RollbackSummary> {
+        let from_slot = self.tip()?.slot_or_default();
+        let slot = match point {
+            Point::Origin => {
+                let blocks = self.slots.len(&self.env.read_txn()?)? as usize;
+                self.clear(indexers)?;
+                let summary = RollbackSummary {
+                    from_slot,
+                    to_slot: 0,
+                    blocks,
+                    txs: 0,
+                    datums: 0,
+                    indexers: self.indexer_ids_of(indexers),
+                };
+                self.metrics.record_rollback(&summary);
+                return Ok(summary);
+            }
+            Point::Specific(slot, _) => *slot + 1,
+        };
+        let mut summary = RollbackSummary {
+            from_slot,
+            to_slot: slot - 1,
+            blocks: 0,
+            txs: 0,
+            datums: 0,
+            indexers: self.indexer_ids_of(indexers),
+        };
+
+        let indexers = indexers
+            .iter()
+            .map(|i| i.lock().expect("indexer mutex poisoned"))
+            .collect::<Vec<_>>();
+        let rtxn = self.env.read_txn()?;
+
+        // Ensure the indexers didn't change
+        let configs = indexer_configs(&indexers);
+        self.assert_indexer_ids(&rtxn, &configs)?;
+        self.assert_schema_versions(&rtxn, &indexers)?;
+        self.assert_volatile_after(&rtxn, slot)?;
+        let disabled = self.disabled_indexers(&rtxn)?;
+        // Blocks reverted for each indexer, see `Indexer::on_rollback`
+        let mut reverted = vec![vec![]; indexers.len()];
+
+        // Each block is committed on its own, so if one fails the ones before it stay reverted
+        let res = (|| -> Result<()> {
+            let mut blocks = self.slots.rev_range(&rtxn, &(slot..))?.peekable();
+            while let Some(res) = blocks.next() {
+                let (block_slot, block_hash) = res?;
+                // Disabled indexers only undo the blocks they saw before being disabled
+                let active = indexers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, i)| {
+                        disabled
+                            .get(i.id())
+                            .is_none_or(|from_slot| block_slot < *from_slot)
+                    })
+                    .collect::<Vec<_>>();
+                let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+
+                let block = self
+                    .volatile_block
+                    .get(&rtxn, &block_hash)?
+                    .with_context(|| {
+                        format!("block not found while rolling back, the db could be corrupt or rolled back further than max_rollback_blocks: {}", block_hash)
+                    })?;
+
+                // NOTE: reverse order because a tx may spend outputs from a previous tx
+                // in the same block
+                let mut wtxn = self.env.write_txn()?;
+                for tx_hash in block.txs.iter().rev() {
+                    let tx_hash = rkyv::deserialize::<TxHash, rkyv::rancor::Error>(tx_hash)?;
+                    let tx = self.volatile_tx.get(&rtxn, &tx_hash)?.with_context(|| {
+                        format!(
+                            "tx not found while rolling back, the db could be corrupt: {}",
+                            tx_hash
+                        )
+                    })?;
+                    let tx = rkyv::deserialize::<Tx, rkyv::rancor::Error>(tx)?;
+                    for (_, indexer) in active.iter() {
+                        indexer.delete_tx(self, &mut wtxn, &tx)?;
+                    }
+                    summary.txs += 1;
+                }
+                for datum_hash in block.datums.iter().rev() {
+                    let datum_hash =
+                        rkyv::deserialize::<DatumHash, rkyv::rancor::Error>(datum_hash)?;
+                    for (_, indexer) in active.iter() {
+                        indexer.delete_datum(self, &mut wtxn, &datum_hash)?;
+                    }
+                    summary.datums += 1;
+                }
+
+                let block = rkyv::deserialize::<VolatileBlock, rkyv::rancor::Error>(block)?;
+                for (_, indexer) in active.iter() {
+                    indexer.delete_block(self, &mut wtxn, &block)?;
+                }
+                let total_txs = self.total_txs(&wtxn)?;
+                self.put_total_txs(&mut wtxn, total_txs.saturating_sub(block.tx_count))?;
+                self.release_datums(&mut wtxn, &block.witnessed_datums)?;
+                self.delete_block_scratch(&mut wtxn, &block.hash)?;
+
+                // Disabled indexers now miss the blocks they saw from this one on, and once the last
+                // block is undone, every block from the rollback target on, where the blocks of
+                // the new fork land
+                let missed_from = match blocks.peek() {
+                    Some(_) => block_slot,
+                    None => slot,
+                };
+                for (id, from_slot) in disabled.iter() {
+                    if missed_from < *from_slot {
+                        self.meta
+                            .put(&mut wtxn, &disabled_key(id), &missed_from.to_be_bytes())?;
+                    }
+                }
+                self.slots.delete(&mut wtxn, &block_slot)?;
+                self.numbers.delete(&mut wtxn, &block.number)?;
+                self.volatile_block.delete(&mut wtxn, &block_hash)?;
+                wtxn.commit()?;
+                for (_, indexer) in active.iter() {
+                    indexer.on_commit()?;
+                }
+                for (i, _) in active.iter() {
+                    reverted[*i].push((Slot(block_slot), block_hash.clone()));
+                }
+                summary.blocks += 1;
+            }
+            Ok(())
+        })();
+
+        // Reported even if a later block failed, so indexers never miss a committed revert
+        for (indexer, reverted) in indexers.iter().zip(reverted) {
+            if reverted.is_empty() {
+                continue;
+            }
+            if let Err(err) = indexer.on_rollback(&reverted) {
+                if res.is_ok() {
+                    return Err(err);
+                }
+                tracing::warn!(
+                    id = indexer.id(),
+                    ?err,
+                    "on_rollback failed after a failed rollback"
+                );
+            }
+        }
+        res?;
+        self.env.resize()?;
+        if summary.blocks == 0 {
+            summary.indexers.clear();
+        }
+        self.metrics.record_rollback(&summary);
+        Ok(summary)
+    }
+
+    /// Errors with [`Error::RollbackTooDeep`] unless every block from `slot` on is still in the
+    /// volatile window. Walks back from the tip, so it only reads as many blocks as would be
+    /// rolled back.
+    fn assert_volatile_after(&self, rtxn: &heed::RoTxn, slot: u64) -> Result<()> {
+        let volatile_block = self.volatile_block.remap_data_type::<DecodeIgnore>();
+        for res in self.slots.rev_range(rtxn, &(slot..))? {
+            let (block_slot, block_hash) = res?;
+            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+            if volatile_block.get(rtxn, &block_hash)?.is_none() {
+                return Err(Error::RollbackTooDeep {
+                    requested_slot: slot - 1,
+                    oldest_slot: block_slot,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn indexer_ids_of(&self, indexers: &IndexerList) -> Vec<String> {
+        indexers
+            .iter()
+            .map(|i| i.lock().expect("indexer mutex poisoned").id().to_string())
+            .collect()
+    }
+
+    pub(crate) fn trim_volatile(&self) -> Result<()> {
+        let rtxn = self.env.read_txn()?;
+        let mut wtxn = self.env.write_txn()?;
+
+        for slot in self
+            .slots
+            .rev_range(&rtxn, &(0..))?
+            .skip(self.max_rollback_blocks)
+        {
+            let (_, block_hash) = slot?;
+            let block_hash = rkyv::deserialize::<BlockHash, rkyv::rancor::Error>(block_hash)?;
+
+            // If we can't find the block, we've already trimmed it
+            let Some(block) = self.volatile_block.get(&rtxn, &block_hash)? else {
+                break;
+            };
+            let block = rkyv::deserialize::<VolatileBlock, rkyv::rancor::Error>(block)?;
 
             // Drop all the txs in the block
             for tx_hash in block.txs.iter().rev() {
                 self.volatile_tx.delete(&mut wtxn, tx_hash)?;
             }
+            self.release_datums(&mut wtxn, &block.witnessed_datums)?;
+            self.delete_block_scratch(&mut wtxn, &block_hash)?;
 
             // Drop the block
             self.volatile_block.delete(&mut wtxn, &block_hash)?;
@@ -261,6 +1356,50 @@ impl Db {
         Ok(wtxn.commit()?)
     }
 
+    /// Adds a reference to the datum from a block entering the volatile window
+    fn retain_datum(&self, wtxn: &mut heed::RwTxn, hash: &DatumHash, datum: &Datum) -> Result<()> {
+        let refs = match self.volatile_datum.get(wtxn, hash)? {
+            Some(existing) => existing.refs.to_native() + 1,
+            None => 1,
+        };
+        let datum = VolatileDatum {
+            refs,
+            datum: datum.clone(),
+        };
+        Ok(self.volatile_datum.put(wtxn, hash, &datum)?)
+    }
+
+    /// Drops a reference to each datum from a block leaving the volatile window, deleting the
+    /// datums that are no longer referenced
+    fn release_datums(&self, wtxn: &mut heed::RwTxn, hashes: &[DatumHash]) -> Result<()> {
+        for hash in hashes.iter() {
+            let Some(datum) = self.volatile_datum.get(wtxn, hash)? else {
+                continue;
+            };
+            let mut datum = rkyv::deserialize::<VolatileDatum, rkyv::rancor::Error>(datum)?;
+            datum.refs -= 1;
+            if datum.refs == 0 {
+                self.volatile_datum.delete(wtxn, hash)?;
+            } else {
+                self.volatile_datum.put(wtxn, hash, &datum)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_block_scratch(&self, wtxn: &mut heed::RwTxn, block_hash: &BlockHash) -> Result<()> {
+        let keys = self
+            .block_scratch
+            .remap_data_type::<DecodeIgnore>()
+            .prefix_iter(wtxn, block_hash.as_slice())?
+            .map(|res| Ok(res?.0.to_vec()))
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys.iter() {
+            self.block_scratch.delete(wtxn, key)?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn clear(&self, indexers: &IndexerList) -> Result<()> {
         let indexers = indexers
             .iter()
@@ -269,51 +1408,1388 @@ impl Db {
         let mut wtxn = self.env.write_txn()?;
 
         self.slots.clear(&mut wtxn)?;
+        self.numbers.clear(&mut wtxn)?;
         self.volatile_block.clear(&mut wtxn)?;
         self.volatile_tx.clear(&mut wtxn)?;
+        self.volatile_datum.clear(&mut wtxn)?;
+        self.block_scratch.clear(&mut wtxn)?;
         self.indexer_ids.clear(&mut wtxn)?;
+        self.meta.delete(&mut wtxn, META_INDEXERS_REGISTERED)?;
+        self.meta.delete(&mut wtxn, META_TOTAL_TXS)?;
+        // Disabled indexers are cleared too, so they miss every block from the origin
+        for id in self.disabled_indexers(&wtxn)?.into_keys() {
+            self.meta
+                .put(&mut wtxn, &disabled_key(&id), &0u64.to_be_bytes())?;
+        }
         for indexer in indexers.iter() {
             indexer.clear(&mut wtxn)?;
         }
 
         wtxn.commit()?;
+        for indexer in indexers.iter() {
+            indexer.on_commit()?;
+        }
         Ok(self.env.resize()?)
     }
 
+    /// Ids of the disabled indexers, with the first slot they missed
+    fn disabled_indexers(&self, rtxn: &heed::RoTxn) -> Result<HashMap<String, u64>> {
+        self.meta
+            .prefix_iter(rtxn, META_DISABLED_PREFIX)?
+            .map(|res| {
+                let (key, from_slot) = res?;
+                let id = &key[META_DISABLED_PREFIX.len()..];
+                Ok((id.to_string(), u64::from_be_bytes(from_slot.try_into()?)))
+            })
+            .collect()
+    }
+
     pub(crate) fn assert_indexer_ids(
         &self,
         rtxn: &heed::RoTxn,
-        indexer_ids: &[&str],
+        configs: &[(&str, Vec<u8>, u32)],
     ) -> Result<()> {
-        // Insert indexer ids if they don't exist
-        if self.indexer_ids.len(rtxn)? == 0 {
+        // Register indexer ids on first use
+        if self.meta.get(rtxn, META_INDEXERS_REGISTERED)?.is_none() {
             let mut wtxn = self.env.write_txn()?;
-            for id in indexer_ids.iter() {
-                self.indexer_ids.put(&mut wtxn, id, &())?;
-            }
-            if indexer_ids.is_empty() {
-                self.indexer_ids.put(&mut wtxn, "empty", &())?;
+            for (id, fingerprint, schema_version) in configs.iter() {
+                self.indexer_ids.put(&mut wtxn, id, fingerprint)?;
+                self.meta.put(
+                    &mut wtxn,
+                    &schema_version_key(id),
+                    &schema_version.to_be_bytes(),
+                )?;
             }
+            self.meta.put(&mut wtxn, META_INDEXERS_REGISTERED, &[])?;
             wtxn.commit()?;
             return Ok(());
         }
 
-        // Check indexer ids
+        // Check indexer ids, which are stored sorted rather than in the order of the list
         let expected_indexer_ids = self
             .indexer_ids
             .iter(rtxn)?
             .map(|res| -> Result<_> { Ok(res?.0) })
             .collect::<Result<Vec<_>>>()?;
+        let mut indexer_ids = configs.iter().map(|(id, ..)| *id).collect::<Vec<_>>();
+        indexer_ids.sort_unstable();
         anyhow::ensure!(
             expected_indexer_ids == indexer_ids,
             "indexer ids don't match. expected: {expected_indexer_ids:?}, got: {indexer_ids:?}"
         );
+
+        for (id, fingerprint, _) in configs.iter() {
+            if self.indexer_ids.get(rtxn, id)? != Some(fingerprint.as_slice()) {
+                return Err(Error::IndexerConfigChanged { id: id.to_string() }.into());
+            }
+        }
         Ok(())
     }
+
+    /// Migrates the tables of indexers registered with an older schema version, see
+    /// [`Indexer::migrate_schema`], or errors with [`Error::IndexerSchemaChanged`] for the first
+    /// one that can't be migrated. Each migration commits on its own.
+    fn assert_schema_versions(
+        &self,
+        rtxn: &heed::RoTxn,
+        indexers: &[MutexGuard<'_, dyn Indexer + Send>],
+    ) -> Result<()> {
+        for indexer in indexers.iter() {
+            let id = indexer.id();
+            let database = self
+                .meta
+                .get(rtxn, &schema_version_key(id))?
+                .context("missing schema version")?;
+            let database =
+                u32::from_be_bytes(database.try_into().context("invalid schema version")?);
+            let current = indexer.schema_version();
+            if database == current {
+                continue;
+            }
+
+            let mut wtxn = self.env.write_txn()?;
+            if current < database || !indexer.migrate_schema(self, &mut wtxn, database)? {
+                return Err(Error::IndexerSchemaChanged {
+                    id: id.to_string(),
+                    database,
+                    indexer: current,
+                }
+                .into());
+            }
+            self.meta
+                .put(&mut wtxn, &schema_version_key(id), &current.to_be_bytes())?;
+            wtxn.commit()?;
+            info!(id, from = database, to = current, "Migrated indexer schema");
+        }
+        Ok(())
+    }
+}
+
+/// Errors with [`Error::PageSizeMismatch`] unless the recorded page size is this system's, which
+/// the map is resized in multiples of
+fn check_page_size(recorded: &[u8]) -> Result<()> {
+    let database = u64::from_be_bytes(recorded.try_into().context("invalid page size")?);
+    let system = page_size::get() as u64;
+    if database != system {
+        return Err(Error::PageSizeMismatch { database, system }.into());
+    }
+    Ok(())
+}
+
+/// The recorded format version, 0 if the database has blocks but none was recorded, or `None`
+/// if it's empty
+fn format_version(
+    rtxn: &heed::RoTxn,
+    meta: &Database<Str, Bytes>,
+    slots: &Database<U64<BigEndian>, RkyvCodec<BlockHash>>,
+) -> Result<Option<u32>> {
+    match meta.get(rtxn, META_FORMAT_VERSION)? {
+        Some(recorded) => Ok(Some(u32::from_be_bytes(
+            recorded.try_into().context("invalid format version")?,
+        ))),
+        None if slots.is_empty(rtxn)? => Ok(None),
+        None => Ok(Some(0)),
+    }
+}
+
+/// Errors with [`Error::FormatVersionMismatch`] unless the database's format version is this
+/// build's
+fn check_format_version(database: u32) -> Result<()> {
+    if database != FORMAT_VERSION {
+        return Err(Error::FormatVersionMismatch {
+            database,
+            current: FORMAT_VERSION,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn disabled_key(id: &str) -> String {
+    format!("{META_DISABLED_PREFIX}{id}")
+}
+
+fn schema_version_key(id: &str) -> String {
+    format!("{META_SCHEMA_VERSION_PREFIX}{id}")
+}
+
+fn indexer_configs<'a>(
+    indexers: &'a [MutexGuard<'_, dyn Indexer + Send>],
+) -> Vec<(&'a str, Vec<u8>, u32)> {
+    indexers
+        .iter()
+        .map(|i| (i.id(), i.config_fingerprint(), i.schema_version()))
+        .collect()
+}
+
+/// Runs two independent reads in parallel and waits for both, e.g. the UTxOs of an address
+/// and the latest value of another indexer for a composite query. LMDB serves concurrent read
+/// txns without blocking each other or the writer, so the latency is that of the slowest read
+/// rather than the sum. Nest calls for more than two reads.
+///
+/// `b` runs on a thread spawned for the call, which costs in the order of tens of
+/// microseconds, so this only pays off for reads slower than that. For many small reads, run
+/// them on a thread pool of your own instead.
+///
+/// Each read should take one short-lived txn at a time, as the indexer queries do: every open
+/// txn holds the resize read-lock, so a read holding a txn while opening another can deadlock
+/// with a pending resize, and a long one delays the writer's next resize.
+pub fn read_parallel<A, B>(
+    a: impl FnOnce() -> Result<A> + Send,
+    b: impl FnOnce() -> Result<B> + Send,
+) -> Result<(A, B)>
+where
+    A: Send,
+    B: Send,
+{
+    std::thread::scope(|scope| {
+        let b = scope.spawn(b);
+        let a = a();
+        let b = match b.join() {
+            Ok(b) => b,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        Ok((a?, b?))
+    })
+}
+
+fn open_table<KC: 'static, DC: 'static>(
+    env: &heed::Env,
+    rtxn: &heed::RoTxn,
+    name: &str,
+) -> Result<Database<KC, DC>> {
+    env.open_database(rtxn, Some(name))?
+        .with_context(|| format!("missing table {name}, is this a hydrant database?"))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+    use crate::test_utils::{
+        MAX_ROLLBACK_BLOCKS, block, hash, output, pointer, temp_db, tx, utxo_addresses,
+        utxo_indexer,
+    };
+    use crate::{GovernanceIndexer, MetadataIndexer, UtxoIndexerBuilder};
+
+    #[test]
+    fn test_max_rollback_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        assert!(Db::new(path, 0).is_err());
+
+        // A single block can still be rolled back, but no more
+        let db = Db::new(path, 1).unwrap();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=3)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        let err = db
+            .roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::RollbackTooDeep { .. })
+        ));
+        db.roll_backward(&indexers, &Point::Specific(20, hash::<32>(2).to_vec()))
+            .unwrap();
+        assert_eq!(
+            db.tip().unwrap(),
+            Point::Specific(20, hash::<32>(2).to_vec())
+        );
+    }
+
+    #[test]
+    fn test_indexer_ids_zero_to_zero() {
+        let (_dir, db) = temp_db();
+        db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[])
+            .unwrap();
+        db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_indexer_ids_zero_to_one() {
+        let (_dir, db) = temp_db();
+        db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[])
+            .unwrap();
+        assert!(
+            db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[("empty", vec![], 0)])
+                .is_err()
+        );
+    }
+
     #[test]
-    fn test_max_rollback_blocks() {}
+    fn test_indexer_ids_one_to_zero() {
+        let (_dir, db) = temp_db();
+        db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[("utxo", vec![], 0)])
+            .unwrap();
+        db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[("utxo", vec![], 0)])
+            .unwrap();
+        assert!(
+            db.assert_indexer_ids(&db.env.read_txn().unwrap(), &[])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_roll_forward_across_commits() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+
+        // One commit spanning two blocks, where the second spends an output of the first
+        let tx1 = tx(1, vec![], vec![output(b"a", 1), output(b"b", 2)]);
+        let tx2 = tx(2, vec![pointer(1, 0)], vec![output(b"c", 1)]);
+        let blocks = [block(1, 10, vec![tx1]), block(2, 20, vec![tx2])];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        let tip = Point::Specific(20, hash::<32>(2).to_vec());
+        assert_eq!(db.tip().unwrap(), tip);
+        assert_eq!(utxo_addresses(&utxo), [b"b", b"c"]);
+
+        // Spend an output that was committed by the previous batch
+        let tx3 = tx(3, vec![pointer(1, 1)], vec![output(b"d", 2)]);
+        db.roll_forward(&indexers, &[block(3, 30, vec![tx3])])
+            .unwrap();
+        let tip = Point::Specific(30, hash::<32>(3).to_vec());
+        assert_eq!(db.tip().unwrap(), tip);
+        assert_eq!(utxo_addresses(&utxo), [b"c", b"d"]);
+    }
+
+    #[test]
+    fn test_delete_tx_not_inserted_by_indexer() {
+        let (_dir, db) = temp_db();
+        let (utxo, _) = utxo_indexer(&db);
+        let metadata = MetadataIndexer::new("metadata", &db.env, Some(vec![721])).unwrap();
+        let metadata = Arc::new(Mutex::new(metadata));
+        let indexers: IndexerList = vec![
+            utxo.clone() as Arc<Mutex<dyn Indexer + Send>>,
+            metadata.clone() as Arc<Mutex<dyn Indexer + Send>>,
+        ];
+
+        let mut nft = tx(1, vec![], vec![output(b"a", 1)]);
+        nft.metadata = vec![(721, vec![0xa0])];
+        let mut message = tx(2, vec![], vec![output(b"b", 1)]);
+        message.metadata = vec![(674, vec![0xa0])];
+        // Only the UTxO indexer inserts the second tx, yet both are asked to delete it
+        let blocks = [block(1, 10, vec![nft]), block(2, 20, vec![message])];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a", b"b"]);
+        let metadata = metadata.lock().unwrap().clone();
+        assert!(metadata.metadata(&hash(2)).unwrap().is_empty());
+
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+        assert_eq!(metadata.metadata(&hash(1)).unwrap(), [(721, vec![0xa0])]);
+    }
+
+    #[test]
+    fn test_rollback_too_deep() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let n = MAX_ROLLBACK_BLOCKS as u64 + 5;
+        let blocks = (1..=n)
+            .map(|number| {
+                let txs = vec![tx(number, vec![], vec![output(b"a", number)])];
+                block(number, number * 10, txs)
+            })
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+
+        // Blocks 1 to 5 left the window, so the furthest back is block 5
+        let tip = db.tip().unwrap();
+        let err = db
+            .roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::RollbackTooDeep {
+                requested_slot: 10,
+                oldest_slot: 50,
+            })
+        ));
+        // Nothing was undone
+        assert_eq!(db.tip().unwrap(), tip);
+        assert_eq!(utxo_addresses(&utxo).len(), n as usize);
+
+        db.roll_backward(&indexers, &Point::Specific(50, hash::<32>(5).to_vec()))
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo).len(), 5);
+    }
+
+    #[test]
+    fn test_roll_back_to_origin() {
+        let (dir, db) = temp_db();
+        db.assert_network(Network::Mainnet).unwrap();
+        // Listed out of order, since the ids are compared regardless of the order
+        let indexers: IndexerList = vec![
+            Arc::new(Mutex::new(
+                UtxoIndexerBuilder::new("utxo").build(&db.env).unwrap(),
+            )) as Arc<Mutex<dyn Indexer + Send>>,
+            Arc::new(Mutex::new(GovernanceIndexer::new("gov", &db.env).unwrap())),
+        ];
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(
+                2,
+                20,
+                vec![tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)])],
+            ),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let summary = db.roll_backward(&indexers, &Point::Origin).unwrap();
+        assert_eq!((summary.blocks, summary.to_slot), (2, 0));
+        assert_eq!(db.tip().unwrap(), Point::Origin);
+        let rtxn = db.env.read_txn().unwrap();
+        assert!(db.indexer_ids.is_empty(&rtxn).unwrap());
+        assert!(db.volatile_block.is_empty(&rtxn).unwrap());
+        assert!(db.volatile_tx.is_empty(&rtxn).unwrap());
+        drop(rtxn);
+        assert_eq!(db.network().unwrap(), Some(Network::Mainnet));
+
+        // The node resumes from the origin, possibly on a different chain
+        let blocks = [block(3, 10, vec![tx(3, vec![], vec![output(b"c", 1)])])];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        let rtxn = db.env.read_txn().unwrap();
+        let ids = db
+            .indexer_ids
+            .iter(&rtxn)
+            .unwrap()
+            .map(|res| res.unwrap().0.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["gov", "utxo"]);
+        drop(rtxn);
+
+        // Reopens with the same indexers, and only those
+        drop((db, indexers));
+        let db = Db::new(dir.path().to_str().unwrap(), MAX_ROLLBACK_BLOCKS).unwrap();
+        assert_eq!(
+            db.tip().unwrap(),
+            Point::Specific(10, hash::<32>(3).to_vec())
+        );
+        let (utxo, indexers) = utxo_indexer(&db);
+        assert!(db.roll_forward(&indexers, &[block(4, 20, vec![])]).is_err());
+        let indexers: IndexerList = vec![
+            utxo.clone() as Arc<Mutex<dyn Indexer + Send>>,
+            Arc::new(Mutex::new(GovernanceIndexer::new("gov", &db.env).unwrap())),
+        ];
+        db.roll_forward(&indexers, &[block(4, 20, vec![])]).unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"c"]);
+    }
+
+    #[test]
+    fn test_snapshot_under_concurrent_writes() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=200)
+            .map(|number| block(number, number * 10, vec![tx(number, vec![], vec![])]))
+            .collect::<Vec<_>>();
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                for chunk in blocks.chunks(5) {
+                    db.roll_forward(&indexers, chunk).unwrap();
+                    db.trim_volatile().unwrap();
+                }
+            });
+            let mut i = 0;
+            while !writer.is_finished() || i == 0 {
+                let path = dir.path().join(format!("snapshot_{i}"));
+                db.snapshot(path.join("data.mdb"), false).unwrap();
+                let copy = Db::open_read_only(path.to_str().unwrap()).unwrap();
+                // Each copy holds whole batches: as many txs as blocks
+                let stats = copy.describe().unwrap().stats;
+                assert_eq!(stats.total_blocks % 5, 0);
+                assert_eq!(stats.total_blocks, stats.total_txs);
+                i += 1;
+            }
+        });
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let (dir, db) = temp_db();
+        let path = dir.path().to_str().unwrap();
+        db.assert_network(Network::Preprod).unwrap();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(2, 20, vec![tx(2, vec![], vec![])]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        let description = db.describe().unwrap();
+        drop((db, indexers));
+
+        let db = Db::open_read_only(path).unwrap();
+        assert_eq!(db.describe().unwrap(), description);
+        assert_eq!(description.network, Some(Network::Preprod));
+        assert_eq!(description.indexer_ids, ["utxo"]);
+        assert_eq!(description.stats.total_txs, 2);
+        assert_eq!(
+            (description.volatile_blocks, description.volatile_txs),
+            (2, 1)
+        );
+        assert!(description.size > 0);
+        assert!(db.env.write_txn().is_err());
+
+        let empty = tempfile::tempdir().unwrap();
+        assert!(Db::open_read_only(empty.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_block_validator() {
+        let dir = tempfile::tempdir().unwrap();
+        let validated = Arc::new(Mutex::new(vec![]));
+        let seen = validated.clone();
+        let options = DbOptions::new(MAX_ROLLBACK_BLOCKS).block_validator(move |block| {
+            seen.lock().unwrap().push(block.number);
+            anyhow::ensure!(!block.txs.is_empty(), "no txs");
+            Ok(())
+        });
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        // Failing the check only logs
+        db.validate_block(&block(1, 10, vec![]));
+        db.validate_block(&block(2, 20, vec![tx(1, vec![], vec![])]));
+        assert_eq!(*validated.lock().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn test_repair() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=3)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        let dangle = |db: &Db| {
+            let mut wtxn = db.env.write_txn().unwrap();
+            db.slots.put(&mut wtxn, &40, &hash(4)).unwrap();
+            db.numbers.put(&mut wtxn, &4, &hash(4)).unwrap();
+            wtxn.commit().unwrap();
+        };
+        dangle(&db);
+
+        let report = db.repair().unwrap();
+        assert_eq!(report.slots, [(Slot(40), hash(4))]);
+        assert_eq!(report.numbers, [(BlockNumber(4), hash(4))]);
+        assert_eq!(
+            db.tip().unwrap(),
+            Point::Specific(30, hash::<32>(3).to_vec())
+        );
+        assert!(db.repair().unwrap().is_empty());
+
+        dangle(&db);
+        drop((db, indexers));
+        let options = DbOptions::new(MAX_ROLLBACK_BLOCKS).repair_on_open(true);
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        assert_eq!(
+            db.tip().unwrap(),
+            Point::Specific(30, hash::<32>(3).to_vec())
+        );
+        let (_, indexers) = utxo_indexer(&db);
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reopen_without_persist() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(
+                2,
+                20,
+                vec![tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)])],
+            ),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        // Copy the files without persisting, as a killed process leaves them
+        let copy = tempfile::tempdir().unwrap();
+        std::fs::copy(dir.path().join("data.mdb"), copy.path().join("data.mdb")).unwrap();
+        drop((db, indexers));
+
+        let db = Db::new(copy.path().to_str().unwrap(), MAX_ROLLBACK_BLOCKS).unwrap();
+        assert_eq!(
+            db.tip().unwrap(),
+            Point::Specific(20, hash::<32>(2).to_vec())
+        );
+        let (utxo, indexers) = utxo_indexer(&db);
+        assert_eq!(
+            utxo.lock()
+                .unwrap()
+                .utxos_by_address(&b"b".to_vec())
+                .unwrap()
+                .len(),
+            1
+        );
+        db.roll_forward(&indexers, &[block(3, 30, vec![])]).unwrap();
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(
+            utxo.lock()
+                .unwrap()
+                .utxos_by_address(&b"a".to_vec())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_registered_indexer_ids() {
+        let (dir, db) = temp_db();
+        drop(db);
+        assert!(
+            Db::registered_indexer_ids(dir.path().to_str().unwrap())
+                .unwrap()
+                .is_empty()
+        );
+
+        let (dir, db) = temp_db();
+        let (_, mut indexers) = utxo_indexer(&db);
+        let governance = GovernanceIndexer::new("governance", &db.env).unwrap();
+        indexers.push(Arc::new(Mutex::new(governance)) as Arc<Mutex<dyn Indexer + Send>>);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        drop((db, indexers));
+        assert_eq!(
+            Db::registered_indexer_ids(dir.path().to_str().unwrap()).unwrap(),
+            ["governance", "utxo"]
+        );
+    }
+
+    #[test]
+    fn test_network_mismatch() {
+        let (dir, db) = temp_db();
+        db.assert_network(Network::Mainnet).unwrap();
+        db.assert_network(Network::Mainnet).unwrap();
+        assert_eq!(db.network().unwrap(), Some(Network::Mainnet));
+
+        // Recorded network survives reopening
+        drop(db);
+        let db = Db::new(dir.path().to_str().unwrap(), 10).unwrap();
+        let err = db.assert_network(Network::Preprod).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NetworkMismatch {
+                database: Network::Mainnet,
+                node: Network::Preprod,
+            })
+        ));
+        assert_eq!(db.network().unwrap(), Some(Network::Mainnet));
+    }
+
+    #[test]
+    fn test_map_size_options() {
+        const MB: usize = 1024 * 1024;
+        let dir = tempfile::tempdir().unwrap();
+        let options = DbOptions::new(MAX_ROLLBACK_BLOCKS)
+            .map_size(16 * MB)
+            .min_free_space(64 * MB)
+            .resize_policy(ResizePolicy::Fixed(32 * MB));
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        assert_eq!(db.env.info().map_size, 16 * MB);
+
+        // Less free space than the minimum, so the first write grows the map by as many steps
+        // as it takes to leave the minimum free
+        let (_, indexers) = utxo_indexer(&db);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        assert_eq!(db.env.info().map_size, 80 * MB);
+
+        // Which is enough until the next write
+        db.roll_forward(&indexers, &[block(2, 20, vec![])]).unwrap();
+        assert_eq!(db.env.info().map_size, 80 * MB);
+    }
+
+    #[test]
+    fn test_resize_policy_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let policies = [
+            ResizePolicy::Fixed(0),
+            ResizePolicy::Geometric {
+                percent: 0,
+                max_increment: 1024,
+            },
+        ];
+        for policy in policies {
+            let options = DbOptions::new(MAX_ROLLBACK_BLOCKS).resize_policy(policy);
+            assert!(Db::with_options(path, options).is_err());
+        }
+    }
+
+    #[test]
+    fn test_page_size_mismatch() {
+        let (dir, db) = temp_db();
+        let path = dir.path().to_str().unwrap();
+        // As if created on a system with larger pages
+        let other = page_size::get() as u64 * 4;
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.meta
+            .put(&mut wtxn, META_PAGE_SIZE, &other.to_be_bytes())
+            .unwrap();
+        wtxn.commit().unwrap();
+        drop(db);
+
+        for res in [Db::new(path, 10), Db::open_read_only(path)] {
+            let err = res.err().unwrap();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::PageSizeMismatch { database, .. }) if *database == other
+            ));
+        }
+    }
+
+    #[test]
+    fn test_format_version_mismatch() {
+        // Created before the format version was recorded, but never synced
+        let (dir, db) = temp_db();
+        let path = dir.path().to_str().unwrap();
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.meta.delete(&mut wtxn, META_FORMAT_VERSION).unwrap();
+        wtxn.commit().unwrap();
+        drop(db);
+        let db = Db::new(path, 10).unwrap();
+
+        // Synced before the format version was recorded
+        let (_, indexers) = utxo_indexer(&db);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.meta.delete(&mut wtxn, META_FORMAT_VERSION).unwrap();
+        wtxn.commit().unwrap();
+        drop((db, indexers));
+
+        for res in [Db::new(path, 10), Db::open_read_only(path)] {
+            let err = res.err().unwrap();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::FormatVersionMismatch { database: 0, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_replaces_stale_temp_file() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+
+        // Left behind by a snapshot that crashed mid-write
+        let snapshot = dir.path().join("snapshot");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        std::fs::write(snapshot.join("data.mdb.tmp"), b"partial").unwrap();
+
+        db.snapshot(snapshot.join("data.mdb"), false).unwrap();
+        assert!(!snapshot.join("data.mdb.tmp").exists());
+        assert!(db.snapshot(snapshot.join("data.mdb"), false).is_err());
+
+        let copy = Db::new(snapshot.to_str().unwrap(), 10).unwrap();
+        assert_eq!(
+            copy.tip().unwrap(),
+            Point::Specific(10, hash::<32>(1).to_vec())
+        );
+    }
+
+    #[test]
+    fn test_restore_from_snapshot() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        let snapshot = dir.path().join("snapshot.mdb");
+        db.snapshot(&snapshot, false).unwrap();
+
+        // Neither a missing snapshot nor one that isn't a database leaves files behind
+        let restored = dir.path().join("restored");
+        let garbage = dir.path().join("garbage.mdb");
+        std::fs::write(&garbage, vec![0xab; 16 * 1024]).unwrap();
+        for snapshot in [dir.path().join("missing.mdb"), garbage] {
+            let res = Db::restore_from_snapshot(
+                &snapshot,
+                restored.to_str().unwrap(),
+                DbOptions::new(MAX_ROLLBACK_BLOCKS),
+            );
+            assert!(res.is_err());
+            assert!(!restored.join("data.mdb").exists());
+            assert!(!restored.join("data.mdb.tmp").exists());
+        }
+
+        let copy = Db::restore_from_snapshot(
+            &snapshot,
+            restored.to_str().unwrap(),
+            DbOptions::new(MAX_ROLLBACK_BLOCKS),
+        )
+        .unwrap();
+        assert_eq!(
+            copy.tip().unwrap(),
+            Point::Specific(10, hash::<32>(1).to_vec())
+        );
+    }
+
+    #[test]
+    fn test_datums_within_volatile_window() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let (shared, other) = (hash::<32>(100), hash::<32>(101));
+        let mut block1 = block(1, 10, vec![]);
+        block1.datums.insert(shared.clone(), vec![1]);
+        let mut block2 = block(2, 20, vec![]);
+        block2.datums.insert(shared.clone(), vec![1]);
+        block2.datums.insert(other.clone(), vec![2]);
+        db.roll_forward(&indexers, &[block1, block2]).unwrap();
+        assert_eq!(db.datum(&shared).unwrap(), Some(vec![1]));
+        assert_eq!(db.datum(&other).unwrap(), Some(vec![2]));
+        let hashes = db
+            .datum_hashes_iter(&db.env.read_txn().unwrap())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(hashes, [shared.clone(), other.clone()]);
+
+        // Still referenced by the first block
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(db.datum(&shared).unwrap(), Some(vec![1]));
+        assert_eq!(db.datum(&other).unwrap(), None);
+
+        // Dropped once the block leaves the volatile window
+        let blocks = (2..=MAX_ROLLBACK_BLOCKS as u64 + 1)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        assert_eq!(db.datum(&shared).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_parallel() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let blocks = [block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])])];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let utxo = utxo.lock().unwrap().clone();
+        let (utxos, tip) = read_parallel(|| utxo.utxos(), || db.tip()).unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(tip, Point::Specific(10, hash::<32>(1).to_vec()));
+
+        let err =
+            read_parallel(|| db.tip(), || -> Result<()> { anyhow::bail!("failed") }).unwrap_err();
+        assert_eq!(err.to_string(), "failed");
+    }
+
+    #[test]
+    fn test_tx_context() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let (stored, missing) = (hash::<32>(100), hash::<32>(101));
+        let mut locked = output(b"script", 5);
+        locked.datum_hash = Some(stored.clone());
+        let mut block1 = block(1, 10, vec![tx(1, vec![], vec![locked.clone()])]);
+        block1.datums.insert(stored.clone(), vec![1]);
+
+        let mut change = output(b"a", 4);
+        change.datum_hash = Some(missing.clone());
+        let mut spend = tx(2, vec![pointer(1, 0), pointer(99, 0)], vec![change]);
+        spend.scripts = vec![crate::primitives::Script::V2(vec![7])];
+        db.roll_forward(&indexers, &[block1, block(2, 20, vec![spend])])
+            .unwrap();
+
+        let context = db.tx_context(&hash(2)).unwrap().unwrap();
+        assert_eq!(context.tx.hash, hash(2));
+        // The second input was created before the volatile window
+        assert_eq!(context.resolved_inputs.len(), 2);
+        assert_eq!(
+            context.resolved_inputs[0].as_ref().unwrap().address,
+            b"script"
+        );
+        assert!(context.resolved_inputs[1].is_none());
+        assert_eq!(context.datums, [(stored, Some(vec![1])), (missing, None)]);
+        assert_eq!(context.scripts.len(), 1);
+        assert!(db.tx_context(&hash(3)).unwrap().is_none());
+    }
+
+    struct ScratchIndexer;
+
+    impl Indexer for ScratchIndexer {
+        fn id(&self) -> &str {
+            "scratch"
+        }
+
+        fn insert_block(&self, db: &Db, wtxn: &mut heed::RwTxn, block: &Block) -> Result<()> {
+            db.put_block_scratch(wtxn, self.id(), &block.hash, &block.number.to_be_bytes())
+        }
+
+        fn delete_block(
+            &self,
+            db: &Db,
+            wtxn: &mut heed::RwTxn,
+            block: &VolatileBlock,
+        ) -> Result<()> {
+            // Still readable while the block is being rolled back
+            let data = db.get_block_scratch(wtxn, self.id(), &block.hash)?;
+            anyhow::ensure!(data == Some(block.number.to_be_bytes().to_vec()));
+            Ok(())
+        }
+
+        fn wants_datums(&self) -> bool {
+            false
+        }
+
+        fn clear(&self, _: &mut heed::RwTxn) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_block_scratch() {
+        let (_dir, db) = temp_db();
+        let indexers: IndexerList =
+            vec![Arc::new(Mutex::new(ScratchIndexer)) as Arc<Mutex<dyn Indexer + Send>>];
+        let scratch = |number: u64| {
+            let rtxn = db.env.read_txn().unwrap();
+            db.get_block_scratch(&rtxn, "scratch", &hash(number))
+                .unwrap()
+        };
+
+        let blocks = (1..=MAX_ROLLBACK_BLOCKS as u64)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        assert_eq!(scratch(2), Some(2u64.to_be_bytes().to_vec()));
+
+        // Deleted on rollback
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(scratch(2), None);
+        assert_eq!(scratch(1), Some(1u64.to_be_bytes().to_vec()));
+
+        // Deleted once the block leaves the volatile window
+        let blocks = (2..=MAX_ROLLBACK_BLOCKS as u64 + 1)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        assert_eq!(scratch(1), None);
+        assert!(scratch(2).is_some());
+    }
+
+    /// Records the blocks each rollback reverted
+    #[derive(Default)]
+    struct RollbackIndexer(Mutex<Vec<Vec<(Slot, BlockHash)>>>);
+
+    impl Indexer for RollbackIndexer {
+        fn id(&self) -> &str {
+            "rollback"
+        }
+
+        fn on_rollback(&self, reverted: &[(Slot, BlockHash)]) -> Result<()> {
+            self.0.lock().unwrap().push(reverted.to_vec());
+            Ok(())
+        }
+
+        fn clear(&self, _: &mut heed::RwTxn) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails to undo the block at a slot
+    struct FailingRollbackIndexer(u64);
+
+    impl Indexer for FailingRollbackIndexer {
+        fn id(&self) -> &str {
+            "failing_rollback"
+        }
+
+        fn delete_block(&self, _: &Db, _: &mut heed::RwTxn, block: &VolatileBlock) -> Result<()> {
+            anyhow::ensure!(
+                block.slot != self.0,
+                "failed to undo block at {}",
+                block.slot
+            );
+            Ok(())
+        }
+
+        fn clear(&self, _: &mut heed::RwTxn) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_on_rollback() {
+        let (_dir, db) = temp_db();
+        let indexer = Arc::new(Mutex::new(RollbackIndexer::default()));
+        let indexers: IndexerList = vec![indexer.clone() as Arc<Mutex<dyn Indexer + Send>>];
+        let blocks = (1..=4)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let rollbacks = || indexer.lock().unwrap().0.lock().unwrap().clone();
+        db.roll_backward(&indexers, &Point::Specific(20, hash::<32>(2).to_vec()))
+            .unwrap();
+        assert_eq!(
+            rollbacks(),
+            [vec![(Slot(40), hash(4)), (Slot(30), hash(3))]]
+        );
+
+        // Nothing reverted, nothing to report
+        db.roll_backward(&indexers, &Point::Specific(20, hash::<32>(2).to_vec()))
+            .unwrap();
+        assert_eq!(rollbacks().len(), 1);
+        // Cleared instead
+        db.roll_backward(&indexers, &Point::Origin).unwrap();
+        assert_eq!(rollbacks().len(), 1);
+    }
+
+    #[test]
+    fn test_on_rollback_after_failure() {
+        let (_dir, db) = temp_db();
+        let indexer = Arc::new(Mutex::new(RollbackIndexer::default()));
+        let indexers: IndexerList = vec![
+            indexer.clone() as Arc<Mutex<dyn Indexer + Send>>,
+            Arc::new(Mutex::new(FailingRollbackIndexer(20))),
+        ];
+        let blocks = (1..=4)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        // The blocks undone before the failing one were committed, so they're still reported
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap_err();
+        let rollbacks = indexer.lock().unwrap().0.lock().unwrap().clone();
+        assert_eq!(rollbacks, [vec![(Slot(40), hash(4)), (Slot(30), hash(3))]]);
+        assert_eq!(db.tip().unwrap().slot_or_default(), 20);
+    }
+
+    /// Indexer at a schema version, which can only migrate from the one before it
+    struct VersionedIndexer(u32);
+
+    impl Indexer for VersionedIndexer {
+        fn id(&self) -> &str {
+            "versioned"
+        }
+
+        fn schema_version(&self) -> u32 {
+            self.0
+        }
+
+        fn migrate_schema(&self, _: &Db, _: &mut heed::RwTxn, from: u32) -> Result<bool> {
+            Ok(from + 1 == self.0)
+        }
+
+        fn clear(&self, _: &mut heed::RwTxn) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let (_dir, db) = temp_db();
+        let versioned = |version| -> IndexerList {
+            vec![Arc::new(Mutex::new(VersionedIndexer(version))) as Arc<Mutex<dyn Indexer + Send>>]
+        };
+        db.roll_forward(&versioned(1), &[block(1, 10, vec![])])
+            .unwrap();
+        db.roll_forward(&versioned(2), &[block(2, 20, vec![])])
+            .unwrap();
+
+        // No migration from two versions back, nor to an older version
+        let err = db
+            .roll_forward(&versioned(4), &[block(3, 30, vec![])])
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::IndexerSchemaChanged {
+                database: 2,
+                indexer: 4,
+                ..
+            })
+        ));
+        assert!(
+            db.roll_backward(&versioned(1), &Point::Specific(10, hash::<32>(1).to_vec()))
+                .is_err()
+        );
+
+        // Resyncing from the origin rebuilds the tables with the new version
+        db.roll_backward(&versioned(4), &Point::Origin).unwrap();
+        db.roll_forward(&versioned(4), &[block(1, 10, vec![])])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_skips_unwanted_datums() {
+        let (_dir, db) = temp_db();
+        let (_, utxo) = utxo_indexer(&db);
+        let scratch: IndexerList =
+            vec![Arc::new(Mutex::new(ScratchIndexer)) as Arc<Mutex<dyn Indexer + Send>>];
+        let options = crate::indexer::parse_options(&scratch);
+        assert!(!options.datums && options.scripts);
+        assert_eq!(
+            crate::indexer::parse_options(&utxo),
+            crate::primitives::ParseOptions {
+                datums: true,
+                scripts: false,
+                metadata: false,
+            }
+        );
+
+        let mut block1 = block(1, 10, vec![]);
+        block1.datums.insert(hash(100), vec![1]);
+        db.roll_forward(&scratch, &[block1]).unwrap();
+        assert_eq!(db.datum(&hash(100)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_datums_while_indexer_disabled() {
+        let (_dir, db) = temp_db();
+        let (_, utxo) = utxo_indexer(&db);
+        let indexers: IndexerList = vec![
+            utxo[0].clone(),
+            Arc::new(Mutex::new(ScratchIndexer)) as Arc<Mutex<dyn Indexer + Send>>,
+        ];
+        let shared = hash::<32>(100);
+        let mut block1 = block(1, 10, vec![]);
+        block1.datums.insert(shared.clone(), vec![1]);
+        db.roll_forward(&indexers, &[block1]).unwrap();
+
+        // Only the scratch indexer is active, which doesn't want datums, so the second block
+        // takes no reference to the datum and its rollback releases none
+        db.disable_indexer("utxo").unwrap();
+        let mut block2 = block(2, 20, vec![]);
+        block2.datums.insert(shared.clone(), vec![1]);
+        db.roll_forward(&indexers, &[block2]).unwrap();
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(db.datum(&shared).unwrap(), Some(vec![1]));
+
+        // Backfilling takes the references the blocks missed
+        let mut block2 = block(2, 20, vec![]);
+        block2.datums.insert(hash(101), vec![2]);
+        db.roll_forward(&indexers, &[block2]).unwrap();
+        assert_eq!(db.datum(&hash(101)).unwrap(), None);
+        db.backfill_indexer(&utxo[0]).unwrap();
+        assert_eq!(db.datum(&hash(101)).unwrap(), Some(vec![2]));
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(db.datum(&hash(101)).unwrap(), None);
+        assert_eq!(db.datum(&shared).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_disable_and_backfill_indexer() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let blocks = [block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])])];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        db.disable_indexer("utxo").unwrap();
+        assert_eq!(db.indexer_disabled_from("utxo").unwrap(), Some(Slot(11)));
+        let blocks = [
+            block(
+                2,
+                20,
+                vec![tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)])],
+            ),
+            block(3, 30, vec![tx(3, vec![], vec![output(b"c", 1)])]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+
+        // Rolling back a block it missed leaves it alone
+        db.roll_backward(&indexers, &Point::Specific(20, hash::<32>(2).to_vec()))
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+
+        assert_eq!(db.backfill_indexer(&indexers[0]).unwrap(), 1);
+        assert_eq!(db.indexer_disabled_from("utxo").unwrap(), None);
+        assert_eq!(utxo_addresses(&utxo), [b"b"]);
+
+        // What it backfilled rolls back like the rest
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+        assert!(db.backfill_indexer(&indexers[0]).is_err());
+    }
+
+    #[test]
+    fn test_backfill_fork_before_disabling() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(2, 30, vec![tx(2, vec![], vec![output(b"b", 1)])]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.disable_indexer("utxo").unwrap();
+        db.roll_forward(&indexers, &[block(3, 40, vec![])]).unwrap();
+
+        // Undoes a block it saw, then the fork lands before that block's slot
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(db.indexer_disabled_from("utxo").unwrap(), Some(Slot(11)));
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+        let fork = block(4, 20, vec![tx(4, vec![], vec![output(b"c", 1)])]);
+        db.roll_forward(&indexers, &[fork]).unwrap();
+
+        assert_eq!(db.backfill_indexer(&indexers[0]).unwrap(), 1);
+        assert_eq!(utxo_addresses(&utxo), [b"a", b"c"]);
+    }
+
+    #[test]
+    fn test_backfill_beyond_volatile_window() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        db.disable_indexer("utxo").unwrap();
+        let blocks = (2..=MAX_ROLLBACK_BLOCKS as u64 + 2)
+            .map(|i| block(i, i * 10, vec![tx(i, vec![], vec![output(b"a", 1)])]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+
+        let err = db.backfill_indexer(&indexers[0]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::BackfillTooFar { from_slot: 11, .. })
+        ));
+        assert!(utxo_addresses(&utxo).is_empty());
+        assert_eq!(db.indexer_disabled_from("utxo").unwrap(), Some(Slot(11)));
+    }
+
+    #[test]
+    fn test_blocks_by_number() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        // Contiguous numbers, with gaps between the slots
+        let blocks = (1..=MAX_ROLLBACK_BLOCKS as u64 + 2)
+            .map(|i| block(i, i * i, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let fifth = db.block_by_number(BlockNumber(5)).unwrap().unwrap();
+        assert_eq!((fifth.number, fifth.slot), (5, 25));
+        let numbers = |range| {
+            db.blocks_by_number_range(range)
+                .unwrap()
+                .iter()
+                .map(|block| block.number)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(numbers(BlockNumber(3)..BlockNumber(6)), [3, 4, 5]);
+
+        // Trimmed blocks are no longer returned
+        db.trim_volatile().unwrap();
+        assert!(db.block_by_number(BlockNumber(2)).unwrap().is_none());
+        assert_eq!(numbers(BlockNumber(1)..BlockNumber(5)), [3, 4]);
+
+        db.roll_backward(&indexers, &Point::Specific(36, hash::<32>(6).to_vec()))
+            .unwrap();
+        assert!(db.block_by_number(BlockNumber(7)).unwrap().is_none());
+        assert_eq!(numbers(BlockNumber(5)..BlockNumber(100)), [5, 6]);
+
+        // Rolled back numbers are reused by the new chain
+        db.roll_forward(&indexers, &[block(7, 40, vec![])]).unwrap();
+        assert_eq!(
+            db.block_by_number(BlockNumber(7)).unwrap().unwrap().slot,
+            40
+        );
+    }
+
+    #[test]
+    fn test_duplicate_block() {
+        let (dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let first = block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]);
+        db.roll_forward(&indexers, &[first.clone()]).unwrap();
+        // Skipped, whether in a later batch or twice within the same one
+        db.roll_forward(&indexers, &[first.clone()]).unwrap();
+        let second = block(2, 20, vec![tx(2, vec![], vec![output(b"b", 1)])]);
+        db.roll_forward(&indexers, &[second.clone(), second.clone()])
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a", b"b"]);
+        let stats = db.chain_stats().unwrap();
+        assert_eq!((stats.total_blocks, stats.total_txs), (2, 2));
+        drop((db, utxo, indexers));
+
+        let options = DbOptions::new(MAX_ROLLBACK_BLOCKS).reject_duplicate_blocks(true);
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        let (_, indexers) = utxo_indexer(&db);
+        let mut moved = first;
+        moved.slot = 30;
+        let err = db.roll_forward(&indexers, &[moved]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DuplicateBlock {
+                slot: 30,
+                existing_slot: 10,
+                ..
+            })
+        ));
+        assert_eq!(
+            db.tip().unwrap(),
+            Point::Specific(20, hash::<32>(2).to_vec())
+        );
+    }
+
+    #[test]
+    fn test_block_hashes() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=5)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let hashes = |iter: BlockHashIter| iter.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(
+            hashes(db.block_hashes(Slot(20)..Slot(40))),
+            [(Slot(20), hash(2)), (Slot(30), hash(3))]
+        );
+        assert_eq!(hashes(db.block_hashes(..)).len(), 5);
+        // Continues across batches, up to the end of the range
+        assert_eq!(
+            hashes(db.block_hashes_batched(Slot(20)..=Slot(40), 2)),
+            [
+                (Slot(20), hash(2)),
+                (Slot(30), hash(3)),
+                (Slot(40), hash(4))
+            ]
+        );
+        assert_eq!(hashes(db.block_hashes_batched(.., 1)).len(), 5);
+        assert_eq!(
+            db.recent_block_hashes(2).unwrap(),
+            [(Slot(50), hash(5)), (Slot(40), hash(4))]
+        );
+        assert!(hashes(db.block_hashes(Slot(60)..)).is_empty());
+
+        assert_eq!(db.block_by_slot(Slot(30)).unwrap().unwrap().number, 3);
+        assert!(db.block_by_slot(Slot(35)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_block_with_txs() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=MAX_ROLLBACK_BLOCKS as u64 + 1)
+            .map(|number| {
+                // The second tx has no outputs, so the utxo indexer doesn't retain it
+                let txs = vec![
+                    tx(number * 10, vec![], vec![output(b"a", number)]),
+                    tx(number * 10 + 1, vec![], vec![]),
+                    tx(number * 10 + 2, vec![], vec![output(b"b", number)]),
+                ];
+                block(number, number * 10, txs)
+            })
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+
+        let (block, txs) = db.block_with_txs(&hash(2)).unwrap().unwrap();
+        assert_eq!(block.tx_count, 3);
+        let tx_hashes = txs.into_iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        assert_eq!(tx_hashes, [hash(20), hash(22)]);
+
+        assert!(db.block_with_txs(&hash(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_chain_stats() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=MAX_ROLLBACK_BLOCKS as u64 + 2)
+            .map(|number| block(number, number * 10, vec![tx(number, vec![], vec![])]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        let stats = db.chain_stats().unwrap();
+        assert_eq!(stats.total_blocks, MAX_ROLLBACK_BLOCKS as u64 + 2);
+        // Not stored by the UTxO indexer, yet still counted
+        assert_eq!(stats.total_txs, MAX_ROLLBACK_BLOCKS as u64 + 2);
+
+        let tip = MAX_ROLLBACK_BLOCKS as u64;
+        db.roll_backward(
+            &indexers,
+            &Point::Specific(tip * 10, hash::<32>(tip).to_vec()),
+        )
+        .unwrap();
+        let stats = db.chain_stats().unwrap();
+        assert_eq!(
+            stats.tip,
+            Point::Specific(tip * 10, hash::<32>(tip).to_vec())
+        );
+        assert_eq!((stats.total_blocks, stats.total_txs), (tip, tip));
+
+        db.roll_backward(&indexers, &Point::Origin).unwrap();
+        let stats = db.chain_stats().unwrap();
+        assert_eq!((stats.total_blocks, stats.total_txs), (0, 0));
+    }
 }
@@ -0,0 +1,254 @@
+use std::sync::Arc;
+
+use crate::primitives::Block;
+
+/// Options for opening a [`Db`](super::Db)
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// Number of most recent blocks kept in the volatile db, and therefore the deepest rollback
+    /// that can be handled. Must be at least 1, since undoing a block needs its volatile copy.
+    /// Nodes may roll back up to the network's security parameter, 2160 blocks on mainnet, so
+    /// anything lower risks a [`RollbackTooDeep`](super::Error::RollbackTooDeep) that can only
+    /// be recovered from by resyncing.
+    pub max_rollback_blocks: usize,
+    /// Number of blocks applied per write transaction while syncing. Committing less often
+    /// speeds up the initial sync considerably, with two caveats:
+    /// - a crash loses up to `commit_interval` blocks of progress, which are re-fetched from the
+    ///   node on restart since the tip only advances on commit
+    /// - indexers observe state from the uncommitted transaction for all blocks of the batch,
+    ///   so anything they keep outside of LMDB must not assume a block is durable once applied
+    ///
+    /// Pending blocks are always committed before a rollback and when nearing the tip.
+    pub commit_interval: usize,
+    /// Bytes of block CBOR buffered between fetching blocks and writing them. Sync waits for the
+    /// writer once it's full, bounding memory use regardless of block sizes.
+    pub write_buffer_bytes: usize,
+    /// Paces the writer while it's far from the tip, so the write burst of an initial sync
+    /// doesn't saturate shared storage. Once the buffer fills, sync waits for the writer, so
+    /// block fetching slows down with it. Blocks near the tip aren't throttled, so live sync
+    /// keeps up with the chain. Unlimited by default.
+    ///
+    /// The initial sync takes at least as long as the limit allows for the whole chain, e.g.
+    /// about a day per 850GB of blocks at 10MB/s, so set it as high as the storage tolerates.
+    pub write_rate_limit: Option<WriteRateLimit>,
+    /// Error when the node serves a block that was already applied, instead of skipping it with
+    /// a warning. A duplicate means a node bug or a misbehaving peer.
+    pub reject_duplicate_blocks: bool,
+    /// Size of the LMDB map when creating the database, 2GB by default. When opening an
+    /// existing database, LMDB grows it to at least the size of the data file. The map is
+    /// grown by [`DbOptions::resize_policy`] whenever less than
+    /// [`DbOptions::min_free_space`] of it is left after a write, so an initial size below the
+    /// minimum free space grows on the first write.
+    pub map_size: usize,
+    /// Free space in the map below which it's grown, 1GB by default
+    pub min_free_space: usize,
+    /// How much to grow the LMDB map by when it's running out of free space. Each step must
+    /// grow it by at least one byte.
+    pub resize_policy: ResizePolicy,
+    /// Flags the LMDB environment is opened with, set with [`DbOptions::env_flags()`]. The
+    /// defaults skip fsyncing on commit, leaving durability to
+    /// [`Db::persist`](super::Db::persist), and write through the memory map. See its docs for
+    /// what survives a crash.
+    pub(crate) env_flags: heed::EnvFlags,
+    /// Records how long each phase of applying blocks takes into [`SyncMetrics`], see
+    /// [`Db::metrics`](super::Db::metrics). Off by default since timing every block has some
+    /// overhead.
+    ///
+    /// [`SyncMetrics`]: crate::SyncMetrics
+    pub timings: bool,
+    /// Checks every block the writer decodes before applying it, see [`BlockValidator`]
+    pub block_validator: Option<BlockValidator>,
+    /// Runs [`Db::repair`](super::Db::repair) when opening, off by default
+    pub repair_on_open: bool,
+}
+
+impl DbOptions {
+    pub fn new(max_rollback_blocks: usize) -> Self {
+        Self {
+            max_rollback_blocks,
+            commit_interval: 1,
+            write_buffer_bytes: 256 * MB,
+            write_rate_limit: None,
+            reject_duplicate_blocks: false,
+            map_size: 2 * GB,
+            min_free_space: GB,
+            resize_policy: ResizePolicy::default(),
+            env_flags: heed::EnvFlags::NO_SYNC // manually fsync data
+                | heed::EnvFlags::NO_META_SYNC // manually fsync metadata
+                | heed::EnvFlags::WRITE_MAP, // assume no memory unsafety in this program
+            timings: false,
+            block_validator: None,
+            repair_on_open: false,
+        }
+    }
+
+    pub fn commit_interval(mut self, commit_interval: usize) -> Self {
+        self.commit_interval = commit_interval.max(1);
+        self
+    }
+
+    pub fn write_buffer_bytes(mut self, write_buffer_bytes: usize) -> Self {
+        self.write_buffer_bytes = write_buffer_bytes;
+        self
+    }
+
+    pub fn write_rate_limit(mut self, write_rate_limit: WriteRateLimit) -> Self {
+        self.write_rate_limit = Some(write_rate_limit);
+        self
+    }
+
+    pub fn reject_duplicate_blocks(mut self, reject_duplicate_blocks: bool) -> Self {
+        self.reject_duplicate_blocks = reject_duplicate_blocks;
+        self
+    }
+
+    pub fn map_size(mut self, map_size: usize) -> Self {
+        self.map_size = map_size;
+        self
+    }
+
+    pub fn min_free_space(mut self, min_free_space: usize) -> Self {
+        self.min_free_space = min_free_space;
+        self
+    }
+
+    /// Replaces the default flags the LMDB environment is opened with.
+    ///
+    /// # Safety
+    ///
+    /// The flags are passed to LMDB unchecked, and some of them make it unsound to use the
+    /// database the way this crate does, e.g. `NO_LOCK` requires the caller to keep every other
+    /// thread and process from using the environment concurrently, and `NO_SUB_DIR` or
+    /// `READ_ONLY` change what the path and txns mean. See the LMDB docs of each flag for the
+    /// requirements the caller must uphold.
+    pub unsafe fn env_flags(mut self, env_flags: heed::EnvFlags) -> Self {
+        self.env_flags = env_flags;
+        self
+    }
+
+    pub fn resize_policy(mut self, resize_policy: ResizePolicy) -> Self {
+        self.resize_policy = resize_policy;
+        self
+    }
+
+    pub fn timings(mut self, timings: bool) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    pub fn block_validator(
+        mut self,
+        validator: impl Fn(&Block) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.block_validator = Some(BlockValidator(Arc::new(validator)));
+        self
+    }
+
+    pub fn repair_on_open(mut self, repair_on_open: bool) -> Self {
+        self.repair_on_open = repair_on_open;
+        self
+    }
+}
+
+/// Monitoring hook run on every block the writer decodes, before it's applied, e.g. to alert
+/// when a block lacks a tx a protocol is expected to submit. Errors are logged as warnings and
+/// the block is applied regardless, so a failing check never halts sync. It runs on the
+/// writer task, so it must be fast: any time it takes is added to every block.
+#[derive(Clone)]
+pub struct BlockValidator(Arc<dyn Fn(&Block) -> anyhow::Result<()> + Send + Sync>);
+
+impl BlockValidator {
+    pub(crate) fn validate(&self, block: &Block) -> anyhow::Result<()> {
+        (self.0)(block)
+    }
+}
+
+impl std::fmt::Debug for BlockValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BlockValidator")
+    }
+}
+
+/// Rate the writer is paced to, see [`DbOptions::write_rate_limit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteRateLimit {
+    /// Bytes of block CBOR applied per second
+    BytesPerSec(u64),
+    /// Write transactions committed per second, see [`DbOptions::commit_interval`]
+    CommitsPerSec(u64),
+}
+
+const MB: usize = 1024 * 1024;
+const GB: usize = 1024 * MB;
+
+/// Growth strategy for the LMDB map. Every resize needs exclusive access to the environment,
+/// so large databases benefit from growing in bigger steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizePolicy {
+    /// Grow by a fixed number of bytes
+    Fixed(usize),
+    /// Grow by a percentage of the current map size, clamped to `max_increment` bytes
+    Geometric {
+        percent: usize,
+        max_increment: usize,
+    },
+}
+
+impl Default for ResizePolicy {
+    fn default() -> Self {
+        ResizePolicy::Fixed(GB)
+    }
+}
+
+impl ResizePolicy {
+    /// Whether every step grows the map, i.e. neither the fixed step, the percentage nor the
+    /// clamp is 0
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            ResizePolicy::Fixed(increment) => increment > 0,
+            ResizePolicy::Geometric {
+                percent,
+                max_increment,
+            } => percent > 0 && max_increment > 0,
+        }
+    }
+
+    pub fn increment(&self, current_size: usize) -> usize {
+        match *self {
+            ResizePolicy::Fixed(increment) => increment,
+            ResizePolicy::Geometric {
+                percent,
+                max_increment,
+            } => (current_size / 100 * percent).min(max_increment),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geometric_resize_is_clamped() {
+        let policy = ResizePolicy::Geometric {
+            percent: 10,
+            max_increment: 4 * GB,
+        };
+        assert_eq!(policy.increment(10 * GB), GB);
+        assert_eq!(policy.increment(100 * GB), 4 * GB);
+        assert_eq!(ResizePolicy::default().increment(100 * GB), GB);
+    }
+
+    #[test]
+    fn test_resize_policy_is_valid() {
+        assert!(ResizePolicy::default().is_valid());
+        assert!(!ResizePolicy::Fixed(0).is_valid());
+        for (percent, max_increment) in [(0, GB), (10, 0)] {
+            let policy = ResizePolicy::Geometric {
+                percent,
+                max_increment,
+            };
+            assert!(!policy.is_valid());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use pallas::network::miniprotocols::Point;
+use serde::Serialize;
+
+use super::Db;
+use crate::network::Network;
+
+#[derive(Serialize)]
+struct Status {
+    network: Option<Network>,
+    tip: Option<TipStatus>,
+    indexers: Vec<IndexerStatus>,
+    /// Tables not named after a registered indexer, including the database's own
+    tables: BTreeMap<String, u64>,
+    env: EnvStatus,
+}
+
+#[derive(Serialize)]
+struct TipStatus {
+    slot: u64,
+    hash: String,
+    /// `None` if the tip block was rolled back before its number was read
+    number: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct IndexerStatus {
+    id: String,
+    tables: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct EnvStatus {
+    map_size: usize,
+    used: usize,
+    readers: u32,
+}
+
+impl Db {
+    /// Writes the network, tip, registered indexers with the entry counts of their tables, and
+    /// the size of the map as a single JSON object, for serving from a monitoring endpoint.
+    /// Tables are attributed to an indexer by the `{id}_` prefix indexers name them with, the
+    /// rest are listed under `tables`.
+    pub fn status_json(&self, w: impl std::io::Write) -> Result<()> {
+        let network = self.network()?;
+        let tip = match self.tip()? {
+            Point::Origin => None,
+            Point::Specific(slot, hash) => Some(TipStatus {
+                slot,
+                hash: hex::encode(hash),
+                number: self.block_by_slot(slot.into())?.map(|block| block.number),
+            }),
+        };
+
+        let rtxn = self.env.read_txn()?;
+        let mut indexers = self
+            .indexer_ids
+            .iter(&rtxn)?
+            .map(|res| {
+                Ok(IndexerStatus {
+                    id: res?.0.to_string(),
+                    tables: BTreeMap::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut tables = BTreeMap::new();
+        for (name, entries) in self.env.table_sizes(&rtxn)? {
+            let owner = indexers
+                .iter_mut()
+                .find(|indexer| name.starts_with(&format!("{}_", indexer.id)));
+            match owner {
+                Some(indexer) => indexer.tables.insert(name, entries),
+                None => tables.insert(name, entries),
+            };
+        }
+        drop(rtxn);
+
+        let info = self.env.info();
+        let status = Status {
+            network,
+            tip,
+            indexers,
+            tables,
+            env: EnvStatus {
+                map_size: info.map_size,
+                used: self.env.used_size(),
+                readers: info.number_of_readers,
+            },
+        };
+        Ok(serde_json::to_writer(w, &status)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::indexer::{Indexer, IndexerList};
+    use crate::test_utils::{block, hash, output, temp_db, tx, utxo_indexer};
+    use crate::{AssetHistoryIndexer, Network};
+
+    #[test]
+    fn test_status_json() {
+        let (_dir, db) = temp_db();
+        let (_, utxo) = utxo_indexer(&db);
+        let history = AssetHistoryIndexer::new("history", &db.env, vec![]).unwrap();
+        let mut indexers: IndexerList =
+            vec![Arc::new(Mutex::new(history)) as Arc<Mutex<dyn Indexer + Send>>];
+        indexers.extend(utxo);
+        db.assert_network(Network::Preview).unwrap();
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(2, 20, vec![]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let mut json = vec![];
+        db.status_json(&mut json).unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(status["network"], "preview");
+        assert_eq!(status["tip"]["slot"], 20);
+        assert_eq!(status["tip"]["hash"], hash::<32>(2).to_string());
+        assert_eq!(status["tip"]["number"], 2);
+        assert_eq!(status["indexers"][0]["id"], "history");
+        assert_eq!(status["indexers"][0]["tables"]["history_asset_history"], 0);
+        assert_eq!(status["indexers"][1]["id"], "utxo");
+        assert_eq!(status["tables"]["utxos"], 1);
+        assert_eq!(status["tables"]["slots"], 2);
+        assert!(status["env"]["used"].as_u64().unwrap() > 0);
+        assert!(
+            status["env"]["map_size"].as_u64().unwrap() >= status["env"]["used"].as_u64().unwrap()
+        );
+    }
+}
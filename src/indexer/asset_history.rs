@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+
+use anyhow::Result;
+use heed::byteorder::BigEndian;
+use heed::types::{Bytes, I128};
+use heed::{Database, RwTxn};
+
+use crate::db::{Db, Env};
+use crate::indexer::{Indexer, set_fingerprint};
+use crate::primitives::{AssetId, Block, Slot, VolatileBlock};
+
+/// Records the net change in the total quantity of the configured assets at each block, i.e.
+/// the quantity minted minus the quantity burned. An asset without a name tracks every asset of
+/// the policy combined. Summing the changes up to a slot gives the supply as of that slot, see
+/// [`AssetHistoryIndexer::curve`].
+#[derive(Clone)]
+pub struct AssetHistoryIndexer {
+    id: String,
+    env: Env,
+    assets: Vec<AssetId>,
+    /// Asset + slot -> net change. Slots are big endian so each asset's changes are ordered.
+    changes: Database<Bytes, I128<BigEndian>>,
+}
+
+impl AssetHistoryIndexer {
+    pub fn new(id: &str, env: &Env, assets: Vec<AssetId>) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let changes = env.create_database(&mut wtxn, &format!("{id}_asset_history"))?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            assets,
+            changes,
+        })
+    }
+
+    /// Net changes in the asset's quantity within the slots, ordered by slot. Slots where the
+    /// quantity didn't change are omitted.
+    pub fn changes(
+        &self,
+        asset: &AssetId,
+        slots: impl RangeBounds<Slot>,
+    ) -> Result<Vec<(Slot, i128)>> {
+        let txn = self.env.read_txn()?;
+        let start = match slots.start_bound() {
+            Bound::Included(slot) => Bound::Included(change_key(asset, slot.0)),
+            Bound::Excluded(slot) => Bound::Excluded(change_key(asset, slot.0)),
+            Bound::Unbounded => Bound::Included(change_key(asset, 0)),
+        };
+        let end = match slots.end_bound() {
+            Bound::Included(slot) => Bound::Included(change_key(asset, slot.0)),
+            Bound::Excluded(slot) => Bound::Excluded(change_key(asset, slot.0)),
+            Bound::Unbounded => Bound::Included(change_key(asset, u64::MAX)),
+        };
+        let range = (
+            start.as_ref().map(Vec::as_slice),
+            end.as_ref().map(Vec::as_slice),
+        );
+        self.changes
+            .range(&txn, &range)?
+            .map(|res| {
+                let (key, change) = res?;
+                Ok((Slot(key_slot(key)), change))
+            })
+            .collect()
+    }
+
+    /// Total quantity of the asset after each change within the slots, for charting its supply
+    /// over time. Changes before the range are folded into the starting total.
+    pub fn curve(
+        &self,
+        asset: &AssetId,
+        slots: impl RangeBounds<Slot>,
+    ) -> Result<Vec<(Slot, i128)>> {
+        let before = match slots.start_bound() {
+            Bound::Included(slot) => (Bound::Unbounded, Bound::Excluded(*slot)),
+            Bound::Excluded(slot) => (Bound::Unbounded, Bound::Included(*slot)),
+            Bound::Unbounded => (Bound::Unbounded, Bound::Excluded(Slot(0))),
+        };
+        let mut total = self
+            .changes(asset, before)?
+            .into_iter()
+            .map(|(_, change)| change)
+            .sum::<i128>();
+        Ok(self
+            .changes(asset, slots)?
+            .into_iter()
+            .map(|(slot, change)| {
+                total += change;
+                (slot, total)
+            })
+            .collect())
+    }
+}
+
+/// Asset key followed by the slot, see [`asset_key`]
+fn change_key(asset: &AssetId, slot: u64) -> Vec<u8> {
+    let mut key = asset_key(asset);
+    key.extend_from_slice(&slot.to_be_bytes());
+    key
+}
+
+/// Policy, then the name prefixed by its length (or `0xff` for the whole policy, since names
+/// are at most 32 bytes)
+fn asset_key(asset: &AssetId) -> Vec<u8> {
+    let mut key = asset.policy.to_vec();
+    match &asset.name {
+        Some(name) => {
+            key.push(name.len() as u8);
+            key.extend_from_slice(name);
+        }
+        None => key.push(0xff),
+    }
+    key
+}
+
+fn key_slot(key: &[u8]) -> u64 {
+    let slot = &key[key.len() - 8..];
+    u64::from_be_bytes(slot.try_into().expect("slot is 8 bytes"))
+}
+
+impl Indexer for AssetHistoryIndexer {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn config_fingerprint(&self) -> Vec<u8> {
+        set_fingerprint(self.assets.iter().map(asset_key))
+    }
+
+    fn insert_block(&self, _: &Db, wtxn: &mut RwTxn, block: &Block) -> Result<()> {
+        let mut changes = HashMap::<usize, i128>::new();
+        // Mints of phase-2 invalid txs don't apply
+        let mints = block
+            .txs
+            .iter()
+            .filter(|tx| tx.valid)
+            .flat_map(|tx| tx.mints.iter());
+        for mint in mints {
+            for (i, asset) in self.assets.iter().enumerate() {
+                let matches = asset.policy == mint.policy
+                    && asset.name.as_ref().is_none_or(|name| name == &mint.name);
+                if matches {
+                    *changes.entry(i).or_default() += mint.quantity as i128;
+                }
+            }
+        }
+        for (i, change) in changes {
+            if change != 0 {
+                let key = change_key(&self.assets[i], block.slot);
+                self.changes.put(wtxn, &key, &change)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_block(&self, _: &Db, wtxn: &mut RwTxn, block: &VolatileBlock) -> Result<()> {
+        for asset in self.assets.iter() {
+            self.changes.delete(wtxn, &change_key(asset, block.slot))?;
+        }
+        Ok(())
+    }
+
+    fn wants_datums(&self) -> bool {
+        false
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.changes.clear(wtxn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::Mint;
+    use crate::test_utils::{hash, roll_forward_and_back, temp_db, tx};
+
+    fn mint(name: &[u8], quantity: i64) -> Mint {
+        Mint {
+            policy: hash(1),
+            name: name.to_vec(),
+            quantity,
+        }
+    }
+
+    #[test]
+    fn test_asset_history() {
+        let (_dir, db) = temp_db();
+        let token = AssetId::new(hash(1), Some(b"token".to_vec()));
+        let policy = AssetId::new(hash(1), None);
+
+        let mut minting = tx(1, vec![], vec![]);
+        minting.mints = vec![mint(b"token", 100), mint(b"other", 5)];
+        let mut failed = tx(2, vec![], vec![]);
+        failed.mints = vec![mint(b"token", 1000)];
+        failed.valid = false;
+        let mut burning = tx(3, vec![], vec![]);
+        burning.mints = vec![mint(b"token", -30)];
+
+        let indexer =
+            AssetHistoryIndexer::new("history", &db.env, vec![token.clone(), policy.clone()])
+                .unwrap();
+        let txs = [vec![minting], vec![failed, burning]];
+        let (indexer, _) = roll_forward_and_back(&db, indexer, txs, |history| {
+            assert_eq!(
+                history.changes(&token, ..).unwrap(),
+                [(Slot(10), 100), (Slot(20), -30)]
+            );
+            assert_eq!(
+                history.curve(&token, ..).unwrap(),
+                [(Slot(10), 100), (Slot(20), 70)]
+            );
+            assert_eq!(history.curve(&token, Slot(11)..).unwrap(), [(Slot(20), 70)]);
+            assert_eq!(
+                history.curve(&policy, ..=Slot(10)).unwrap(),
+                [(Slot(10), 105)]
+            );
+        });
+
+        let history = indexer.lock().unwrap();
+        assert_eq!(history.curve(&token, ..).unwrap(), [(Slot(10), 100)]);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use heed::byteorder::BigEndian;
+use heed::types::U64;
+use heed::{Database, DatabaseFlags, RwTxn};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::db::{Db, Env, RkyvCodec};
+use crate::indexer::Indexer;
+use crate::primitives::{Block, Slot, TxHash, TxOutput, TxOutputPointer, VolatileBlock};
+
+/// Collateral effects of a transaction that failed phase-2 validation
+#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+#[rkyv(compare(PartialEq))]
+pub struct FailedTx {
+    pub slot: u64,
+    pub collateral_spent: Vec<TxOutputPointer>,
+    pub collateral_return: Option<TxOutput>,
+}
+
+/// Records every phase-2 invalid transaction, for analytics on failed smart-contract txs
+#[derive(Clone)]
+pub struct FailedTxIndexer {
+    id: String,
+    env: Env,
+    failed_txs: Database<RkyvCodec<TxHash>, RkyvCodec<FailedTx>>,
+    // big endian ints are lexicographically ordered
+    by_slot: Database<U64<BigEndian>, RkyvCodec<TxHash>>,
+}
+
+impl FailedTxIndexer {
+    pub fn new(id: &str, env: &Env) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let failed_txs = env.create_database(&mut wtxn, &format!("{id}_txs"))?;
+        let by_slot = env.create_database_with_flags(
+            &mut wtxn,
+            &format!("{id}_by_slot"),
+            DatabaseFlags::DUP_SORT,
+        )?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            failed_txs,
+            by_slot,
+        })
+    }
+
+    /// Failed txs included at or after the slot, ordered by slot
+    pub fn failed_txs_since(&self, slot: Slot) -> Result<Vec<(TxHash, FailedTx)>> {
+        let txn = self.env.read_txn()?;
+        self.by_slot
+            .range(&txn, &(slot.0..))?
+            .map(|res| {
+                let tx_hash = rkyv::deserialize::<TxHash, rkyv::rancor::Error>(res?.1)?;
+                let failed_tx = self
+                    .failed_txs
+                    .get(&txn, &tx_hash)?
+                    .context("missing failed tx")?;
+                let failed_tx = rkyv::deserialize::<FailedTx, rkyv::rancor::Error>(failed_tx)?;
+                Ok((tx_hash, failed_tx))
+            })
+            .collect::<Result<Vec<_>>>()
+    }
+}
+
+impl Indexer for FailedTxIndexer {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn insert_block(&self, _: &Db, wtxn: &mut RwTxn, block: &Block) -> Result<()> {
+        for tx in block.txs.iter().filter(|tx| !tx.valid) {
+            let failed_tx = FailedTx {
+                slot: block.slot,
+                collateral_spent: tx.collateral.clone(),
+                collateral_return: tx.collateral_return.clone(),
+            };
+            self.failed_txs.put(wtxn, &tx.hash, &failed_tx)?;
+            self.by_slot.put(wtxn, &block.slot, &tx.hash)?;
+        }
+        Ok(())
+    }
+
+    fn delete_block(&self, _: &Db, wtxn: &mut RwTxn, block: &VolatileBlock) -> Result<()> {
+        let tx_hashes = match self.by_slot.get_duplicates(wtxn, &block.slot)? {
+            Some(duplicates) => duplicates
+                .map(|res| Ok(rkyv::deserialize::<TxHash, rkyv::rancor::Error>(res?.1)?))
+                .collect::<Result<Vec<_>>>()?,
+            None => return Ok(()),
+        };
+        for tx_hash in tx_hashes.iter() {
+            self.failed_txs.delete(wtxn, tx_hash)?;
+        }
+        self.by_slot.delete(wtxn, &block.slot)?;
+        Ok(())
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.failed_txs.clear(wtxn)?;
+        self.by_slot.clear(wtxn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{output, pointer, roll_forward_and_back, temp_db, tx};
+
+    #[test]
+    fn test_failed_txs_roll_back() {
+        let (_dir, db) = temp_db();
+        let mut failed = tx(2, vec![pointer(1, 0)], vec![output(b"a", 1)]);
+        failed.valid = false;
+        failed.collateral = vec![pointer(1, 1)];
+        failed.collateral_return = Some(output(b"b", 1));
+        let txs = [vec![tx(1, vec![], vec![output(b"a", 1)])], vec![failed]];
+
+        let indexer = FailedTxIndexer::new("failed", &db.env).unwrap();
+        let (indexer, _) = roll_forward_and_back(&db, indexer, txs, |indexer| {
+            let failed_txs = indexer.failed_txs_since(Slot(0)).unwrap();
+            assert_eq!(failed_txs.len(), 1);
+            assert_eq!(failed_txs[0].1.slot, 20);
+            assert!(indexer.failed_txs_since(Slot(21)).unwrap().is_empty());
+        });
+        let indexer = indexer.lock().unwrap();
+        assert!(indexer.failed_txs_since(Slot(0)).unwrap().is_empty());
+    }
+}
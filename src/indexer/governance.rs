@@ -0,0 +1,214 @@
+use anyhow::Result;
+use heed::byteorder::BigEndian;
+use heed::types::U64;
+use heed::{Database, DatabaseFlags, RwTxn};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::db::{Db, Env, RkyvCodec};
+use crate::indexer::Indexer;
+use crate::primitives::{
+    Credential, DRepCert, GovActionId, Proposal, Tx, TxHash, Vote, VoteRecord, Voter,
+};
+
+/// Vote cast on a governance action
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub struct CastVote {
+    pub voter: Voter,
+    pub vote: Vote,
+    pub tx_hash: TxHash,
+}
+
+/// Indexes Conway governance: registered DReps, proposed governance actions and the votes cast
+/// on them
+#[derive(Clone)]
+pub struct GovernanceIndexer {
+    id: String,
+    env: Env,
+    dreps: Database<RkyvCodec<Credential>, U64<BigEndian>>,
+    proposals: Database<RkyvCodec<GovActionId>, RkyvCodec<Proposal>>,
+    votes: Database<RkyvCodec<GovActionId>, RkyvCodec<CastVote>>,
+}
+
+impl GovernanceIndexer {
+    pub fn new(id: &str, env: &Env) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let dreps = env.create_database(&mut wtxn, &format!("{id}_dreps"))?;
+        let proposals = env.create_database(&mut wtxn, &format!("{id}_proposals"))?;
+        let votes = env.create_database_with_flags(
+            &mut wtxn,
+            &format!("{id}_votes"),
+            DatabaseFlags::DUP_SORT,
+        )?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            dreps,
+            proposals,
+            votes,
+        })
+    }
+
+    /// Deposit of the DRep, if currently registered
+    pub fn drep_deposit(&self, credential: &Credential) -> Result<Option<u64>> {
+        let txn = self.env.read_txn()?;
+        Ok(self.dreps.get(&txn, credential)?)
+    }
+
+    pub fn proposal(&self, action: &GovActionId) -> Result<Option<Proposal>> {
+        let txn = self.env.read_txn()?;
+        self.proposals
+            .get(&txn, action)?
+            .map(|res| Ok(rkyv::deserialize::<Proposal, rkyv::rancor::Error>(res)?))
+            .transpose()
+    }
+
+    /// Every vote cast on the action. A voter that changed its vote appears once per vote.
+    pub fn votes(&self, action: &GovActionId) -> Result<Vec<CastVote>> {
+        let txn = self.env.read_txn()?;
+        let Some(votes) = self.votes.get_duplicates(&txn, action)? else {
+            return Ok(vec![]);
+        };
+        votes
+            .map(|res| Ok(rkyv::deserialize::<CastVote, rkyv::rancor::Error>(res?.1)?))
+            .collect()
+    }
+
+    fn cast_vote(tx: &Tx, record: &VoteRecord) -> CastVote {
+        CastVote {
+            voter: record.voter.clone(),
+            vote: record.vote,
+            tx_hash: tx.hash.clone(),
+        }
+    }
+}
+
+impl Indexer for GovernanceIndexer {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn insert_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> Result<bool> {
+        let governance = &tx.governance;
+        for cert in governance.drep_certs.iter() {
+            match cert {
+                DRepCert::Registration {
+                    credential,
+                    deposit,
+                } => self.dreps.put(wtxn, credential, deposit)?,
+                DRepCert::Deregistration { credential, .. } => {
+                    self.dreps.delete(wtxn, credential)?;
+                }
+                DRepCert::Update { .. } => {}
+            }
+        }
+        for proposal in governance.proposals.iter() {
+            self.proposals.put(wtxn, &proposal.action, proposal)?;
+        }
+        for record in governance.votes.iter() {
+            self.votes
+                .put(wtxn, &record.action, &Self::cast_vote(tx, record))?;
+        }
+        Ok(!governance.is_empty())
+    }
+
+    fn delete_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> Result<()> {
+        let governance = &tx.governance;
+        for record in governance.votes.iter() {
+            self.votes
+                .delete_one_duplicate(wtxn, &record.action, &Self::cast_vote(tx, record))?;
+        }
+        for proposal in governance.proposals.iter() {
+            self.proposals.delete(wtxn, &proposal.action)?;
+        }
+        // NOTE: reverse order because a tx may deregister and re-register the same DRep
+        for cert in governance.drep_certs.iter().rev() {
+            match cert {
+                DRepCert::Registration { credential, .. } => {
+                    self.dreps.delete(wtxn, credential)?;
+                }
+                DRepCert::Deregistration { credential, refund } => {
+                    self.dreps.put(wtxn, credential, refund)?
+                }
+                DRepCert::Update { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.dreps.clear(wtxn)?;
+        self.proposals.clear(wtxn)?;
+        self.votes.clear(wtxn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+    use crate::primitives::GovActionKind;
+    use crate::test_utils::{hash, roll_forward_and_back, temp_db, tx};
+
+    #[test]
+    fn test_governance_roll_back() {
+        let (_dir, db) = temp_db();
+        let drep = Credential::Key(hash(1));
+        let action = GovActionId {
+            tx_hash: hash(1),
+            index: 0,
+        };
+
+        let mut propose = tx(1, vec![], vec![]);
+        propose.governance.drep_certs = vec![DRepCert::Registration {
+            credential: drep.clone(),
+            deposit: 500,
+        }];
+        propose.governance.proposals = vec![Proposal {
+            action: action.clone(),
+            kind: GovActionKind::Information,
+            deposit: 100,
+            reward_account: vec![0xe1],
+        }];
+        let mut vote = tx(2, vec![], vec![]);
+        vote.governance.votes = vec![VoteRecord {
+            voter: Voter::DRep(drep.clone()),
+            action: action.clone(),
+            vote: Vote::Yes,
+        }];
+        vote.governance.drep_certs = vec![DRepCert::Deregistration {
+            credential: drep.clone(),
+            refund: 500,
+        }];
+
+        let indexer = GovernanceIndexer::new("gov", &db.env).unwrap();
+        let txs = [vec![propose], vec![vote]];
+        let (indexer, indexers) = roll_forward_and_back(&db, indexer, txs, |gov| {
+            assert_eq!(gov.drep_deposit(&drep).unwrap(), None);
+            assert_eq!(gov.proposal(&action).unwrap().unwrap().deposit, 100);
+            let votes = gov.votes(&action).unwrap();
+            assert_eq!(votes.len(), 1);
+            assert_eq!(votes[0].vote, Vote::Yes);
+        });
+
+        // Undoes the vote and deregistration
+        let gov = indexer.lock().unwrap().clone();
+        assert_eq!(gov.drep_deposit(&drep).unwrap(), Some(500));
+        assert!(gov.votes(&action).unwrap().is_empty());
+        assert!(gov.proposal(&action).unwrap().is_some());
+
+        db.roll_backward(&indexers, &Point::Origin).unwrap();
+        assert_eq!(gov.drep_deposit(&drep).unwrap(), None);
+        assert!(gov.proposal(&action).unwrap().is_none());
+    }
+}
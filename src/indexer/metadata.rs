@@ -0,0 +1,181 @@
+use anyhow::Result;
+use heed::types::Bytes;
+use heed::{Database, RwTxn};
+
+use crate::db::{Db, Env};
+use crate::indexer::{Indexer, set_fingerprint};
+use crate::primitives::{Tx, TxHash};
+
+/// Stores the auxiliary data metadata of txs, e.g. CIP-25 NFT metadata under label 721, as the
+/// CBOR of each label's metadatum. Limited to a set of labels unless indexing all of them.
+/// Metadata of txs that failed phase-2 validation is kept too, since it's part of the tx
+/// regardless.
+#[derive(Clone)]
+pub struct MetadataIndexer {
+    id: String,
+    env: Env,
+    /// `None` indexes every label
+    labels: Option<Vec<u64>>,
+    /// Tx hash + label -> CBOR of the metadatum
+    metadata: Database<Bytes, Bytes>,
+}
+
+impl MetadataIndexer {
+    pub fn new(id: &str, env: &Env, labels: Option<Vec<u64>>) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let metadata = env.create_database(&mut wtxn, &format!("{id}_metadata"))?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            labels,
+            metadata,
+        })
+    }
+
+    /// Labels of the tx's metadata and the CBOR of their metadatum, ordered by label. Empty if
+    /// the tx has none or isn't indexed.
+    pub fn metadata(&self, tx_hash: &TxHash) -> Result<Vec<(u64, Vec<u8>)>> {
+        let txn = self.env.read_txn()?;
+        self.metadata
+            .prefix_iter(&txn, tx_hash.as_slice())?
+            .map(|res| {
+                let (key, cbor) = res?;
+                let label = u64::from_be_bytes(key[tx_hash.len()..].try_into()?);
+                Ok((label, cbor.to_vec()))
+            })
+            .collect()
+    }
+
+    fn tracked<'a>(&'a self, tx: &'a Tx) -> impl Iterator<Item = &'a (u64, Vec<u8>)> + 'a {
+        tx.metadata.iter().filter(|(label, _)| match &self.labels {
+            Some(labels) => labels.contains(label),
+            None => true,
+        })
+    }
+}
+
+/// Tx hash, then the label
+fn metadata_key(tx_hash: &TxHash, label: u64) -> Vec<u8> {
+    [tx_hash.as_slice(), &label.to_be_bytes()].concat()
+}
+
+impl Indexer for MetadataIndexer {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Empty when indexing every label
+    fn config_fingerprint(&self) -> Vec<u8> {
+        match &self.labels {
+            Some(labels) => {
+                set_fingerprint(labels.iter().map(|label| label.to_be_bytes().to_vec()))
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn insert_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> Result<bool> {
+        let mut added_some = false;
+        for (label, cbor) in self.tracked(tx) {
+            self.metadata
+                .put(wtxn, &metadata_key(&tx.hash, *label), cbor)?;
+            added_some = true;
+        }
+        Ok(added_some)
+    }
+
+    fn delete_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> Result<()> {
+        for (label, _) in self.tracked(tx) {
+            self.metadata
+                .delete(wtxn, &metadata_key(&tx.hash, *label))?;
+        }
+        Ok(())
+    }
+
+    fn wants_datums(&self) -> bool {
+        false
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn wants_metadata(&self) -> bool {
+        true
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.metadata.clear(wtxn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::indexer::IndexerList;
+    use crate::primitives::Mint;
+    use crate::test_utils::{block, hash, output, roll_forward_and_back, temp_db, tx};
+
+    #[test]
+    fn test_nft_metadata() {
+        let (_dir, db) = temp_db();
+        // {"nft": {"name": "Nft"}}
+        let payload = hex::decode("a1636e6674a1646e616d65634e6674").unwrap();
+        let mut mint = tx(1, vec![], vec![output(b"a", 2)]);
+        mint.mints = vec![Mint {
+            policy: hash(7),
+            name: b"nft".to_vec(),
+            quantity: 1,
+        }];
+        mint.metadata = vec![(674, vec![0xf6]), (721, payload.clone())];
+
+        let indexer = MetadataIndexer::new("metadata", &db.env, Some(vec![721])).unwrap();
+        let txs = [vec![tx(2, vec![], vec![output(b"b", 1)])], vec![mint]];
+        let (indexer, _) = roll_forward_and_back(&db, indexer, txs, |metadata| {
+            // Only the tracked label
+            assert_eq!(metadata.metadata(&hash(1)).unwrap(), [(721, payload)]);
+            assert!(metadata.metadata(&hash(2)).unwrap().is_empty());
+        });
+
+        let metadata = indexer.lock().unwrap();
+        assert!(metadata.metadata(&hash(1)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_labels_changed() {
+        let (dir, db) = temp_db();
+        let open = |db: &Db, labels: Option<Vec<u64>>| -> IndexerList {
+            let indexer = MetadataIndexer::new("metadata", &db.env, labels).unwrap();
+            vec![Arc::new(Mutex::new(indexer)) as Arc<Mutex<dyn Indexer + Send>>]
+        };
+        let indexers = open(&db, Some(vec![721, 674]));
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        drop((db, indexers));
+
+        // The same labels in a different order
+        let db = Db::new(dir.path().to_str().unwrap(), 10).unwrap();
+        let indexers = open(&db, Some(vec![674, 721, 674]));
+        db.roll_forward(&indexers, &[block(2, 20, vec![])]).unwrap();
+        drop((db, indexers));
+
+        // Different labels, or all of them
+        for labels in [Some(vec![721]), None] {
+            let db = Db::new(dir.path().to_str().unwrap(), 10).unwrap();
+            let indexers = open(&db, labels);
+            let err = db
+                .roll_forward(&indexers, &[block(3, 30, vec![])])
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<crate::db::Error>(),
+                Some(crate::db::Error::IndexerConfigChanged { id }) if id == "metadata"
+            ));
+        }
+    }
+}
@@ -3,17 +3,58 @@ use std::sync::{Arc, Mutex};
 use anyhow::Result;
 
 use crate::db::Db;
-use crate::primitives::{Datum, DatumHash, Script, ScriptHash, Tx};
+use crate::network::Network;
+use crate::primitives::{
+    Block, BlockHash, Credential, Datum, DatumHash, ParseOptions, Script, ScriptHash, Slot, Tx,
+    VolatileBlock,
+};
 
+pub mod asset_history;
+pub mod failed_tx;
+pub mod governance;
+pub mod metadata;
+pub mod reward_account;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod stake_distribution;
 pub mod utxo;
 
 pub trait Indexer {
     fn id(&self) -> &str;
 
+    /// Identifies the configuration the indexer's tables are built under, such as its filters.
+    /// Stored alongside the id when the indexer is registered and compared on every later use,
+    /// failing with [`Error::IndexerConfigChanged`](crate::db::Error::IndexerConfigChanged) if
+    /// it changed. Empty by default, for indexers whose tables don't depend on configuration.
+    fn config_fingerprint(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Version of the indexer's storage layout, to bump whenever its tables change, e.g. a new
+    /// secondary index. Stored when the indexer is registered and compared on every later use:
+    /// an older stored version is passed to [`Indexer::migrate_schema`], failing with
+    /// [`Error::IndexerSchemaChanged`](crate::db::Error::IndexerSchemaChanged) if the indexer
+    /// can't migrate it. 0 by default.
+    fn schema_version(&self) -> u32 {
+        0
+    }
+
+    /// Upgrades tables built with the older `from` schema version to the current one, e.g. by
+    /// filling a new secondary index from the existing tables, returning `false` if there's no
+    /// migration from that version. Runs in its own write txn before the next block is applied.
+    #[allow(unused_variables)]
+    fn migrate_schema(&self, db: &Db, wtxn: &mut heed::RwTxn, from: u32) -> Result<bool> {
+        Ok(false)
+    }
+
     #[allow(unused_variables)]
     fn insert_tx(&self, db: &Db, wtxn: &mut heed::RwTxn, tx: &Tx) -> Result<bool> {
         Ok(false)
     }
+    /// Undoes `insert_tx` when rolling back. Called on every indexer for each tx that at least
+    /// one indexer inserted, so it must be a no-op for txs this indexer returned `false` for:
+    /// undo only what the tx itself added rather than, say, resetting state keyed by the tx's
+    /// address or asset.
     #[allow(unused_variables)]
     fn delete_tx(&self, db: &Db, wtxn: &mut heed::RwTxn, tx: &Tx) -> Result<()> {
         Ok(())
@@ -49,7 +90,109 @@ pub trait Indexer {
         Ok(())
     }
 
+    /// Called once all txs and datums of the block have been passed to the indexer
+    #[allow(unused_variables)]
+    fn insert_block(&self, db: &Db, wtxn: &mut heed::RwTxn, block: &Block) -> Result<()> {
+        Ok(())
+    }
+    /// Called when rolling back the block, after its txs and datums have been deleted
+    #[allow(unused_variables)]
+    fn delete_block(&self, db: &Db, wtxn: &mut heed::RwTxn, block: &VolatileBlock) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after each write transaction the indexer took part in has been committed, for
+    /// propagating durable changes outside of LMDB
+    fn on_commit(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once a rollback has been committed with the blocks it reverted for this indexer,
+    /// newest first, e.g. to invalidate exactly the cache entries derived from them. Each block
+    /// is committed on its own, so if undoing one fails, this is still called with the blocks
+    /// reverted before it. Not called when rolling back to the origin, which clears the indexer
+    /// instead.
+    #[allow(unused_variables)]
+    fn on_rollback(&self, reverted: &[(Slot, BlockHash)]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the indexer reads datums, see [`ParseOptions::datums`]
+    fn wants_datums(&self) -> bool {
+        true
+    }
+
+    /// Whether the indexer reads scripts, see [`ParseOptions::scripts`]
+    fn wants_scripts(&self) -> bool {
+        true
+    }
+
+    /// Whether the indexer reads tx metadata, see [`ParseOptions::metadata`]. Unlike datums and
+    /// scripts it's opt-in, since hardly any indexer needs it.
+    fn wants_metadata(&self) -> bool {
+        false
+    }
+
     fn clear(&self, wtxn: &mut heed::RwTxn) -> Result<()>;
 }
 
+/// Guardrails for queries that materialize every matching result at once, such that a huge
+/// result errors with [`Error::ResultTooLarge`](crate::db::Error::ResultTooLarge) instead of
+/// exhausting memory. Unlimited by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryLimits {
+    pub max_results: Option<usize>,
+}
+
+impl QueryLimits {
+    pub fn max_results(max_results: usize) -> Self {
+        Self {
+            max_results: Some(max_results),
+        }
+    }
+
+    pub(crate) fn check(&self, found: usize) -> Result<()> {
+        match self.max_results {
+            Some(limit) if found > limit => {
+                Err(crate::db::Error::ResultTooLarge { found, limit }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 pub(crate) type IndexerList = Vec<Arc<Mutex<dyn Indexer + Send + 'static>>>;
+
+/// [`Indexer::config_fingerprint`] of an indexer configured with a set of items, e.g. the assets
+/// it tracks. Sorted and deduplicated first, so the same set listed differently matches, and
+/// length-prefixed so the items can't run into one another.
+pub(crate) fn set_fingerprint(items: impl IntoIterator<Item = Vec<u8>>) -> Vec<u8> {
+    let mut items = items.into_iter().collect::<Vec<_>>();
+    items.sort_unstable();
+    items.dedup();
+    let mut bytes = Vec::new();
+    for item in items {
+        bytes.extend_from_slice(&(item.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&item);
+    }
+    pallas::crypto::hash::Hasher::<256>::hash(&bytes).to_vec()
+}
+
+/// Fingerprint item of a credential, whose reward account on any fixed network tells key and
+/// script hashes apart
+pub(crate) fn credential_item(credential: &Credential) -> Vec<u8> {
+    credential.to_reward_account(Network::Mainnet)
+}
+
+/// Decodes only what at least one of the indexers wants
+pub(crate) fn parse_options(indexers: &IndexerList) -> ParseOptions {
+    let indexers = indexers
+        .iter()
+        .map(|i| i.lock().expect("indexer mutex poisoned"))
+        .collect::<Vec<_>>();
+    ParseOptions {
+        datums: indexers.iter().any(|i| i.wants_datums()),
+        scripts: indexers.iter().any(|i| i.wants_scripts()),
+        metadata: indexers.iter().any(|i| i.wants_metadata()),
+    }
+}
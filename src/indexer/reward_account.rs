@@ -0,0 +1,246 @@
+use std::ops::{Bound, RangeBounds};
+
+use anyhow::Result;
+use heed::types::Bytes;
+use heed::{Database, DatabaseFlags, RwTxn};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::db::{Db, Env, RkyvCodec};
+use crate::indexer::{Indexer, credential_item, set_fingerprint};
+use crate::primitives::{
+    Block, Credential, Slot, TxHash, TxOutputPointer, VolatileBlock, address_stake_credential,
+    reward_account_to_credential,
+};
+
+/// Movement of funds associated with a stake credential
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub enum StakeEvent {
+    /// Output paid to an address delegated with the credential
+    Output {
+        pointer: TxOutputPointer,
+        lovelace: u64,
+    },
+    /// Rewards withdrawn from the credential's reward account
+    Withdrawal { tx_hash: TxHash, amount: u64 },
+}
+
+/// Tracks the funds moving to and from a set of stake credentials, e.g. a pool operator's
+/// reward account: outputs paid to base addresses staked with one of the credentials, and
+/// withdrawals from their reward accounts. Spending those outputs isn't tracked, index them with
+/// a [`UtxoIndexer`](crate::UtxoIndexer) for that.
+#[derive(Clone)]
+pub struct RewardAccountIndexer {
+    id: String,
+    env: Env,
+    credentials: Vec<Credential>,
+    /// Credential + slot -> events of the block
+    events: Database<Bytes, RkyvCodec<StakeEvent>>,
+}
+
+impl RewardAccountIndexer {
+    pub fn new(id: &str, env: &Env, credentials: Vec<Credential>) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let events = env.create_database_with_flags(
+            &mut wtxn,
+            &format!("{id}_reward_account_events"),
+            DatabaseFlags::DUP_SORT,
+        )?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            credentials,
+            events,
+        })
+    }
+
+    /// Events of the credential within the slots, ordered by slot
+    pub fn events(
+        &self,
+        credential: &Credential,
+        slots: impl RangeBounds<Slot>,
+    ) -> Result<Vec<(Slot, StakeEvent)>> {
+        let txn = self.env.read_txn()?;
+        let start = match slots.start_bound() {
+            Bound::Included(slot) => Bound::Included(event_key(credential, slot.0)),
+            Bound::Excluded(slot) => Bound::Excluded(event_key(credential, slot.0)),
+            Bound::Unbounded => Bound::Included(event_key(credential, 0)),
+        };
+        let end = match slots.end_bound() {
+            Bound::Included(slot) => Bound::Included(event_key(credential, slot.0)),
+            Bound::Excluded(slot) => Bound::Excluded(event_key(credential, slot.0)),
+            Bound::Unbounded => Bound::Included(event_key(credential, u64::MAX)),
+        };
+        let range = (
+            start.as_ref().map(Vec::as_slice),
+            end.as_ref().map(Vec::as_slice),
+        );
+        self.events
+            .range(&txn, &range)?
+            .map(|res| {
+                let (key, event) = res?;
+                let slot = u64::from_be_bytes(key[key.len() - 8..].try_into()?);
+                let event = rkyv::deserialize::<StakeEvent, rkyv::rancor::Error>(event)?;
+                Ok((Slot(slot), event))
+            })
+            .collect()
+    }
+
+    fn tracked(&self, credential: Option<Credential>) -> Option<Credential> {
+        credential.filter(|credential| self.credentials.contains(credential))
+    }
+}
+
+/// Credential kind and hash, then the slot
+fn event_key(credential: &Credential, slot: u64) -> Vec<u8> {
+    let (kind, hash) = match credential {
+        Credential::Key(hash) => (0, hash),
+        Credential::Script(hash) => (1, hash),
+    };
+    let mut key = vec![kind];
+    key.extend_from_slice(hash.as_slice());
+    key.extend_from_slice(&slot.to_be_bytes());
+    key
+}
+
+impl Indexer for RewardAccountIndexer {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn config_fingerprint(&self) -> Vec<u8> {
+        set_fingerprint(self.credentials.iter().map(credential_item))
+    }
+
+    fn insert_block(&self, _: &Db, wtxn: &mut RwTxn, block: &Block) -> Result<()> {
+        // Neither the outputs nor the withdrawals of phase-2 invalid txs apply
+        for tx in block.txs.iter().filter(|tx| tx.valid) {
+            for (index, output) in tx.outputs.iter().enumerate() {
+                if let Some(credential) = self.tracked(address_stake_credential(&output.address)) {
+                    let event = StakeEvent::Output {
+                        pointer: TxOutputPointer::new(tx.hash.clone(), index),
+                        lovelace: output.lovelace,
+                    };
+                    self.events
+                        .put(wtxn, &event_key(&credential, block.slot), &event)?;
+                }
+            }
+            for (account, amount) in tx.withdrawals.iter() {
+                if let Some(credential) = self.tracked(reward_account_to_credential(account).ok()) {
+                    let event = StakeEvent::Withdrawal {
+                        tx_hash: tx.hash.clone(),
+                        amount: *amount,
+                    };
+                    self.events
+                        .put(wtxn, &event_key(&credential, block.slot), &event)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_block(&self, _: &Db, wtxn: &mut RwTxn, block: &VolatileBlock) -> Result<()> {
+        for credential in self.credentials.iter() {
+            self.events
+                .delete(wtxn, &event_key(credential, block.slot))?;
+        }
+        Ok(())
+    }
+
+    fn wants_datums(&self) -> bool {
+        false
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.events.clear(wtxn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+    use crate::Network;
+    use crate::test_utils::{hash, output, roll_forward_and_back, temp_db, tx};
+
+    #[test]
+    fn test_reward_account_roll_back() {
+        let (_dir, db) = temp_db();
+        let pool = Credential::Key(hash(1));
+
+        // Base address paying to some key, staked with the pool's credential
+        let staked = [
+            &[0x01][..],
+            hash::<28>(2).as_slice(),
+            hash::<28>(1).as_slice(),
+        ]
+        .concat();
+        let other = [
+            &[0x01][..],
+            hash::<28>(2).as_slice(),
+            hash::<28>(3).as_slice(),
+        ]
+        .concat();
+        // The same credential withdraws its rewards and receives an output
+        let mut withdraw = tx(1, vec![], vec![output(&staked, 5), output(&other, 1)]);
+        withdraw.withdrawals = vec![(pool.to_reward_account(Network::Mainnet), 10)];
+        let mut failed = tx(2, vec![], vec![output(&staked, 7)]);
+        failed.withdrawals = withdraw.withdrawals.clone();
+        failed.valid = false;
+
+        let indexer = RewardAccountIndexer::new("rewards", &db.env, vec![pool.clone()]).unwrap();
+        let txs = [
+            vec![withdraw],
+            vec![failed, tx(3, vec![], vec![output(&staked, 3)])],
+        ];
+        let (indexer, indexers) = roll_forward_and_back(&db, indexer, txs, |rewards| {
+            let events = rewards.events(&pool, ..).unwrap();
+            assert_eq!(events.len(), 3);
+            assert!(events.contains(&(
+                Slot(10),
+                StakeEvent::Withdrawal {
+                    tx_hash: hash(1),
+                    amount: 10
+                }
+            )));
+            assert!(events.contains(&(
+                Slot(10),
+                StakeEvent::Output {
+                    pointer: TxOutputPointer::new(hash(1), 0),
+                    lovelace: 5
+                }
+            )));
+            assert_eq!(
+                rewards.events(&pool, Slot(11)..).unwrap(),
+                [(
+                    Slot(20),
+                    StakeEvent::Output {
+                        pointer: TxOutputPointer::new(hash(3), 0),
+                        lovelace: 3
+                    }
+                )]
+            );
+            assert!(
+                rewards
+                    .events(&Credential::Key(hash(3)), ..)
+                    .unwrap()
+                    .is_empty()
+            );
+        });
+
+        let rewards = indexer.lock().unwrap().clone();
+        assert_eq!(rewards.events(&pool, ..).unwrap().len(), 2);
+        db.roll_backward(&indexers, &Point::Origin).unwrap();
+        assert!(rewards.events(&pool, ..).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use heed::byteorder::BigEndian;
+use heed::types::{Str, U64};
+use heed::{Database, RwTxn};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::db::{Db, Env, RkyvCodec, from_unaligned_bytes};
+use crate::indexer::Indexer;
+use crate::primitives::{Address, Block, Tx, TxHash, TxOutput, TxOutputPointer, VolatileBlock};
+
+/// Row of the UTxO table mirrored by [`UtxoSqlIndexer`], e.g.
+///
+/// ```sql
+/// CREATE TABLE utxos (
+///     tx_hash BYTEA NOT NULL,
+///     "index" BIGINT NOT NULL,
+///     address BYTEA NOT NULL,
+///     lovelace BIGINT NOT NULL,
+///     PRIMARY KEY (tx_hash, "index")
+/// );
+/// ```
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UtxoRow {
+    pub tx_hash: TxHash,
+    pub index: u64,
+    pub address: Address,
+    pub lovelace: u64,
+}
+
+impl UtxoRow {
+    fn new(pointer: &TxOutputPointer, output: &TxOutput) -> Self {
+        Self {
+            tx_hash: pointer.hash.clone(),
+            index: pointer.index,
+            address: output.address.clone(),
+            lovelace: output.lovelace,
+        }
+    }
+}
+
+/// Row-level change to the UTxO table
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+pub enum UtxoChange {
+    Insert(UtxoRow),
+    /// Delete the row with the `(tx_hash, index)` primary key
+    Delete(TxOutputPointer),
+    /// Delete every row, after rolling back to the origin
+    DeleteAll,
+}
+
+/// Destination of the changes produced by an indexer, typically an external SQL database.
+///
+/// Delivery is at-least-once: changes are staged in LMDB alongside the indexed block and only
+/// dropped once `apply` returns `Ok`, so a crash or error before then replays them on the next
+/// commit. `apply` must therefore be idempotent, e.g. by using `INSERT .. ON CONFLICT DO
+/// NOTHING` and deletes that ignore missing rows, and should apply the batch in a single SQL
+/// transaction.
+pub trait SqlSink<C>: Send {
+    /// Applies the changes, in order
+    fn apply(&mut self, changes: &[C]) -> Result<()>;
+}
+
+/// Mirrors the UTxO set into an [`SqlSink`]. Changes are accumulated in an outbox table within
+/// the sync write transaction and flushed to the sink after it commits, with rollbacks emitted
+/// as the inverse changes.
+///
+/// Keeps a copy of the rows, so the rows a block spent can be re-inserted on rollback even once
+/// the txs that created them left the volatile window. Every tx is kept in the volatile db,
+/// such that the rows it created are deleted on rollback.
+pub struct UtxoSqlIndexer<S> {
+    id: String,
+    env: Env,
+    sink: Mutex<S>,
+    confirmation_depth: u64,
+    rows: Database<RkyvCodec<TxOutputPointer>, RkyvCodec<UtxoRow>>,
+    // Rows spent by the block being applied, moved into its block scratch by `insert_block`
+    spent: Database<RkyvCodec<TxOutputPointer>, RkyvCodec<UtxoRow>>,
+    // big endian ints are lexicographically ordered
+    outbox: Database<U64<BigEndian>, RkyvCodec<UtxoChange>>,
+    /// Block number -> sequence number of the block's first change, for blocks whose changes
+    /// haven't been passed to the sink yet
+    outbox_blocks: Database<U64<BigEndian>, U64<BigEndian>>,
+    meta: Database<Str, U64<BigEndian>>,
+}
+
+const NEXT_SEQ_KEY: &str = "next_seq";
+const BLOCK_START_KEY: &str = "block_start";
+
+impl<S: SqlSink<UtxoChange>> UtxoSqlIndexer<S> {
+    pub fn new(id: &str, env: &Env, sink: S) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let rows = env.create_database(&mut wtxn, &format!("{id}_sql_rows"))?;
+        let spent = env.create_database(&mut wtxn, &format!("{id}_sql_spent"))?;
+        let outbox = env.create_database(&mut wtxn, &format!("{id}_sql_outbox"))?;
+        let outbox_blocks = env.create_database(&mut wtxn, &format!("{id}_sql_outbox_blocks"))?;
+        let meta = env.create_database(&mut wtxn, &format!("{id}_sql_meta"))?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            sink: Mutex::new(sink),
+            confirmation_depth: 0,
+            rows,
+            spent,
+            outbox,
+            outbox_blocks,
+            meta,
+        })
+    }
+
+    /// Only passes a block's changes to the sink once `depth` further blocks have been applied
+    /// on top of it, such that the sink never sees blocks that are rolled back within `depth`
+    /// blocks. Changes of buffered blocks that are rolled back are discarded rather than undone.
+    ///
+    /// The local index is unaffected, but the sink lags behind the tip by `depth` blocks, i.e.
+    /// roughly `depth * 20` seconds on mainnet. Defaults to 0, emitting every block on commit.
+    pub fn confirmation_depth(mut self, depth: u64) -> Self {
+        self.confirmation_depth = depth;
+        self
+    }
+
+    fn meta(&self, wtxn: &RwTxn, key: &str) -> Result<u64> {
+        Ok(self.meta.get(wtxn, key)?.unwrap_or(0))
+    }
+
+    fn push(&self, wtxn: &mut RwTxn, change: &UtxoChange) -> Result<()> {
+        let seq = self.meta(wtxn, NEXT_SEQ_KEY)?;
+        self.outbox.put(wtxn, &seq, change)?;
+        Ok(self.meta.put(wtxn, NEXT_SEQ_KEY, &(seq + 1))?)
+    }
+
+    /// Changes pushed from now on belong to the next block
+    fn start_block(&self, wtxn: &mut RwTxn) -> Result<()> {
+        let next = self.meta(wtxn, NEXT_SEQ_KEY)?;
+        Ok(self.meta.put(wtxn, BLOCK_START_KEY, &next)?)
+    }
+}
+
+impl<S: SqlSink<UtxoChange>> Indexer for UtxoSqlIndexer<S> {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn insert_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> Result<bool> {
+        for input in tx.spent() {
+            let row = match self.rows.get(wtxn, input)? {
+                Some(row) => Some(rkyv::deserialize::<UtxoRow, rkyv::rancor::Error>(row)?),
+                None => None,
+            };
+            if let Some(row) = row {
+                self.rows.delete(wtxn, input)?;
+                self.spent.put(wtxn, input, &row)?;
+            }
+            self.push(wtxn, &UtxoChange::Delete(input.clone()))?;
+        }
+        for (index, output) in tx.unspent().enumerate() {
+            let pointer = TxOutputPointer::new(tx.hash.clone(), index);
+            let row = UtxoRow::new(&pointer, output);
+            self.rows.put(wtxn, &pointer, &row)?;
+            self.push(wtxn, &UtxoChange::Insert(row))?;
+        }
+        Ok(true)
+    }
+
+    /// Deletes the rows the tx created. Spent rows are re-inserted by `delete_block`.
+    fn delete_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> Result<()> {
+        for (index, _) in tx.unspent().enumerate() {
+            let pointer = TxOutputPointer::new(tx.hash.clone(), index);
+            self.rows.delete(wtxn, &pointer)?;
+            self.push(wtxn, &UtxoChange::Delete(pointer))?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the rows spent by the block in its scratch, since the txs that created them may
+    /// have left the volatile window by the time the block is rolled back
+    fn insert_block(&self, db: &Db, wtxn: &mut RwTxn, block: &Block) -> Result<()> {
+        // Created and spent within the block, so there's nothing to restore
+        let created = block
+            .txs
+            .iter()
+            .map(|tx| &tx.hash)
+            .collect::<HashSet<&TxHash>>();
+        let mut spent = self
+            .spent
+            .iter(wtxn)?
+            .map(|res| {
+                let (pointer, row) = res?;
+                let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(pointer)?;
+                let row = rkyv::deserialize::<UtxoRow, rkyv::rancor::Error>(row)?;
+                Ok((pointer, row))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        spent.retain(|(pointer, _)| !created.contains(&pointer.hash));
+        self.spent.clear(wtxn)?;
+        if !spent.is_empty() {
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&spent)?;
+            db.put_block_scratch(wtxn, &self.id, &block.hash, &bytes)?;
+        }
+
+        let start = self.meta(wtxn, BLOCK_START_KEY)?;
+        self.outbox_blocks.put(wtxn, &block.number, &start)?;
+        self.start_block(wtxn)
+    }
+
+    fn delete_block(&self, db: &Db, wtxn: &mut RwTxn, block: &VolatileBlock) -> Result<()> {
+        let restored = match db.get_block_scratch(wtxn, &self.id, &block.hash)? {
+            Some(bytes) => from_unaligned_bytes::<Vec<(TxOutputPointer, UtxoRow)>>(&bytes)?,
+            None => vec![],
+        };
+        for (pointer, row) in restored.iter() {
+            self.rows.put(wtxn, pointer, row)?;
+        }
+
+        // Never emitted, so drop its changes along with the inverse changes from `delete_tx`
+        if let Some(start) = self.outbox_blocks.get(wtxn, &block.number)? {
+            self.outbox.delete_range(wtxn, &(start..))?;
+            self.outbox_blocks.delete(wtxn, &block.number)?;
+        } else {
+            for (_, row) in restored {
+                self.push(wtxn, &UtxoChange::Insert(row))?;
+            }
+        }
+        self.start_block(wtxn)
+    }
+
+    fn on_commit(&self) -> Result<()> {
+        // A write txn rather than a read txn, since the db may hold a read txn on this thread
+        let mut wtxn = self.env.write_txn()?;
+
+        // Blocks up to `confirmed` are deep enough to be emitted, along with any changes from
+        // before the first buffered block, e.g. the inverse of an emitted block
+        let tip = self.outbox_blocks.last(&wtxn)?.map(|(number, _)| number);
+        let confirmed = tip.and_then(|tip| tip.checked_sub(self.confirmation_depth));
+        let end = match confirmed {
+            Some(confirmed) => self.outbox_blocks.range(&wtxn, &(confirmed + 1..))?.next(),
+            None => self.outbox_blocks.first(&wtxn)?.map(Ok),
+        }
+        .transpose()?
+        .map_or(u64::MAX, |(_, start)| start);
+        if let Some(confirmed) = confirmed {
+            self.outbox_blocks
+                .delete_range(&mut wtxn, &(..=confirmed))?;
+        }
+
+        let changes = self
+            .outbox
+            .range(&wtxn, &(..end))?
+            .map(|res| {
+                Ok(rkyv::deserialize::<UtxoChange, rkyv::rancor::Error>(
+                    res?.1,
+                )?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !changes.is_empty() {
+            self.sink
+                .lock()
+                .expect("sql sink mutex poisoned")
+                .apply(&changes)
+                .context("failed to apply changes to sql sink")?;
+            self.outbox.delete_range(&mut wtxn, &(..end))?;
+        }
+        Ok(wtxn.commit()?)
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.rows.clear(wtxn)?;
+        self.spent.clear(wtxn)?;
+        self.outbox.clear(wtxn)?;
+        self.outbox_blocks.clear(wtxn)?;
+        self.push(wtxn, &UtxoChange::DeleteAll)?;
+        self.start_block(wtxn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+    use crate::indexer::IndexerList;
+    use crate::test_utils::{
+        MAX_ROLLBACK_BLOCKS, block, hash, output, pointer, roll_forward_and_back, temp_db, tx,
+    };
+
+    #[derive(Clone, Default)]
+    struct VecSink(Arc<Mutex<Vec<UtxoChange>>>);
+
+    impl SqlSink<UtxoChange> for VecSink {
+        fn apply(&mut self, changes: &[UtxoChange]) -> Result<()> {
+            self.0.lock().unwrap().extend_from_slice(changes);
+            Ok(())
+        }
+    }
+
+    fn row(tx_id: u64, index: usize, address: &[u8], lovelace: u64) -> UtxoChange {
+        UtxoChange::Insert(UtxoRow::new(
+            &pointer(tx_id, index),
+            &output(address, lovelace),
+        ))
+    }
+
+    #[test]
+    fn test_sql_sink_roll_forward_and_back() {
+        let (_dir, db) = temp_db();
+        let sink = VecSink::default();
+        let take = || std::mem::take(&mut *sink.0.lock().unwrap());
+
+        let indexer = UtxoSqlIndexer::new("sql", &db.env, sink.clone()).unwrap();
+        let txs = [
+            vec![tx(1, vec![], vec![output(b"a", 1)])],
+            vec![tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)])],
+        ];
+        let (_, indexers) = roll_forward_and_back(&db, indexer, txs, |_| {
+            assert_eq!(
+                take(),
+                [
+                    row(1, 0, b"a", 1),
+                    UtxoChange::Delete(pointer(1, 0)),
+                    row(2, 0, b"b", 1),
+                ]
+            );
+        });
+        assert_eq!(
+            take(),
+            [UtxoChange::Delete(pointer(2, 0)), row(1, 0, b"a", 1)]
+        );
+
+        db.roll_backward(&indexers, &Point::Origin).unwrap();
+        assert_eq!(take(), [UtxoChange::DeleteAll]);
+    }
+
+    #[test]
+    fn test_confirmation_depth() {
+        let (_dir, db) = temp_db();
+        let sink = VecSink::default();
+        let indexer = UtxoSqlIndexer::new("sql", &db.env, sink.clone())
+            .unwrap()
+            .confirmation_depth(1);
+        let indexers: IndexerList =
+            vec![Arc::new(Mutex::new(indexer)) as Arc<Mutex<dyn Indexer + Send>>];
+        let take = || std::mem::take(&mut *sink.0.lock().unwrap());
+
+        let block1 = block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]);
+        db.roll_forward(&indexers, &[block1]).unwrap();
+        assert_eq!(take(), []);
+
+        let spend = tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)]);
+        db.roll_forward(&indexers, &[block(2, 20, vec![spend])])
+            .unwrap();
+        assert_eq!(take(), [row(1, 0, b"a", 1)]);
+
+        // Never emitted, so nothing to undo
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(take(), []);
+
+        let blocks = [
+            block(3, 30, vec![tx(3, vec![], vec![output(b"c", 1)])]),
+            block(4, 40, vec![]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        assert_eq!(take(), [row(3, 0, b"c", 1)]);
+
+        // Undoes the emitted blocks only
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(take(), [UtxoChange::Delete(pointer(3, 0))]);
+    }
+
+    #[test]
+    fn test_restore_row_created_beyond_volatile_window() {
+        let (_dir, db) = temp_db();
+        let sink = VecSink::default();
+        let indexer = UtxoSqlIndexer::new("sql", &db.env, sink.clone()).unwrap();
+        let indexers: IndexerList =
+            vec![Arc::new(Mutex::new(indexer)) as Arc<Mutex<dyn Indexer + Send>>];
+        let take = || std::mem::take(&mut *sink.0.lock().unwrap());
+
+        let mut blocks = vec![block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])])];
+        blocks.extend(
+            (2..=MAX_ROLLBACK_BLOCKS as u64 + 1).map(|number| block(number, number * 10, vec![])),
+        );
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        take();
+
+        let number = MAX_ROLLBACK_BLOCKS as u64 + 2;
+        let spend = tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)]);
+        db.roll_forward(&indexers, &[block(number, number * 10, vec![spend])])
+            .unwrap();
+        take();
+
+        let parent = number - 1;
+        db.roll_backward(
+            &indexers,
+            &Point::Specific(parent * 10, hash::<32>(parent).to_vec()),
+        )
+        .unwrap();
+        assert_eq!(
+            take(),
+            [UtxoChange::Delete(pointer(2, 0)), row(1, 0, b"a", 1)]
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::Result;
+use heed::byteorder::BigEndian;
+use heed::types::U64;
+use heed::{Database, DatabaseFlags, RwTxn};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::db::{Db, Env, RkyvCodec, from_unaligned_bytes};
+use crate::indexer::{Indexer, credential_item, set_fingerprint};
+use crate::primitives::{
+    Block, Credential, PoolKeyHash, StakeCert, TxOutputPointer, VolatileBlock,
+    address_stake_credential,
+};
+
+/// UTxO counted towards a credential's stake
+#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+struct StakedOutput {
+    credential: Credential,
+    lovelace: u64,
+}
+
+/// Value an entry had before the block changed it, restored in reverse order on rollback
+#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+enum Undo {
+    Utxo {
+        pointer: TxOutputPointer,
+        prior: Option<StakedOutput>,
+    },
+    Delegation {
+        credential: Credential,
+        prior: Option<PoolKeyHash>,
+    },
+}
+
+/// Tracks which pool each stake credential delegates to, from delegation and deregistration
+/// certificates, alongside the lovelace held by the UTxOs at base addresses staked with it, to
+/// answer how much stake is delegated to a pool. Limited to a set of credentials unless
+/// indexing all of them.
+///
+/// Only UTxO stake is counted: reward account balances aren't on chain, and outputs to pointer
+/// addresses aren't resolved to their credential.
+#[derive(Clone)]
+pub struct StakeDistributionIndexer {
+    id: String,
+    env: Env,
+    /// `None` tracks every credential
+    credentials: Option<Vec<Credential>>,
+    /// UTxOs at base addresses staked with a tracked credential
+    utxos: Database<RkyvCodec<TxOutputPointer>, RkyvCodec<StakedOutput>>,
+    /// Credential -> lovelace of its UTxOs
+    stake: Database<RkyvCodec<Credential>, U64<BigEndian>>,
+    /// Credential -> pool it delegates to
+    delegations: Database<RkyvCodec<Credential>, RkyvCodec<PoolKeyHash>>,
+    /// Pool -> credentials delegating to it
+    delegators: Database<RkyvCodec<PoolKeyHash>, RkyvCodec<Credential>>,
+}
+
+impl StakeDistributionIndexer {
+    pub fn new(id: &str, env: &Env, credentials: Option<Vec<Credential>>) -> Result<Self> {
+        let env = env.clone();
+
+        let mut wtxn = env.write_txn()?;
+        let utxos = env.create_database(&mut wtxn, &format!("{id}_stake_utxos"))?;
+        let stake = env.create_database(&mut wtxn, &format!("{id}_stake"))?;
+        let delegations = env.create_database(&mut wtxn, &format!("{id}_delegations"))?;
+        let delegators = env.create_database_with_flags(
+            &mut wtxn,
+            &format!("{id}_delegators"),
+            DatabaseFlags::DUP_SORT,
+        )?;
+        wtxn.commit()?;
+
+        Ok(Self {
+            id: id.to_string(),
+            env,
+            credentials,
+            utxos,
+            stake,
+            delegations,
+            delegators,
+        })
+    }
+
+    /// Pool the credential currently delegates to
+    pub fn delegation(&self, credential: &Credential) -> Result<Option<PoolKeyHash>> {
+        let txn = self.env.read_txn()?;
+        self.delegations
+            .get(&txn, credential)?
+            .map(|pool| Ok(rkyv::deserialize::<PoolKeyHash, rkyv::rancor::Error>(pool)?))
+            .transpose()
+    }
+
+    /// Lovelace held by the UTxOs staked with the credential, whether or not it delegates
+    pub fn stake(&self, credential: &Credential) -> Result<u64> {
+        let txn = self.env.read_txn()?;
+        Ok(self.stake.get(&txn, credential)?.unwrap_or(0))
+    }
+
+    /// Total lovelace of the UTxOs staked with the tracked credentials delegating to the pool
+    pub fn stake_to_pool(&self, pool: &PoolKeyHash) -> Result<u64> {
+        let txn = self.env.read_txn()?;
+        let Some(delegators) = self.delegators.get_duplicates(&txn, pool)? else {
+            return Ok(0);
+        };
+        let mut total = 0u64;
+        for res in delegators {
+            let (_, credential) = res?;
+            let credential = rkyv::deserialize::<Credential, rkyv::rancor::Error>(credential)?;
+            let stake = self.stake.get(&txn, &credential)?.unwrap_or(0);
+            total = total
+                .checked_add(stake)
+                .ok_or_else(|| anyhow::anyhow!("stake of pool {pool} overflows"))?;
+        }
+        Ok(total)
+    }
+
+    fn tracked(&self, credential: Option<Credential>) -> Option<Credential> {
+        credential.filter(|credential| match &self.credentials {
+            Some(credentials) => credentials.contains(credential),
+            None => true,
+        })
+    }
+
+    /// Sets or removes the UTxO, keeping its credential's stake in sync. Returns the previous
+    /// value.
+    fn set_utxo(
+        &self,
+        wtxn: &mut RwTxn,
+        pointer: &TxOutputPointer,
+        utxo: Option<&StakedOutput>,
+    ) -> Result<Option<StakedOutput>> {
+        let prior = match self.utxos.get(wtxn, pointer)? {
+            Some(utxo) => Some(rkyv::deserialize::<StakedOutput, rkyv::rancor::Error>(
+                utxo,
+            )?),
+            None => None,
+        };
+        if let Some(prior) = &prior {
+            let stake = self.stake.get(wtxn, &prior.credential)?.unwrap_or(0);
+            let stake = stake.saturating_sub(prior.lovelace);
+            if stake == 0 {
+                self.stake.delete(wtxn, &prior.credential)?;
+            } else {
+                self.stake.put(wtxn, &prior.credential, &stake)?;
+            }
+        }
+        match utxo {
+            Some(utxo) => {
+                let stake = self.stake.get(wtxn, &utxo.credential)?.unwrap_or(0);
+                self.stake
+                    .put(wtxn, &utxo.credential, &stake.saturating_add(utxo.lovelace))?;
+                self.utxos.put(wtxn, pointer, utxo)?;
+            }
+            None => {
+                self.utxos.delete(wtxn, pointer)?;
+            }
+        }
+        Ok(prior)
+    }
+
+    /// Sets or removes the credential's delegation. Returns the previous pool.
+    fn set_delegation(
+        &self,
+        wtxn: &mut RwTxn,
+        credential: &Credential,
+        pool: Option<&PoolKeyHash>,
+    ) -> Result<Option<PoolKeyHash>> {
+        let prior = match self.delegations.get(wtxn, credential)? {
+            Some(pool) => Some(rkyv::deserialize::<PoolKeyHash, rkyv::rancor::Error>(pool)?),
+            None => None,
+        };
+        if let Some(prior) = &prior {
+            self.delegators
+                .delete_one_duplicate(wtxn, prior, credential)?;
+        }
+        match pool {
+            Some(pool) => {
+                self.delegations.put(wtxn, credential, pool)?;
+                self.delegators.put(wtxn, pool, credential)?;
+            }
+            None => {
+                self.delegations.delete(wtxn, credential)?;
+            }
+        }
+        Ok(prior)
+    }
+}
+
+impl Indexer for StakeDistributionIndexer {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Empty when tracking every credential
+    fn config_fingerprint(&self) -> Vec<u8> {
+        match &self.credentials {
+            Some(credentials) => set_fingerprint(credentials.iter().map(credential_item)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Applies the block and keeps what it overwrote in the block scratch, since the txs that
+    /// created the spent UTxOs may have left the volatile window by the time it's rolled back
+    fn insert_block(&self, db: &Db, wtxn: &mut RwTxn, block: &Block) -> Result<()> {
+        let mut undo = vec![];
+        for tx in block.txs.iter() {
+            for input in tx.spent() {
+                if self.utxos.get(wtxn, input)?.is_some() {
+                    let prior = self.set_utxo(wtxn, input, None)?;
+                    undo.push(Undo::Utxo {
+                        pointer: input.clone(),
+                        prior,
+                    });
+                }
+            }
+            for (index, output) in tx.unspent().enumerate() {
+                if let Some(credential) = self.tracked(address_stake_credential(&output.address)) {
+                    let pointer = TxOutputPointer::new(tx.hash.clone(), index);
+                    let utxo = StakedOutput {
+                        credential,
+                        lovelace: output.lovelace,
+                    };
+                    let prior = self.set_utxo(wtxn, &pointer, Some(&utxo))?;
+                    undo.push(Undo::Utxo { pointer, prior });
+                }
+            }
+            for cert in tx.stake_certs.iter() {
+                let Some(credential) = self.tracked(Some(cert.credential().clone())) else {
+                    continue;
+                };
+                let pool = match cert {
+                    StakeCert::Delegation { pool, .. } => Some(pool),
+                    StakeCert::Deregistration { .. } => None,
+                };
+                let prior = self.set_delegation(wtxn, &credential, pool)?;
+                undo.push(Undo::Delegation { credential, prior });
+            }
+        }
+
+        if !undo.is_empty() {
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&undo)?;
+            db.put_block_scratch(wtxn, &self.id, &block.hash, &bytes)?;
+        }
+        Ok(())
+    }
+
+    fn delete_block(&self, db: &Db, wtxn: &mut RwTxn, block: &VolatileBlock) -> Result<()> {
+        let Some(bytes) = db.get_block_scratch(wtxn, &self.id, &block.hash)? else {
+            return Ok(());
+        };
+        // Changes made by the block, as stored by `insert_block`
+        for undo in from_unaligned_bytes::<Vec<Undo>>(&bytes)?.iter().rev() {
+            match undo {
+                Undo::Utxo { pointer, prior } => {
+                    self.set_utxo(wtxn, pointer, prior.as_ref())?;
+                }
+                Undo::Delegation { credential, prior } => {
+                    self.set_delegation(wtxn, credential, prior.as_ref())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn wants_datums(&self) -> bool {
+        false
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
+    fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.utxos.clear(wtxn)?;
+        self.stake.clear(wtxn)?;
+        self.delegations.clear(wtxn)?;
+        self.delegators.clear(wtxn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{hash, output, pointer, roll_forward_and_back, temp_db, tx};
+
+    #[test]
+    fn test_stake_to_pool() {
+        let (_dir, db) = temp_db();
+        let (alice, bob, carol) = (
+            Credential::Key(hash(1)),
+            Credential::Key(hash(2)),
+            Credential::Key(hash(3)),
+        );
+        // Base address paying to some key, staked with the credential
+        let staked = |stake: u64| {
+            [
+                &[0x01][..],
+                hash::<28>(9).as_slice(),
+                hash::<28>(stake).as_slice(),
+            ]
+            .concat()
+        };
+        let (pool_a, pool_b) = (hash::<28>(10), hash::<28>(11));
+        let delegate = |credential: &Credential, pool: &PoolKeyHash| StakeCert::Delegation {
+            credential: credential.clone(),
+            pool: pool.clone(),
+        };
+
+        let mut first = tx(
+            1,
+            vec![],
+            vec![
+                output(&staked(1), 5),
+                output(&staked(2), 7),
+                output(&staked(3), 11),
+            ],
+        );
+        first.stake_certs = vec![
+            delegate(&alice, &pool_a),
+            delegate(&bob, &pool_a),
+            delegate(&carol, &pool_a),
+        ];
+        // Bob spends part of his stake and moves to another pool, Alice deregisters
+        let mut second = tx(2, vec![pointer(1, 1)], vec![output(&staked(2), 3)]);
+        second.stake_certs = vec![
+            delegate(&bob, &pool_b),
+            StakeCert::Deregistration {
+                credential: alice.clone(),
+            },
+        ];
+
+        let indexer =
+            StakeDistributionIndexer::new("stake", &db.env, Some(vec![alice.clone(), bob.clone()]))
+                .unwrap();
+        let txs = [vec![first], vec![second]];
+        let (indexer, _) = roll_forward_and_back(&db, indexer, txs, |stake| {
+            assert_eq!(stake.stake_to_pool(&pool_a).unwrap(), 0);
+            assert_eq!(stake.stake_to_pool(&pool_b).unwrap(), 3);
+            assert_eq!(stake.delegation(&alice).unwrap(), None);
+            assert_eq!(stake.stake(&alice).unwrap(), 5);
+        });
+
+        let stake = indexer.lock().unwrap();
+        // Carol isn't tracked
+        assert_eq!(stake.stake_to_pool(&pool_a).unwrap(), 12);
+        assert_eq!(stake.stake_to_pool(&pool_b).unwrap(), 0);
+        assert_eq!(stake.delegation(&bob).unwrap(), Some(pool_a));
+        assert_eq!(stake.delegation(&carol).unwrap(), None);
+        assert_eq!(stake.stake(&bob).unwrap(), 7);
+    }
+}
@@ -1,14 +1,37 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+
 use anyhow::{Context, Result};
+use heed::byteorder::BigEndian;
+use heed::types::{Bytes, DecodeIgnore, U64, Unit};
 use heed::{Database, DatabaseFlags, RwTxn};
 
-use crate::db::{Db, Env, RkyvCodec};
-use crate::indexer::Indexer;
-use crate::primitives::{Address, AssetId, Tx, TxOutput, TxOutputPointer};
+use crate::db::{Db, Env, RkyvCodec, from_unaligned_bytes};
+use crate::indexer::{Indexer, QueryLimits};
+use crate::primitives::{
+    Address, ArchivedTxOutput, ArchivedTxOutputPointer, Asset, AssetId, AssetName, Block,
+    BlockHash, Policy, Script, ScriptHash, Slot, Tx, TxHash, TxOutput, TxOutputPointer,
+    VolatileBlock,
+};
+
+/// How the address and asset filters of a [`UtxoIndexer`] combine when both are set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Outputs at one of the addresses or holding one of the assets
+    Any,
+    /// Outputs at one of the addresses that also hold one of the assets
+    #[default]
+    All,
+}
 
 pub struct UtxoIndexerBuilder {
     id: String,
     addresses: Option<Vec<Address>>,
     assets: Option<Vec<AssetId>>,
+    filter_mode: FilterMode,
+    retain_referenced: bool,
+    max_script_bytes: usize,
+    query_limits: QueryLimits,
 }
 
 impl UtxoIndexerBuilder {
@@ -17,6 +40,10 @@ impl UtxoIndexerBuilder {
             id: id.to_string(),
             addresses: None,
             assets: None,
+            filter_mode: FilterMode::default(),
+            retain_referenced: false,
+            max_script_bytes: DEFAULT_MAX_SCRIPT_BYTES,
+            query_limits: QueryLimits::default(),
         }
     }
 
@@ -42,8 +69,50 @@ impl UtxoIndexerBuilder {
         self
     }
 
+    /// Whether outputs have to pass both the address and the asset filter, or either of them.
+    /// Only matters when both are set.
+    pub fn filter_mode(mut self, filter_mode: FilterMode) -> Self {
+        self.filter_mode = filter_mode;
+        self
+    }
+
+    /// Also index UTxOs the filters skip when a tx references them, so reference scripts and
+    /// datums used with the filtered UTxOs stay resolvable. Such UTxOs are kept until spent,
+    /// which grows the index beyond the filters by every UTxO referenced at least once, e.g.
+    /// shared script UTxOs of popular protocols. Only UTxOs of txs retained in the volatile
+    /// window can be resolved, i.e. ones created within the window by a tx some indexer stored.
+    pub fn retain_referenced(mut self, retain_referenced: bool) -> Self {
+        self.retain_referenced = retain_referenced;
+        self
+    }
+
+    /// Largest reference script kept with its output, 64KB by default. Larger Plutus scripts
+    /// are dropped from the output, with a warning, and their hashes listed by
+    /// [`UtxoIndexer::skipped_scripts`] for fetching elsewhere. Native scripts are always kept.
+    /// Only applies to outputs indexed after it's changed.
+    pub fn max_script_bytes(mut self, max_script_bytes: usize) -> Self {
+        self.max_script_bytes = max_script_bytes;
+        self
+    }
+
+    pub fn query_limits(mut self, query_limits: QueryLimits) -> Self {
+        self.query_limits = query_limits;
+        self
+    }
+
     pub fn build(self, env: &Env) -> Result<UtxoIndexer> {
-        UtxoIndexer::new(&self.id, env, self.addresses, self.assets)
+        let mut indexer = UtxoIndexer::new(&self.id, env, self.addresses, self.assets)?;
+        indexer.query_limits = self.query_limits;
+        indexer.retain_referenced = self.retain_referenced;
+        indexer.filter_mode = self.filter_mode;
+        indexer.max_script_bytes = self.max_script_bytes;
+        indexer.fingerprint = filter_fingerprint(
+            &indexer.addresses,
+            &indexer.assets,
+            self.filter_mode,
+            self.retain_referenced,
+        );
+        Ok(indexer)
     }
 }
 
@@ -54,8 +123,205 @@ pub struct UtxoIndexer {
     utxos: Database<RkyvCodec<TxOutputPointer>, RkyvCodec<TxOutput>>,
     by_address: Database<RkyvCodec<Address>, RkyvCodec<TxOutputPointer>>,
     by_asset: Database<RkyvCodec<AssetId>, RkyvCodec<TxOutputPointer>>,
+    // rkyv stores the asset name out of line, so `by_asset` keys can't be seeked by name prefix.
+    // Keyed by policy bytes followed by the name bytes instead.
+    by_asset_name: Database<Bytes, RkyvCodec<TxOutputPointer>>,
+    // big endian ints are lexicographically ordered
+    by_lovelace: Database<U64<BigEndian>, RkyvCodec<TxOutputPointer>>,
+    // UTxOs spent by the block being applied, moved into its block scratch by `insert_block`
+    spent: Database<RkyvCodec<TxOutputPointer>, RkyvCodec<TxOutput>>,
+    // UTxOs retained for being referenced by the block being applied, see
+    // `UtxoIndexerBuilder::retain_referenced`, moved into its block scratch like `spent`
+    retained: Database<RkyvCodec<TxOutputPointer>, Unit>,
+    /// Hashes of reference scripts too large to keep, see
+    /// [`UtxoIndexerBuilder::max_script_bytes`]
+    skipped_scripts: Database<RkyvCodec<ScriptHash>, Unit>,
     addresses: Option<Vec<Address>>,
-    assets: Option<Vec<AssetId>>,
+    assets: Option<AssetWhitelist>,
+    /// See [`UtxoIndexerBuilder::filter_mode`]
+    filter_mode: FilterMode,
+    /// See [`UtxoIndexerBuilder::retain_referenced`]
+    retain_referenced: bool,
+    /// See [`UtxoIndexerBuilder::max_script_bytes`]
+    max_script_bytes: usize,
+    /// Hash of the filters, see [`Indexer::config_fingerprint`]
+    fingerprint: Vec<u8>,
+    query_limits: QueryLimits,
+}
+
+/// Differences between two UTxO sets, see [`UtxoIndexer::diff_against`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UtxoDiff {
+    pub only_in_self: Vec<TxOutputPointer>,
+    pub only_in_other: Vec<TxOutputPointer>,
+    /// Present in both, with a different output
+    pub changed: Vec<TxOutputPointer>,
+}
+
+impl UtxoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// UTxOs a block created and spent, see [`UtxoIndexer::block_effects`]
+#[derive(Debug, Clone, Default)]
+pub struct BlockEffects {
+    pub created: Vec<(TxOutputPointer, TxOutput)>,
+    pub spent: Vec<(TxOutputPointer, TxOutput)>,
+}
+
+/// Totals of the UTxOs at an address, see [`UtxoIndexer::balance`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub lovelace: u64,
+    /// Keyed by asset ids that always have a name
+    pub assets: HashMap<AssetId, u64>,
+}
+
+/// UTxOs [`UtxoIter`] reads per read txn
+const UTXO_ITER_BATCH: usize = 1024;
+
+/// Iterator over the indexed UTxOs, see [`UtxoIndexer::utxos_iter`]
+pub struct UtxoIter {
+    env: Env,
+    utxos: Database<RkyvCodec<TxOutputPointer>, RkyvCodec<TxOutput>>,
+    batch_size: usize,
+    batch: std::vec::IntoIter<(TxOutputPointer, TxOutput)>,
+    /// Where the next batch starts, `None` once the table is exhausted
+    next: Option<Bound<TxOutputPointer>>,
+}
+
+impl UtxoIter {
+    fn read_batch(&mut self) -> Result<()> {
+        if self.next.is_none() {
+            return Ok(());
+        }
+        let txn = self.env.read_txn()?;
+        self.read_batch_in(&txn)
+    }
+
+    fn read_batch_in(&mut self, txn: &heed::RoTxn) -> Result<()> {
+        let Some(start) = self.next.take() else {
+            return Ok(());
+        };
+        let mut batch = Vec::new();
+        for res in self
+            .utxos
+            .range(txn, &(start.as_ref(), Bound::Unbounded))?
+            .take(self.batch_size)
+        {
+            let (pointer, txo) = res?;
+            let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(pointer)?;
+            let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
+            batch.push((pointer, txo));
+        }
+        if batch.len() == self.batch_size {
+            self.next = batch
+                .last()
+                .map(|(pointer, _)| Bound::Excluded(pointer.clone()));
+        }
+        self.batch = batch.into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for UtxoIter {
+    type Item = Result<(TxOutputPointer, TxOutput)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(utxo) = self.batch.next() {
+            return Some(Ok(utxo));
+        }
+        // Errors end the iteration, as `next` was taken
+        if let Err(err) = self.read_batch() {
+            return Some(Err(err));
+        }
+        self.batch.next().map(Ok)
+    }
+}
+
+const DEFAULT_MAX_SCRIPT_BYTES: usize = 64 * 1024;
+
+/// Whitelisted asset names by policy, where `None` whitelists every asset of the policy. Keeps
+/// the filter a couple of hash lookups per output asset, however long the whitelist.
+type AssetWhitelist = HashMap<Policy, Option<HashSet<AssetName>>>;
+
+fn asset_whitelist(assets: Vec<AssetId>) -> AssetWhitelist {
+    let mut whitelist = AssetWhitelist::new();
+    for AssetId { policy, name } in assets {
+        let names = whitelist
+            .entry(policy)
+            .or_insert_with(|| Some(HashSet::new()));
+        match (names, name) {
+            (Some(names), Some(name)) => {
+                names.insert(name);
+            }
+            (names, None) => *names = None,
+            (None, Some(_)) => {}
+        }
+    }
+    whitelist
+}
+
+/// Hashes the filters in a canonical order, so equivalent filters listed differently match.
+/// Empty when unfiltered.
+fn filter_fingerprint(
+    addresses: &Option<Vec<Address>>,
+    assets: &Option<AssetWhitelist>,
+    filter_mode: FilterMode,
+    retain_referenced: bool,
+) -> Vec<u8> {
+    if addresses.is_none() && assets.is_none() {
+        return Vec::new();
+    }
+    let mut bytes = Vec::new();
+    let mut push = |value: &[u8]| {
+        bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(value);
+    };
+    if let Some(addresses) = addresses {
+        push(b"addresses");
+        let mut addresses = addresses.iter().collect::<Vec<_>>();
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses
+            .into_iter()
+            .for_each(|address| push(address.as_slice()));
+    }
+    if let Some(assets) = assets {
+        push(b"assets");
+        let mut policies = assets.iter().collect::<Vec<_>>();
+        policies.sort_unstable_by_key(|(policy, _)| policy.0);
+        for (policy, names) in policies {
+            push(policy.as_slice());
+            match names {
+                Some(names) => {
+                    let mut names = names.iter().collect::<Vec<_>>();
+                    names.sort_unstable();
+                    push(b"names");
+                    names.into_iter().for_each(|name| push(name.as_slice()));
+                }
+                None => push(b"policy"),
+            }
+        }
+    }
+    // Left out for the default, which is how both filters always combined before
+    if filter_mode == FilterMode::Any && addresses.is_some() && assets.is_some() {
+        push(b"any");
+    }
+    if retain_referenced {
+        push(b"referenced");
+    }
+    pallas::crypto::hash::Hasher::<256>::hash(&bytes).to_vec()
+}
+
+fn is_whitelisted(whitelist: &AssetWhitelist, asset: &Asset) -> bool {
+    match whitelist.get(&asset.policy) {
+        Some(Some(names)) => names.contains(&asset.name),
+        Some(None) => true,
+        None => false,
+    }
 }
 
 impl UtxoIndexer {
@@ -73,25 +339,370 @@ impl UtxoIndexer {
             env.create_database_with_flags(&mut wtxn, "by_address", DatabaseFlags::DUP_SORT)?;
         let by_asset =
             env.create_database_with_flags(&mut wtxn, "by_asset", DatabaseFlags::DUP_SORT)?;
+        let by_asset_name =
+            env.create_database_with_flags(&mut wtxn, "by_asset_name", DatabaseFlags::DUP_SORT)?;
+        let by_lovelace =
+            env.create_database_with_flags(&mut wtxn, "by_lovelace", DatabaseFlags::DUP_SORT)?;
+        let spent = env.create_database(&mut wtxn, "spent")?;
+        let retained = env.create_database(&mut wtxn, "retained")?;
+        let skipped_scripts = env.create_database(&mut wtxn, "skipped_scripts")?;
         wtxn.commit()?;
 
+        let assets = assets.map(asset_whitelist);
+        let fingerprint = filter_fingerprint(&addresses, &assets, FilterMode::default(), false);
         Ok(Self {
             id: id.to_string(),
             env,
             utxos,
             by_address,
             by_asset,
+            by_asset_name,
+            by_lovelace,
+            spent,
+            retained,
+            skipped_scripts,
             addresses,
             assets,
+            filter_mode: FilterMode::default(),
+            retain_referenced: false,
+            max_script_bytes: DEFAULT_MAX_SCRIPT_BYTES,
+            fingerprint,
+            query_limits: QueryLimits::default(),
         })
     }
 
+    /// All indexed UTxOs, read in a single pass while holding the read txn. Like every query
+    /// here, the txn is dropped before returning, so it never holds up a resize while the
+    /// caller consumes the results. See [`UtxoIndexer::utxos_iter`] for sets too large to
+    /// collect.
     pub fn utxos(&self) -> Result<Vec<(TxOutputPointer, TxOutput)>> {
+        let txn = self.env.read_txn()?;
+        self.query_limits.check(self.utxos.len(&txn)? as usize)?;
+        // A single batch read in the txn that was counted, so both see the same snapshot
+        let mut utxos = self.utxos_batched(usize::MAX);
+        utxos.read_batch_in(&txn)?;
+        Ok(utxos.batch.collect())
+    }
+
+    /// All indexed UTxOs, ordered by pointer, read lazily in batches of 1024 with a read txn
+    /// each, so memory use stays flat and no txn is kept open while the caller consumes them.
+    /// Unlike [`UtxoIndexer::utxos`], the set isn't read from a single snapshot: blocks
+    /// committed while iterating show up in the batches read after them. Not subject to the
+    /// [`QueryLimits`].
+    pub fn utxos_iter(&self) -> UtxoIter {
+        self.utxos_batched(UTXO_ITER_BATCH)
+    }
+
+    fn utxos_batched(&self, batch_size: usize) -> UtxoIter {
+        UtxoIter {
+            env: self.env.clone(),
+            utxos: self.utxos,
+            batch_size,
+            batch: Vec::new().into_iter(),
+            next: Some(Bound::Unbounded),
+        }
+    }
+
+    /// The UTxO, if it's unspent and indexed
+    pub fn utxo(&self, pointer: &TxOutputPointer) -> Result<Option<TxOutput>> {
+        let txn = self.env.read_txn()?;
+        self.utxos
+            .get(&txn, pointer)?
+            .map(|txo| Ok(rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?))
+            .transpose()
+    }
+
+    /// Assets of the UTxO, if it's unspent and indexed. Only the assets are deserialized out of
+    /// the archived output, sparing the address, datum hash and reference script, e.g. for
+    /// listing the tokens of a UTxO. See `benches/assets_of.rs` for how it compares to
+    /// deserializing the whole output.
+    pub fn assets_of(&self, pointer: &TxOutputPointer) -> Result<Option<Vec<Asset>>> {
         let txn = self.env.read_txn()?;
         self.utxos
+            .get(&txn, pointer)?
+            .map(|txo| {
+                Ok(rkyv::deserialize::<Vec<Asset>, rkyv::rancor::Error>(
+                    &txo.assets,
+                )?)
+            })
+            .transpose()
+    }
+
+    /// UTxOs held at the address, looked up through the `by_address` index rather than
+    /// scanning the whole set
+    pub fn utxos_by_address(&self, address: &Address) -> Result<Vec<(TxOutputPointer, TxOutput)>> {
+        let txn = self.env.read_txn()?;
+        let Some(pointers) = self.by_address.get_duplicates(&txn, address)? else {
+            return Ok(vec![]);
+        };
+        let utxos = pointers
+            .map(|res| {
+                let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(res?.1)?;
+                // Both tables are written in the same txn, so this means they're out of sync
+                let txo = self.utxos.get(&txn, &pointer)?.with_context(|| {
+                    format!("{pointer} is indexed by address but missing from the UTxO table, the db could be corrupt")
+                })?;
+                let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
+                Ok((pointer, txo))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.query_limits.check(utxos.len())?;
+        Ok(utxos)
+    }
+
+    /// Lovelace and assets held by the UTxOs at the address, summed in a single read txn
+    /// without collecting the UTxOs. Only covers what the indexer holds, so with an asset
+    /// filter it's the balance of the filtered outputs.
+    pub fn balance(&self, address: &Address) -> Result<Balance> {
+        let txn = self.env.read_txn()?;
+        let mut balance = Balance::default();
+        let Some(pointers) = self.by_address.get_duplicates(&txn, address)? else {
+            return Ok(balance);
+        };
+        for res in pointers {
+            let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(res?.1)?;
+            let txo = self.utxos.get(&txn, &pointer)?.with_context(|| {
+                format!("{pointer} is indexed by address but missing from the UTxO table, the db could be corrupt")
+            })?;
+            let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
+            balance.lovelace = balance
+                .lovelace
+                .checked_add(txo.lovelace)
+                .context("lovelace balance overflows")?;
+            for asset in txo.assets.iter() {
+                let quantity = u64::try_from(asset.quantity)
+                    .with_context(|| format!("{pointer} holds a negative quantity"))?;
+                let total = balance.assets.entry(AssetId::from(asset)).or_default();
+                *total = total
+                    .checked_add(quantity)
+                    .context("asset balance overflows")?;
+            }
+        }
+        Ok(balance)
+    }
+
+    /// The indexed UTxO set as it was at `slot`, in no particular order, including referenced
+    /// UTxOs retained by then. Reconstructed by undoing the blocks after `slot` on a copy of the
+    /// live set, which is left untouched. Reads the whole set, so it's far heavier than the live
+    /// queries. Only reaches back as far as the volatile window, erroring with
+    /// [`Error::RollbackTooDeep`](crate::db::Error::RollbackTooDeep) beyond it.
+    pub fn utxos_at(&self, db: &Db, slot: Slot) -> Result<Vec<(TxOutputPointer, TxOutput)>> {
+        let txn = self.env.read_txn()?;
+        let blocks = db.volatile_blocks_after(&txn, slot)?;
+        let mut utxos = self
+            .utxos
             .iter(&txn)?
             .map(|res| {
-                let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(res?.0)?;
+                let (pointer, txo) = res?;
+                let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(pointer)?;
+                let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
+                Ok((pointer, txo))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        // Same as rolling back with `delete_tx` and `delete_block`
+        for block in blocks.iter() {
+            for tx_hash in block.txs.iter().rev() {
+                let tx = db
+                    .get_volatile_tx(&txn, tx_hash)?
+                    .context("missing tx in volatile db")?;
+                for (index, _) in tx.unspent().enumerate() {
+                    utxos.remove(&TxOutputPointer::new(tx.hash.clone(), index));
+                }
+            }
+            if let Some(bytes) = db.get_block_scratch(&txn, &self.id, &block.hash)? {
+                utxos.extend(decode_spent(&bytes)?);
+            }
+            if let Some(bytes) =
+                db.get_block_scratch(&txn, &self.retained_scratch_id(), &block.hash)?
+            {
+                for pointer in from_unaligned_bytes::<Vec<TxOutputPointer>>(&bytes)?.iter() {
+                    utxos.remove(pointer);
+                }
+            }
+        }
+
+        self.query_limits.check(utxos.len())?;
+        Ok(utxos.into_iter().collect())
+    }
+
+    /// UTxOs of this indexer the block created and spent, or `None` if the block isn't in the
+    /// volatile window. Created UTxOs are listed whether or not they were spent since, those
+    /// spent within the block itself show up on both sides.
+    pub fn block_effects(&self, db: &Db, block_hash: &BlockHash) -> Result<Option<BlockEffects>> {
+        let txn = self.env.read_txn()?;
+        let Some(block) = db.get_volatile_block(&txn, block_hash)? else {
+            return Ok(None);
+        };
+        let mut effects = BlockEffects::default();
+        if let Some(bytes) = db.get_block_scratch(&txn, &self.id, block_hash)? {
+            effects.spent = decode_spent(&bytes)?;
+        }
+        // Only the txs some indexer retained are stored, which includes every tx that touched
+        // this one's UTxOs
+        for tx_hash in block.txs.iter() {
+            let tx = db
+                .get_volatile_tx(&txn, tx_hash)?
+                .context("missing tx in volatile db")?;
+            // Left out of the block scratch, as there's nothing to restore on rollback
+            for input in tx.spent() {
+                if let Some((_, output)) = effects.created.iter().find(|(ptr, _)| ptr == input) {
+                    effects.spent.push((input.clone(), output.clone()));
+                }
+            }
+            for (index, output) in tx.unspent().enumerate() {
+                if self.passes_filters(output) {
+                    let pointer = TxOutputPointer::new(tx.hash.clone(), index);
+                    effects.created.push((pointer, output.clone()));
+                }
+            }
+        }
+        Ok(Some(effects))
+    }
+
+    /// Hashes of the reference scripts dropped for exceeding
+    /// [`UtxoIndexerBuilder::max_script_bytes`]. Kept after their outputs are spent or rolled
+    /// back, since the scripts themselves never change.
+    pub fn skipped_scripts(&self) -> Result<Vec<ScriptHash>> {
+        let txn = self.env.read_txn()?;
+        self.skipped_scripts
+            .iter(&txn)?
+            .map(|res| {
+                Ok(rkyv::deserialize::<ScriptHash, rkyv::rancor::Error>(
+                    res?.0,
+                )?)
+            })
+            .collect()
+    }
+
+    /// Iterates the UTxO table as stored: rkyv archived [`TxOutputPointer`] keys and
+    /// [`TxOutput`] values, without deserializing. Together with [`UtxoIndexer::put_raw`] this
+    /// transfers a UTxO set between processes without re-serializing it. Holds the resize
+    /// read-lock through `rtxn`, see [`Env::read_txn`].
+    pub fn raw_entries_iter<'t>(
+        &self,
+        rtxn: &'t heed::RoTxn,
+    ) -> Result<impl Iterator<Item = Result<(&'t [u8], &'t [u8])>> + 't> {
+        Ok(self
+            .utxos
+            .remap_types::<Bytes, Bytes>()
+            .iter(rtxn)?
+            .map(|res| Ok(res?)))
+    }
+
+    /// Inserts an entry yielded by [`UtxoIndexer::raw_entries_iter`], e.g. on a read replica that
+    /// doesn't sync itself. The address and asset filters apply as usual, returning whether the
+    /// entry was stored. The secondary indexes still need the deserialized output.
+    ///
+    /// Both archives are validated with `rkyv::access` before anything is stored, so malformed
+    /// bytes error instead of corrupting the table. That only checks the bytes are a valid
+    /// archive though: a well-formed but fabricated output is stored as is, so only accept
+    /// entries from a trusted primary.
+    pub fn put_raw(&self, wtxn: &mut RwTxn, key: &[u8], value: &[u8]) -> Result<bool> {
+        // Network buffers aren't aligned for rkyv
+        let mut aligned_key = rkyv::util::AlignedVec::<16>::new();
+        aligned_key.extend_from_slice(key);
+        let mut aligned_value = rkyv::util::AlignedVec::<16>::new();
+        aligned_value.extend_from_slice(value);
+        let pointer = rkyv::access::<ArchivedTxOutputPointer, rkyv::rancor::Error>(&aligned_key)
+            .context("invalid archived tx output pointer")?;
+        let txo = rkyv::access::<ArchivedTxOutput, rkyv::rancor::Error>(&aligned_value)
+            .context("invalid archived tx output")?;
+
+        // The secondary indexes are keyed by individual fields
+        let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(pointer)?;
+        let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
+        self.insert_output(wtxn, &pointer, &txo)
+    }
+
+    /// Compares the UTxO set with another indexer's, e.g. to verify a reindex or a replica
+    /// matches the primary. A single merge-join over both tables in key order, comparing the
+    /// archived outputs byte for byte, so it's linear in the size of the sets.
+    ///
+    /// The other indexer should live in a different [`Db`]: both tables are read at once, which
+    /// needs a read txn on each environment.
+    pub fn diff_against(&self, other: &UtxoIndexer) -> Result<UtxoDiff> {
+        let txn = self.env.read_txn()?;
+        let other_txn = other.env.read_txn()?;
+        let mut ours = self.utxos.remap_types::<Bytes, Bytes>().iter(&txn)?;
+        let mut theirs = other.utxos.remap_types::<Bytes, Bytes>().iter(&other_txn)?;
+        let pointer = |key: &[u8]| -> Result<TxOutputPointer> {
+            let pointer = rkyv::access::<ArchivedTxOutputPointer, rkyv::rancor::Error>(key)?;
+            Ok(rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(
+                pointer,
+            )?)
+        };
+
+        let mut diff = UtxoDiff::default();
+        let (mut a, mut b) = (ours.next().transpose()?, theirs.next().transpose()?);
+        loop {
+            match (a, b) {
+                (None, None) => break,
+                (Some((key, _)), None) => {
+                    diff.only_in_self.push(pointer(key)?);
+                    a = ours.next().transpose()?;
+                }
+                (None, Some((key, _))) => {
+                    diff.only_in_other.push(pointer(key)?);
+                    b = theirs.next().transpose()?;
+                }
+                (Some((key_a, txo_a)), Some((key_b, txo_b))) => match key_a.cmp(key_b) {
+                    std::cmp::Ordering::Less => {
+                        diff.only_in_self.push(pointer(key_a)?);
+                        a = ours.next().transpose()?;
+                    }
+                    std::cmp::Ordering::Greater => {
+                        diff.only_in_other.push(pointer(key_b)?);
+                        b = theirs.next().transpose()?;
+                    }
+                    std::cmp::Ordering::Equal => {
+                        if txo_a != txo_b {
+                            diff.changed.push(pointer(key_a)?);
+                        }
+                        a = ours.next().transpose()?;
+                        b = theirs.next().transpose()?;
+                    }
+                },
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Number of UTxOs held at the address. Walks the address' duplicates in `by_address`
+    /// without deserializing the pointers, so the cost is linear in the UTxOs at that address,
+    /// unlike `len()` on a table which reads LMDB's page stats in constant time.
+    pub fn utxo_count_by_address(&self, address: &Address) -> Result<u64> {
+        let txn = self.env.read_txn()?;
+        let Some(duplicates) = self
+            .by_address
+            .remap_data_type::<DecodeIgnore>()
+            .get_duplicates(&txn, address)?
+        else {
+            return Ok(0);
+        };
+        duplicates.try_fold(0, |count, res| res.map(|_| count + 1).map_err(Into::into))
+    }
+
+    /// Whether the address holds any UTxO. A single seek into `by_address`, so much cheaper
+    /// than counting or fetching the UTxOs when polling many addresses.
+    pub fn has_utxos(&self, address: &Address) -> Result<bool> {
+        let txn = self.env.read_txn()?;
+        Ok(self
+            .by_address
+            .remap_data_type::<DecodeIgnore>()
+            .get(&txn, address)?
+            .is_some())
+    }
+
+    /// The `n` UTxOs holding the most lovelace, in descending order. Backed by the `by_lovelace`
+    /// index, which costs an extra 8 byte key and pointer per UTxO.
+    pub fn top_by_lovelace(&self, n: usize) -> Result<Vec<(TxOutputPointer, TxOutput)>> {
+        let txn = self.env.read_txn()?;
+        self.by_lovelace
+            .rev_iter(&txn)?
+            .take(n)
+            .map(|res| {
+                let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(res?.1)?;
                 let txo = self.utxos.get(&txn, &pointer)?.context("missing txo")?;
                 let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
                 Ok((pointer, txo))
@@ -99,63 +710,256 @@ impl UtxoIndexer {
             .collect::<Result<Vec<_>>>()
     }
 
+    /// Indexed assets of the policy whose name starts with `prefix`, ordered by name. Useful for
+    /// finding CIP-68 reference/user token pairs or searching a collection by serial prefix.
+    pub fn assets_by_name_prefix(&self, policy: &Policy, prefix: &[u8]) -> Result<Vec<AssetId>> {
+        let txn = self.env.read_txn()?;
+        let mut assets: Vec<AssetId> = vec![];
+        for res in self
+            .by_asset_name
+            .remap_data_type::<DecodeIgnore>()
+            .prefix_iter(&txn, &asset_name_key(policy, prefix))?
+        {
+            let (key, _) = res?;
+            let name = &key[policy.len()..];
+            // Each UTxO holding the asset is a duplicate of the same key
+            if assets.last().and_then(|a| a.name.as_deref()) != Some(name) {
+                assets.push(AssetId::new(policy.clone(), Some(name.to_vec())));
+            }
+        }
+        self.query_limits.check(assets.len())?;
+        Ok(assets)
+    }
+
+    /// UTxOs holding every one of the assets, where an asset without a name matches any asset
+    /// of its policy. Finds the rarest asset by walking the holders of all of them in
+    /// `by_asset_name` in lockstep until one runs out, then checks each output of that one for
+    /// the rest. Both steps cost in proportion to the number of assets times the holders of the
+    /// rarest one, so a widely held asset in the set costs no more than a rare one. With no
+    /// assets, every UTxO matches.
+    pub fn utxos_with_all_assets(
+        &self,
+        assets: &[AssetId],
+    ) -> Result<Vec<(TxOutputPointer, TxOutput)>> {
+        if assets.is_empty() {
+            return self.utxos();
+        }
+        let txn = self.env.read_txn()?;
+        let mut holders = assets
+            .iter()
+            .map(|asset| self.asset_holders::<DecodeIgnore>(&txn, asset))
+            .collect::<Result<Vec<_>>>()?;
+        let rarest = 'walk: loop {
+            for (i, holders) in holders.iter_mut().enumerate() {
+                if holders.next().transpose()?.is_none() {
+                    break 'walk i;
+                }
+            }
+        };
+
+        let mut seen = HashSet::new();
+        let mut utxos = vec![];
+        for res in self.asset_holders::<RkyvCodec<TxOutputPointer>>(&txn, &assets[rarest])? {
+            let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(res?)?;
+            // An output holding several assets of a policy is a holder of each of them
+            if !seen.insert(pointer.clone()) {
+                continue;
+            }
+            let txo = self.utxos.get(&txn, &pointer)?.context("missing txo")?;
+            let txo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(txo)?;
+            if assets
+                .iter()
+                .all(|asset| txo.assets.iter().any(|held| asset == held))
+            {
+                utxos.push((pointer, txo));
+            }
+        }
+        self.query_limits.check(utxos.len())?;
+        Ok(utxos)
+    }
+
+    /// Pointers of the UTxOs holding the asset, or any asset of the policy if it has no name,
+    /// decoded with `DC`. Yields a UTxO once per matching asset it holds.
+    fn asset_holders<'t, DC>(
+        &self,
+        txn: &'t heed::RoTxn,
+        asset: &AssetId,
+    ) -> Result<Box<dyn Iterator<Item = heed::Result<DC::DItem>> + 't>>
+    where
+        DC: heed::BytesDecode<'t> + 'static,
+        DC::DItem: 't,
+    {
+        let by_asset_name = self.by_asset_name.remap_data_type::<DC>();
+        Ok(match &asset.name {
+            Some(name) => {
+                match by_asset_name.get_duplicates(txn, &asset_name_key(&asset.policy, name))? {
+                    Some(holders) => Box::new(holders.map(|res| res.map(|(_, pointer)| pointer))),
+                    None => Box::new(std::iter::empty()),
+                }
+            }
+            None => Box::new(
+                by_asset_name
+                    .prefix_iter(txn, asset.policy.as_slice())?
+                    .map(|res| res.map(|(_, pointer)| pointer)),
+            ),
+        })
+    }
+
     fn insert_output(
         &self,
         wtxn: &mut RwTxn,
         pointer: &TxOutputPointer,
         output: &TxOutput,
     ) -> Result<bool> {
-        // Filter based on address
-        if let Some(addresses) = &self.addresses
-            && addresses.contains(&output.address)
-        {
+        if !self.passes_filters(output) {
             return Ok(false);
         }
-        // Filter based on asset
-        if let Some(assets) = &self.assets
-            && !assets
+        self.put_output(wtxn, pointer, output)?;
+        Ok(true)
+    }
+
+    fn passes_filters(&self, output: &TxOutput) -> bool {
+        let address_matches = self
+            .addresses
+            .as_ref()
+            .map(|addresses| addresses.contains(&output.address));
+        let asset_matches = self.assets.as_ref().map(|whitelist| {
+            output
+                .assets
                 .iter()
-                .any(|whitelisted_asset| output.assets.iter().any(|a| whitelisted_asset == a))
-        {
-            return Ok(false);
+                .any(|asset| is_whitelisted(whitelist, asset))
+        });
+        match (address_matches, asset_matches) {
+            (Some(address), Some(asset)) => match self.filter_mode {
+                FilterMode::Any => address || asset,
+                FilterMode::All => address && asset,
+            },
+            (Some(matches), None) | (None, Some(matches)) => matches,
+            (None, None) => true,
         }
+    }
 
+    fn put_output(
+        &self,
+        wtxn: &mut RwTxn,
+        pointer: &TxOutputPointer,
+        output: &TxOutput,
+    ) -> Result<()> {
+        let stripped;
+        let output = match self.oversized_script(output) {
+            Some((hash, bytes)) => {
+                tracing::warn!(
+                    %pointer,
+                    script_hash = %hash,
+                    bytes,
+                    "reference script too large, indexing the output without it"
+                );
+                self.skipped_scripts.put(wtxn, &hash, &())?;
+                stripped = TxOutput {
+                    script_ref: None,
+                    ..output.clone()
+                };
+                &stripped
+            }
+            None => output,
+        };
         self.utxos.put(wtxn, pointer, output)?;
         self.by_address.put(wtxn, &output.address, pointer)?;
+        self.by_lovelace.put(wtxn, &output.lovelace, pointer)?;
         for asset in output.assets.iter() {
             self.by_asset.put(wtxn, &asset.into(), pointer)?;
+            self.by_asset_name
+                .put(wtxn, &asset_name_key(&asset.policy, &asset.name), pointer)?;
         }
-        Ok(true)
+        Ok(())
+    }
+
+    /// Hash and size of the output's reference script, if it exceeds `max_script_bytes`
+    fn oversized_script(&self, output: &TxOutput) -> Option<(ScriptHash, usize)> {
+        let script = output.script_ref.as_ref()?;
+        let bytes = match script {
+            Script::V1(bytes) | Script::V2(bytes) | Script::V3(bytes) => bytes.len(),
+            Script::Native(_) => return None,
+        };
+        if bytes <= self.max_script_bytes {
+            return None;
+        }
+        Some((script.hash()?, bytes))
     }
 
-    fn consume_input(&self, wtxn: &mut RwTxn, input: &TxOutputPointer) -> Result<bool> {
+    /// Removes the UTxO, returning it if it was indexed
+    fn consume_input(&self, wtxn: &mut RwTxn, input: &TxOutputPointer) -> Result<Option<TxOutput>> {
         let Some(utxo) = self.utxos.get(wtxn, input)? else {
-            return Ok(false);
+            return Ok(None);
         };
         let utxo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(utxo)?;
 
         self.utxos.delete(wtxn, input)?;
         self.by_address
             .delete_one_duplicate(wtxn, &utxo.address, input)?;
+        self.by_lovelace
+            .delete_one_duplicate(wtxn, &utxo.lovelace, input)?;
         for asset in utxo.assets.iter() {
             self.by_asset
                 .delete_one_duplicate(wtxn, &asset.into(), input)?;
+            self.by_asset_name.delete_one_duplicate(
+                wtxn,
+                &asset_name_key(&asset.policy, &asset.name),
+                input,
+            )?;
         }
-        Ok(true)
+        Ok(Some(utxo))
     }
 }
 
+impl UtxoIndexer {
+    /// Block scratch id of the UTxOs a block retained, kept apart from the spent ones. The
+    /// leading nul keeps it apart from indexer ids.
+    fn retained_scratch_id(&self) -> String {
+        format!("\0retained:{}", self.id)
+    }
+}
+
+/// UTxOs spent by a block, as stored in its block scratch by `insert_block`
+fn decode_spent(bytes: &[u8]) -> Result<Vec<(TxOutputPointer, TxOutput)>> {
+    from_unaligned_bytes(bytes)
+}
+
+fn asset_name_key(policy: &Policy, name: &[u8]) -> Vec<u8> {
+    [policy.as_slice(), name].concat()
+}
+
 impl Indexer for UtxoIndexer {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn insert_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> anyhow::Result<bool> {
+    fn config_fingerprint(&self) -> Vec<u8> {
+        self.fingerprint.clone()
+    }
+
+    fn insert_tx(&self, db: &Db, wtxn: &mut RwTxn, tx: &Tx) -> anyhow::Result<bool> {
         let mut added_some = false;
 
+        // Retain referenced UTxOs the filters skipped, before spending any inputs
+        if self.retain_referenced {
+            for input in tx.reference_inputs.iter() {
+                if self.utxos.get(wtxn, input)?.is_none()
+                    && let Some(output) = db.get_volatile_tx_output(wtxn, input)?
+                {
+                    self.put_output(wtxn, input, &output)?;
+                    self.retained.put(wtxn, input, &())?;
+                    added_some = true;
+                }
+            }
+        }
+
         // Mark consumed UTxOs as spent
         for input in tx.spent() {
-            added_some |= self.consume_input(wtxn, input)?;
+            if let Some(utxo) = self.consume_input(wtxn, input)? {
+                self.spent.put(wtxn, input, &utxo)?;
+                added_some = true;
+            }
         }
 
         // Add UTxOs
@@ -167,16 +971,8 @@ impl Indexer for UtxoIndexer {
         Ok(added_some)
     }
 
-    fn delete_tx(&self, db: &Db, wtxn: &mut RwTxn, tx: &Tx) -> anyhow::Result<()> {
-        // Restore consumed UTxOs
-        for input in tx.spent() {
-            let volatile_tx_output = db
-                .get_volatile_tx_output(wtxn, input)?
-                .context("missing tx output in volatile db")?;
-            self.insert_output(wtxn, input, &volatile_tx_output)?;
-        }
-
-        // Remove UTxOs
+    fn delete_tx(&self, _: &Db, wtxn: &mut RwTxn, tx: &Tx) -> anyhow::Result<()> {
+        // Remove UTxOs. Consumed UTxOs are restored by `delete_block`.
         for (index, _) in tx.unspent().enumerate() {
             let pointer = TxOutputPointer::new(tx.hash.clone(), index);
             self.consume_input(wtxn, &pointer)?;
@@ -185,10 +981,805 @@ impl Indexer for UtxoIndexer {
         Ok(())
     }
 
+    /// Keeps the UTxOs spent by the block in its scratch, since the txs that created them may
+    /// have left the volatile window by the time the block is rolled back
+    fn insert_block(&self, db: &Db, wtxn: &mut RwTxn, block: &Block) -> anyhow::Result<()> {
+        // Created and spent within the block, so there's nothing to restore
+        let created = block
+            .txs
+            .iter()
+            .map(|tx| &tx.hash)
+            .collect::<HashSet<&TxHash>>();
+        let mut spent = self
+            .spent
+            .iter(wtxn)?
+            .map(|res| {
+                let (pointer, utxo) = res?;
+                let pointer = rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(pointer)?;
+                let utxo = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(utxo)?;
+                Ok((pointer, utxo))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        spent.retain(|(pointer, _)| !created.contains(&pointer.hash));
+        self.spent.clear(wtxn)?;
+
+        if !spent.is_empty() {
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&spent)?;
+            db.put_block_scratch(wtxn, &self.id, &block.hash, &bytes)?;
+        }
+
+        let retained = self
+            .retained
+            .iter(wtxn)?
+            .map(|res| {
+                let (pointer, _) = res?;
+                Ok(rkyv::deserialize::<TxOutputPointer, rkyv::rancor::Error>(
+                    pointer,
+                )?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.retained.clear(wtxn)?;
+        if !retained.is_empty() {
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&retained)?;
+            db.put_block_scratch(wtxn, &self.retained_scratch_id(), &block.hash, &bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the UTxOs spent by the block, then drops those it retained
+    fn delete_block(&self, db: &Db, wtxn: &mut RwTxn, block: &VolatileBlock) -> anyhow::Result<()> {
+        if let Some(bytes) = db.get_block_scratch(wtxn, &self.id, &block.hash)? {
+            // Every spent UTxO was indexed, whether it passed the filters or was referenced
+            for (pointer, utxo) in decode_spent(&bytes)?.iter() {
+                self.put_output(wtxn, pointer, utxo)?;
+            }
+        }
+        // Including any the block also spent, which were only indexed for being retained
+        if let Some(bytes) = db.get_block_scratch(wtxn, &self.retained_scratch_id(), &block.hash)? {
+            for pointer in from_unaligned_bytes::<Vec<TxOutputPointer>>(&bytes)?.iter() {
+                self.consume_input(wtxn, pointer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn wants_scripts(&self) -> bool {
+        false
+    }
+
     fn clear(&self, wtxn: &mut RwTxn) -> anyhow::Result<()> {
         self.utxos.clear(wtxn)?;
         self.by_address.clear(wtxn)?;
         self.by_asset.clear(wtxn)?;
+        self.by_asset_name.clear(wtxn)?;
+        self.by_lovelace.clear(wtxn)?;
+        self.spent.clear(wtxn)?;
+        self.retained.clear(wtxn)?;
+        self.skipped_scripts.clear(wtxn)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+    use crate::indexer::IndexerList;
+    use crate::test_utils::{
+        MAX_ROLLBACK_BLOCKS, block, hash, output, pointer, temp_db, tx, utxo_addresses,
+        utxo_indexer,
+    };
+
+    #[test]
+    fn test_top_by_lovelace() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let outputs = vec![
+            output(b"a", 5),
+            output(b"b", 50),
+            output(b"c", 20),
+            output(b"d", 30),
+        ];
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], outputs)]),
+            block(
+                2,
+                20,
+                vec![tx(2, vec![pointer(1, 1)], vec![output(b"e", 1)])],
+            ),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let top = utxo.lock().unwrap().top_by_lovelace(2).unwrap();
+        let top = top
+            .into_iter()
+            .map(|(pointer, output)| (pointer.index, output.lovelace))
+            .collect::<Vec<_>>();
+        assert_eq!(top, [(3, 30), (2, 20)]);
+    }
+
+    #[test]
+    fn test_restore_utxo_created_beyond_volatile_window() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let mut blocks = vec![block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])])];
+        blocks.extend(
+            (2..=MAX_ROLLBACK_BLOCKS as u64 + 1).map(|number| block(number, number * 10, vec![])),
+        );
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        let rtxn = db.env.read_txn().unwrap();
+        assert!(
+            db.get_volatile_tx_output(&rtxn, &pointer(1, 0))
+                .unwrap()
+                .is_none()
+        );
+        drop(rtxn);
+
+        let number = MAX_ROLLBACK_BLOCKS as u64 + 2;
+        let spend = tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)]);
+        db.roll_forward(&indexers, &[block(number, number * 10, vec![spend])])
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"b"]);
+
+        let parent = number - 1;
+        db.roll_backward(
+            &indexers,
+            &Point::Specific(parent * 10, hash::<32>(parent).to_vec()),
+        )
+        .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+    }
+
+    #[test]
+    fn test_utxos_at() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(
+                2,
+                20,
+                vec![tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)])],
+            ),
+            block(3, 30, vec![tx(3, vec![], vec![output(b"c", 1)])]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        let addresses_at = |slot: u64| {
+            let mut utxos = utxo.lock().unwrap().utxos_at(&db, Slot(slot)).unwrap();
+            utxos.sort_by_key(|(_, txo)| txo.address.clone());
+            utxos
+                .into_iter()
+                .map(|(_, txo)| txo.address)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(addresses_at(30), [b"b", b"c"]);
+        assert_eq!(addresses_at(25), [b"b"]);
+        assert_eq!(addresses_at(10), [b"a"]);
+        assert!(addresses_at(0).is_empty());
+        // The live index is untouched
+        assert_eq!(utxo_addresses(&utxo), [b"b", b"c"]);
+
+        let blocks = (4..=MAX_ROLLBACK_BLOCKS as u64 + 3)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+        db.trim_volatile().unwrap();
+        let err = utxo.lock().unwrap().utxos_at(&db, Slot(10)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::db::Error>(),
+            Some(crate::db::Error::RollbackTooDeep {
+                requested_slot: 10,
+                oldest_slot: 30,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_raw_entries_round_trip() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let outputs = vec![output(b"a", 1), output(b"b", 2)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let (_replica_dir, replica_db) = temp_db();
+        let (replica, _) = utxo_indexer(&replica_db);
+        {
+            let primary = utxo.lock().unwrap();
+            let replica = replica.lock().unwrap();
+            let rtxn = db.env.read_txn().unwrap();
+            let mut wtxn = replica_db.env.write_txn().unwrap();
+            for entry in primary.raw_entries_iter(&rtxn).unwrap() {
+                let (key, value) = entry.unwrap();
+                assert!(replica.put_raw(&mut wtxn, key, value).unwrap());
+            }
+            assert!(replica.put_raw(&mut wtxn, b"garbage", b"garbage").is_err());
+            wtxn.commit().unwrap();
+        }
+        assert_eq!(utxo_addresses(&replica), [b"a", b"b"]);
+        assert!(replica.lock().unwrap().has_utxos(&b"b".to_vec()).unwrap());
+    }
+
+    #[test]
+    fn test_diff_against() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let (_other_dir, other_db) = temp_db();
+        let (other, other_indexers) = utxo_indexer(&other_db);
+        let outputs = vec![output(b"a", 1), output(b"b", 2), output(b"c", 3)];
+        db.roll_forward(
+            &indexers,
+            &[block(1, 10, vec![tx(1, vec![], outputs.clone())])],
+        )
+        .unwrap();
+        let diff = || {
+            utxo.lock()
+                .unwrap()
+                .diff_against(&other.lock().unwrap())
+                .unwrap()
+        };
+        assert_eq!(diff().only_in_self.len(), 3);
+
+        let mut diverged = outputs;
+        diverged[2].lovelace = 30;
+        other_db
+            .roll_forward(
+                &other_indexers,
+                &[block(1, 10, vec![tx(1, vec![], diverged)])],
+            )
+            .unwrap();
+        let spend = tx(2, vec![pointer(1, 1)], vec![output(b"d", 4)]);
+        db.roll_forward(&indexers, &[block(2, 20, vec![spend])])
+            .unwrap();
+        assert_eq!(
+            diff(),
+            UtxoDiff {
+                only_in_self: vec![pointer(2, 0)],
+                only_in_other: vec![pointer(1, 1)],
+                changed: vec![pointer(1, 2)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_limits() {
+        let (_dir, db) = temp_db();
+        let indexer = UtxoIndexerBuilder::new("utxo")
+            .query_limits(QueryLimits::max_results(1))
+            .build(&db.env)
+            .unwrap();
+        let utxo = Arc::new(Mutex::new(indexer));
+        let indexers: IndexerList = vec![utxo.clone() as Arc<Mutex<dyn Indexer + Send>>];
+
+        let outputs = vec![output(b"a", 1)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+        assert_eq!(utxo.lock().unwrap().utxos().unwrap().len(), 1);
+
+        let outputs = vec![output(b"b", 1)];
+        db.roll_forward(&indexers, &[block(2, 20, vec![tx(2, vec![], outputs)])])
+            .unwrap();
+        let err = utxo.lock().unwrap().utxos().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::db::Error>(),
+            Some(crate::db::Error::ResultTooLarge { found: 2, limit: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_utxos_iter() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let outputs = (1..=5).map(|n| output(b"a", n)).collect();
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+        let utxo = utxo.lock().unwrap().clone();
+        let all = utxo
+            .utxos()
+            .unwrap()
+            .into_iter()
+            .map(|(pointer, _)| pointer)
+            .collect::<Vec<_>>();
+        assert_eq!(all.len(), 5);
+
+        let mut iter = utxo.utxos_batched(2);
+        assert_eq!(iter.next().unwrap().unwrap().0, all[0]);
+        // Batches read after a commit see it
+        let spend = tx(2, vec![all[4].clone()], vec![]);
+        db.roll_forward(&indexers, &[block(2, 20, vec![spend])])
+            .unwrap();
+        let rest = iter
+            .map(|res| res.map(|(pointer, _)| pointer))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rest, all[1..4]);
+        assert_eq!(utxo.utxos_iter().count(), 4);
+    }
+
+    #[test]
+    fn test_balance() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let asset = |policy, name: &[u8], quantity| Asset {
+            policy: hash(policy),
+            name: name.to_vec(),
+            quantity,
+        };
+        let mut outputs = vec![output(b"a", 1), output(b"a", 2), output(b"a", 4)];
+        outputs[0].assets = vec![asset(7, b"x", 10), asset(7, b"y", 1)];
+        outputs[1].assets = vec![asset(7, b"x", 5), asset(8, b"x", 3)];
+        outputs.push(output(b"b", 8));
+        outputs[3].assets = vec![asset(7, b"x", 100)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let balance = utxo.lock().unwrap().balance(&b"a".to_vec()).unwrap();
+        let id = |policy, name: &[u8]| AssetId::new(hash(policy), Some(name.to_vec()));
+        assert_eq!(
+            balance,
+            Balance {
+                lovelace: 7,
+                assets: HashMap::from([(id(7, b"x"), 15), (id(7, b"y"), 1), (id(8, b"x"), 3)]),
+            }
+        );
+        assert_eq!(
+            utxo.lock().unwrap().balance(&b"c".to_vec()).unwrap(),
+            Balance::default()
+        );
+    }
+
+    #[test]
+    fn test_assets_of() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let assets = (0..50)
+            .map(|i| Asset {
+                policy: hash(i % 3),
+                name: vec![i as u8],
+                quantity: i as u64 + 1,
+            })
+            .collect::<Vec<_>>();
+        let mut outputs = vec![output(b"a", 1), output(b"a", 2)];
+        outputs[1].assets = assets.clone();
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let utxo = utxo.lock().unwrap();
+        assert_eq!(utxo.assets_of(&pointer(1, 1)).unwrap(), Some(assets));
+        assert_eq!(utxo.assets_of(&pointer(1, 0)).unwrap(), Some(vec![]));
+        assert_eq!(utxo.assets_of(&pointer(1, 2)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_block_effects() {
+        let (_dir, db) = temp_db();
+        let utxo = UtxoIndexerBuilder::new("utxo")
+            .address(b"a".to_vec())
+            .build(&db.env)
+            .unwrap();
+        let indexers: IndexerList =
+            vec![Arc::new(Mutex::new(utxo.clone())) as Arc<Mutex<dyn Indexer + Send>>];
+        let blocks = [
+            block(
+                1,
+                10,
+                vec![tx(1, vec![], vec![output(b"a", 1), output(b"b", 2)])],
+            ),
+            block(
+                2,
+                20,
+                vec![
+                    tx(
+                        2,
+                        vec![pointer(1, 0)],
+                        vec![output(b"a", 3), output(b"b", 4)],
+                    ),
+                    tx(3, vec![pointer(2, 0)], vec![output(b"a", 5)]),
+                ],
+            ),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let pointers = |utxos: &[(TxOutputPointer, TxOutput)]| {
+            utxos.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>()
+        };
+        let effects = utxo.block_effects(&db, &hash(1)).unwrap().unwrap();
+        assert_eq!(pointers(&effects.created), [pointer(1, 0)]);
+        assert!(effects.spent.is_empty());
+        let effects = utxo.block_effects(&db, &hash(2)).unwrap().unwrap();
+        assert_eq!(pointers(&effects.created), [pointer(2, 0), pointer(3, 0)]);
+        assert_eq!(pointers(&effects.spent), [pointer(1, 0), pointer(2, 0)]);
+        assert!(utxo.block_effects(&db, &hash(3)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_max_script_bytes() {
+        let (_dir, db) = temp_db();
+        let utxo = UtxoIndexerBuilder::new("utxo")
+            .max_script_bytes(4)
+            .build(&db.env)
+            .unwrap();
+        let indexers: IndexerList =
+            vec![Arc::new(Mutex::new(utxo.clone())) as Arc<Mutex<dyn Indexer + Send>>];
+        let large = Script::V2(vec![1; 5]);
+        let mut outputs = vec![output(b"a", 1), output(b"a", 2), output(b"a", 3)];
+        outputs[0].script_ref = Some(Script::V2(vec![1; 4]));
+        outputs[1].script_ref = Some(large.clone());
+        outputs[2].script_ref = Some(Script::Native(
+            crate::primitives::NativeScript::InvalidBefore(1),
+        ));
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let has_script = |index| {
+            let output = utxo.utxo(&pointer(1, index)).unwrap().unwrap();
+            output.script_ref.is_some()
+        };
+        assert!(has_script(0));
+        assert!(!has_script(1));
+        assert!(has_script(2));
+        assert_eq!(utxo.skipped_scripts().unwrap(), [large.hash().unwrap()]);
+    }
+
+    #[test]
+    fn test_has_utxos() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let outputs = vec![output(b"a", 1), output(b"a", 2)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+        let has_utxos = |address: &[u8]| utxo.lock().unwrap().has_utxos(&address.to_vec()).unwrap();
+        assert!(has_utxos(b"a"));
+        assert!(!has_utxos(b"b"));
+
+        let spend = tx(2, vec![pointer(1, 0), pointer(1, 1)], vec![]);
+        db.roll_forward(&indexers, &[block(2, 20, vec![spend])])
+            .unwrap();
+        assert!(!has_utxos(b"a"));
+    }
+
+    #[test]
+    fn test_asset_whitelist() {
+        let asset = |policy: u64, name: &[u8]| Asset {
+            policy: hash(policy),
+            name: name.to_vec(),
+            quantity: 1,
+        };
+        // Wildcard wins regardless of order
+        let whitelist = asset_whitelist(vec![
+            AssetId::new(hash(1), Some(b"a".to_vec())),
+            AssetId::new(hash(1), None),
+            AssetId::new(hash(2), Some(b"a".to_vec())),
+            AssetId::new(hash(2), Some(b"b".to_vec())),
+            AssetId::new(hash(3), None),
+            AssetId::new(hash(3), Some(b"a".to_vec())),
+        ]);
+        assert!(is_whitelisted(&whitelist, &asset(1, b"z")));
+        assert!(is_whitelisted(&whitelist, &asset(2, b"b")));
+        assert!(!is_whitelisted(&whitelist, &asset(2, b"c")));
+        assert!(is_whitelisted(&whitelist, &asset(3, b"z")));
+        assert!(!is_whitelisted(&whitelist, &asset(4, b"a")));
+    }
+
+    #[test]
+    fn test_address_filter() {
+        let (_dir, db) = temp_db();
+        let utxo = UtxoIndexerBuilder::new("utxo")
+            .address(b"kept".to_vec())
+            .build(&db.env)
+            .unwrap();
+        let utxo = Arc::new(Mutex::new(utxo));
+        let indexers: IndexerList = vec![utxo.clone() as Arc<Mutex<dyn Indexer + Send>>];
+        let outputs = vec![output(b"kept", 1), output(b"dropped", 2)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        assert_eq!(utxo_addresses(&utxo), [b"kept"]);
+        let utxo = utxo.lock().unwrap();
+        assert!(utxo.has_utxos(&b"kept".to_vec()).unwrap());
+        assert!(!utxo.has_utxos(&b"dropped".to_vec()).unwrap());
+        assert_eq!(utxo.utxo_count_by_address(&b"dropped".to_vec()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_filter_mode() {
+        let policy = hash::<28>(1);
+        let mut with_asset = output(b"other", 1);
+        with_asset.assets = vec![Asset {
+            policy: policy.clone(),
+            name: b"a".to_vec(),
+            quantity: 1,
+        }];
+        for (mode, expected) in [
+            (FilterMode::Any, vec![b"kept".to_vec(), b"other".to_vec()]),
+            (FilterMode::All, vec![]),
+        ] {
+            let (_dir, db) = temp_db();
+            let utxo = UtxoIndexerBuilder::new("utxo")
+                .address(b"kept".to_vec())
+                .asset(AssetId::new(policy.clone(), None))
+                .filter_mode(mode)
+                .build(&db.env)
+                .unwrap();
+            let utxo = Arc::new(Mutex::new(utxo));
+            let indexers: IndexerList = vec![utxo.clone() as Arc<Mutex<dyn Indexer + Send>>];
+            // Each output only passes one of the filters
+            let outputs = vec![output(b"kept", 1), with_asset.clone(), output(b"x", 1)];
+            db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+                .unwrap();
+            let mut addresses = utxo_addresses(&utxo);
+            addresses.sort();
+            assert_eq!(addresses, expected, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn test_utxos_by_address() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let outputs = vec![output(b"a", 1), output(b"b", 2), output(b"a", 3)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let utxo = utxo.lock().unwrap();
+        let mut utxos = utxo.utxos_by_address(&b"a".to_vec()).unwrap();
+        utxos.sort_by_key(|(pointer, _)| pointer.index);
+        assert_eq!(utxos.len(), 2);
+        assert_eq!((utxos[0].0.index, utxos[0].1.lovelace), (0, 1));
+        assert_eq!((utxos[1].0.index, utxos[1].1.lovelace), (2, 3));
+        assert!(utxo.utxos_by_address(&b"c".to_vec()).unwrap().is_empty());
+
+        // A dangling index entry errors instead of being skipped
+        let mut wtxn = db.env.write_txn().unwrap();
+        utxo.utxos.delete(&mut wtxn, &pointer(1, 0)).unwrap();
+        wtxn.commit().unwrap();
+        let err = utxo.utxos_by_address(&b"a".to_vec()).unwrap_err();
+        assert!(err.to_string().contains("missing from the UTxO table"));
+    }
+
+    #[test]
+    fn test_config_fingerprint() {
+        let (dir, db) = temp_db();
+        let open = |db: &Db, assets: Vec<AssetId>| -> IndexerList {
+            let builder = assets
+                .into_iter()
+                .fold(UtxoIndexerBuilder::new("utxo"), UtxoIndexerBuilder::asset);
+            let indexer = builder.build(&db.env).unwrap();
+            vec![Arc::new(Mutex::new(indexer)) as Arc<Mutex<dyn Indexer + Send>>]
+        };
+        let (a, b) = (
+            AssetId::new(hash(1), Some(b"a".to_vec())),
+            AssetId::new(hash(2), None),
+        );
+        let indexers = open(&db, vec![a.clone(), b.clone()]);
+        db.roll_forward(&indexers, &[block(1, 10, vec![])]).unwrap();
+        drop((db, indexers));
+
+        // The same filters in a different order
+        let db = Db::new(dir.path().to_str().unwrap(), MAX_ROLLBACK_BLOCKS).unwrap();
+        let indexers = open(&db, vec![b.clone(), a.clone(), b.clone()]);
+        db.roll_forward(&indexers, &[block(2, 20, vec![])]).unwrap();
+        drop((db, indexers));
+
+        // Different filters, or none at all
+        for assets in [vec![a.clone()], vec![]] {
+            let db = Db::new(dir.path().to_str().unwrap(), MAX_ROLLBACK_BLOCKS).unwrap();
+            let indexers = open(&db, assets);
+            let err = db
+                .roll_forward(&indexers, &[block(3, 30, vec![])])
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<crate::db::Error>(),
+                Some(crate::db::Error::IndexerConfigChanged { id }) if id == "utxo"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_retain_referenced() {
+        let (_dir, db) = temp_db();
+        let token = AssetId::new(hash(1), None);
+        let indexer = UtxoIndexerBuilder::new("utxo")
+            .asset(token)
+            .retain_referenced(true)
+            .build(&db.env)
+            .unwrap();
+        let utxo = Arc::new(Mutex::new(indexer));
+        let indexers: IndexerList = vec![utxo.clone() as Arc<Mutex<dyn Indexer + Send>>];
+
+        let mut with_token = output(b"a", 1);
+        with_token.assets = vec![Asset {
+            policy: hash(1),
+            name: b"token".to_vec(),
+            quantity: 1,
+        }];
+        // The script UTxO isn't whitelisted, but is referenced by a later tx
+        let create = tx(1, vec![], vec![with_token, output(b"script", 1)]);
+        let mut reference = tx(2, vec![pointer(1, 0)], vec![output(b"b", 1)]);
+        reference.reference_inputs = vec![pointer(1, 1)];
+        let spend = tx(3, vec![pointer(1, 1)], vec![]);
+        db.roll_forward(
+            &indexers,
+            &[block(1, 10, vec![create]), block(2, 20, vec![reference])],
+        )
+        .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"script"]);
+
+        // Restored when the block spending it is rolled back
+        db.roll_forward(&indexers, &[block(3, 30, vec![spend])])
+            .unwrap();
+        assert!(utxo_addresses(&utxo).is_empty());
+        let addresses_at = |slot| {
+            let utxos = utxo.lock().unwrap().utxos_at(&db, Slot(slot)).unwrap();
+            utxos
+                .into_iter()
+                .map(|(_, txo)| txo.address)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(addresses_at(20), [b"script"]);
+        assert_eq!(addresses_at(10), [b"a"]);
+        db.roll_backward(&indexers, &Point::Specific(20, hash::<32>(2).to_vec()))
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"script"]);
+
+        // Dropped again along with the block that referenced it
+        db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"a"]);
+    }
+
+    #[test]
+    fn test_assets_by_name_prefix() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let (policy, other_policy) = (hash::<28>(1), hash::<28>(2));
+        let with_asset = |policy: &Policy, name: &[u8]| {
+            let mut txo = output(b"a", 1);
+            txo.assets = vec![Asset {
+                policy: policy.clone(),
+                name: name.to_vec(),
+                quantity: 1,
+            }];
+            txo
+        };
+        let outputs = vec![
+            with_asset(&policy, b"nft2"),
+            with_asset(&policy, b"nft1"),
+            with_asset(&policy, b"nft1"),
+            with_asset(&policy, b"other"),
+            with_asset(&other_policy, b"nft3"),
+        ];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let names = |prefix: &[u8]| {
+            let assets = utxo
+                .lock()
+                .unwrap()
+                .assets_by_name_prefix(&policy, prefix)
+                .unwrap();
+            assets
+                .into_iter()
+                .map(|asset| {
+                    assert_eq!(asset.policy, policy);
+                    asset.name.unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(b"nft"), [b"nft1", b"nft2"]);
+        assert_eq!(names(b""), [&b"nft1"[..], b"nft2", b"other"]);
+        assert!(names(b"x").is_empty());
+
+        // Still indexed while any UTxO holds it
+        let spend = tx(2, vec![pointer(1, 1), pointer(1, 0)], vec![]);
+        db.roll_forward(&indexers, &[block(2, 20, vec![spend])])
+            .unwrap();
+        assert_eq!(names(b"nft"), [b"nft1"]);
+    }
+
+    #[test]
+    fn test_utxos_with_all_assets() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        let (collateral, governance) = (hash::<28>(1), hash::<28>(2));
+        let holding = |assets: &[(&Policy, &[u8])]| {
+            let mut txo = output(b"a", 1);
+            txo.assets = assets
+                .iter()
+                .map(|(policy, name)| Asset {
+                    policy: (*policy).clone(),
+                    name: name.to_vec(),
+                    quantity: 1,
+                })
+                .collect();
+            txo
+        };
+        let outputs = vec![
+            holding(&[(&collateral, b"c"), (&governance, b"g1")]),
+            holding(&[(&collateral, b"c")]),
+            holding(&[(&governance, b"g1"), (&governance, b"g2")]),
+            holding(&[(&collateral, b"c"), (&governance, b"g2")]),
+            holding(&[]),
+        ];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+
+        let utxo = utxo.lock().unwrap();
+        let query = |assets: &[AssetId]| {
+            let mut indexes = utxo
+                .utxos_with_all_assets(assets)
+                .unwrap()
+                .into_iter()
+                .map(|(pointer, _)| pointer.index)
+                .collect::<Vec<_>>();
+            indexes.sort();
+            // Same as checking every UTxO
+            let mut naive = utxo
+                .utxos()
+                .unwrap()
+                .into_iter()
+                .filter(|(_, txo)| {
+                    assets
+                        .iter()
+                        .all(|asset| txo.assets.iter().any(|held| asset == held))
+                })
+                .map(|(pointer, _)| pointer.index)
+                .collect::<Vec<_>>();
+            naive.sort();
+            assert_eq!(indexes, naive);
+            indexes
+        };
+        let c = AssetId::new(collateral.clone(), Some(b"c".to_vec()));
+        let g1 = AssetId::new(governance.clone(), Some(b"g1".to_vec()));
+        let any_governance = AssetId::new(governance.clone(), None);
+        assert_eq!(query(&[c.clone(), g1.clone()]), [0]);
+        assert_eq!(query(&[c.clone(), any_governance.clone()]), [0, 3]);
+        // Holding two assets of the policy doesn't list the UTxO twice
+        assert_eq!(query(&[any_governance]), [0, 2, 3]);
+        assert_eq!(query(&[c, AssetId::new(hash(3), None)]), [] as [u64; 0]);
+        assert_eq!(query(&[]).len(), 5);
+    }
+
+    #[test]
+    fn test_concurrent_reads_during_resizes() {
+        let (_dir, db) = temp_db();
+        let (utxo, indexers) = utxo_indexer(&db);
+        // Readers don't go through the mutex, so they really run alongside the writer
+        let reader = utxo.lock().unwrap().clone();
+        let done = AtomicBool::new(false);
+        let blocks = 50;
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut seen = 0;
+                    while !done.load(Ordering::SeqCst) {
+                        let count = reader.utxos().unwrap().len();
+                        assert!(count >= seen && count <= blocks);
+                        seen = count;
+                    }
+                });
+            }
+            // Stop the readers even if writing fails, so the scope doesn't hang
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                for number in 1..=blocks as u64 {
+                    let txs = vec![tx(number, vec![], vec![output(b"a", number)])];
+                    db.roll_forward(&indexers, &[block(number, number * 10, txs)])
+                        .unwrap();
+                    if number % 10 == 0 {
+                        db.env.grow().unwrap();
+                    }
+                }
+            }));
+            done.store(true, Ordering::SeqCst);
+            res.unwrap();
+        });
+        assert_eq!(reader.utxos().unwrap().len(), blocks);
+    }
+}
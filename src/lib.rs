@@ -1,10 +1,33 @@
+mod archive;
 pub mod db;
 mod indexer;
+mod metrics;
+mod network;
 pub mod primitives;
 mod sync;
+#[cfg(test)]
+mod test_utils;
+#[cfg(feature = "utxorpc")]
+mod utxorpc;
 mod writer;
 
+pub use archive::{ArchiveRecord, ArchiveRotation, ArchiveSink, segment_path};
 pub use db::Db;
-pub use indexer::Indexer;
-pub use indexer::utxo::{UtxoIndexer, UtxoIndexerBuilder};
+pub use indexer::asset_history::AssetHistoryIndexer;
+pub use indexer::failed_tx::{FailedTx, FailedTxIndexer};
+pub use indexer::governance::{CastVote, GovernanceIndexer};
+pub use indexer::metadata::MetadataIndexer;
+pub use indexer::reward_account::{RewardAccountIndexer, StakeEvent};
+#[cfg(feature = "sql")]
+pub use indexer::sql::{SqlSink, UtxoChange, UtxoRow, UtxoSqlIndexer};
+pub use indexer::stake_distribution::StakeDistributionIndexer;
+pub use indexer::utxo::{
+    Balance, BlockEffects, FilterMode, UtxoDiff, UtxoIndexer, UtxoIndexerBuilder, UtxoIter,
+};
+pub use indexer::{Indexer, QueryLimits};
+pub use metrics::{RollbackStats, RollbackSummary, SyncMetrics, SyncPhase, TimingStats};
+pub use network::Network;
 pub use sync::Sync;
+#[cfg(feature = "utxorpc")]
+pub use utxorpc::UtxoRpcService;
+pub use writer::WriterHandle;
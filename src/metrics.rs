@@ -0,0 +1,230 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Phases of applying a block, timed separately to tell a slow indexer apart from a slow disk
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncPhase {
+    /// Decoding the block CBOR, once per block
+    Decode,
+    /// Passing the block to the indexers and updating the volatile db, once per block
+    Indexers,
+    /// Committing the write transaction, once per batch of blocks
+    Commit,
+    /// Growing the LMDB map if needed, once per batch of blocks
+    Resize,
+    /// Dropping blocks that left the volatile window, once per checkpoint of the writer
+    Trim,
+    /// Fsyncing the database and archive, once per checkpoint of the writer. Long stalls here
+    /// are the disk, not the node.
+    Persist,
+}
+
+/// Sync statistics of a [`Db`](crate::Db). Timings of the sync phases are only recorded when
+/// enabled with [`DbOptions::timings()`].
+///
+/// [`DbOptions::timings()`]: crate::db::DbOptions::timings()
+#[derive(Debug, Default)]
+pub struct SyncMetrics {
+    decode: Timing,
+    indexers: Timing,
+    commit: Timing,
+    resize: Timing,
+    trim: Timing,
+    persist: Timing,
+    rollbacks: AtomicU64,
+    rolled_back_blocks: AtomicU64,
+    max_rollback_blocks: AtomicU64,
+}
+
+impl SyncMetrics {
+    pub fn rollbacks(&self) -> RollbackStats {
+        RollbackStats {
+            count: self.rollbacks.load(Ordering::Relaxed),
+            blocks: self.rolled_back_blocks.load(Ordering::Relaxed),
+            max_blocks: self.max_rollback_blocks.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn record_rollback(&self, summary: &RollbackSummary) {
+        let blocks = summary.blocks as u64;
+        self.rollbacks.fetch_add(1, Ordering::Relaxed);
+        self.rolled_back_blocks.fetch_add(blocks, Ordering::Relaxed);
+        self.max_rollback_blocks
+            .fetch_max(blocks, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self, phase: SyncPhase) -> TimingStats {
+        self.timing(phase).stats()
+    }
+
+    pub(crate) fn record(&self, phase: SyncPhase, duration: Duration) {
+        self.timing(phase).record(duration)
+    }
+
+    fn timing(&self, phase: SyncPhase) -> &Timing {
+        match phase {
+            SyncPhase::Decode => &self.decode,
+            SyncPhase::Indexers => &self.indexers,
+            SyncPhase::Commit => &self.commit,
+            SyncPhase::Resize => &self.resize,
+            SyncPhase::Trim => &self.trim,
+            SyncPhase::Persist => &self.persist,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Timing {
+    fn record(&self, duration: Duration) {
+        let nanos = duration.as_nanos() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> TimingStats {
+        TimingStats {
+            count: self.count.load(Ordering::Relaxed),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Aggregated timings of a phase since the database was opened
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl TimingStats {
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+}
+
+/// Aggregated rollbacks since the database was opened
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RollbackStats {
+    pub count: u64,
+    /// Total number of blocks reverted
+    pub blocks: u64,
+    /// Deepest rollback, in blocks
+    pub max_blocks: u64,
+}
+
+/// What a rollback reverted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollbackSummary {
+    /// Slot of the tip before rolling back
+    pub from_slot: u64,
+    /// Slot rolled back to, 0 for the origin
+    pub to_slot: u64,
+    /// Number of blocks reverted
+    pub blocks: usize,
+    /// Number of txs and datums passed to the indexers to delete. Not counted when rolling
+    /// back to the origin, where the indexers are cleared instead.
+    pub txs: usize,
+    pub datums: usize,
+    /// Ids of the indexers that reverted blocks, empty if nothing was reverted
+    pub indexers: Vec<String>,
+}
+
+impl RollbackSummary {
+    /// Depth of the rollback in slots
+    pub fn slots(&self) -> u64 {
+        self.from_slot.saturating_sub(self.to_slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::network::miniprotocols::Point;
+
+    use super::*;
+    use crate::db::{Db, DbOptions};
+    use crate::test_utils::{block, hash, output, temp_db, tx, utxo_indexer};
+
+    #[test]
+    fn test_timings() {
+        let metrics = SyncMetrics::default();
+        metrics.record(SyncPhase::Commit, Duration::from_millis(1));
+        metrics.record(SyncPhase::Commit, Duration::from_millis(3));
+        let stats = metrics.stats(SyncPhase::Commit);
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.mean(), Duration::from_millis(2));
+        assert_eq!(stats.max, Duration::from_millis(3));
+        assert_eq!(metrics.stats(SyncPhase::Decode), TimingStats::default());
+    }
+
+    #[test]
+    fn test_roll_forward_timings() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DbOptions::new(10).timings(true);
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(2, 20, vec![]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let metrics = db.metrics();
+        assert_eq!(metrics.stats(SyncPhase::Indexers).count, 2);
+        assert_eq!(metrics.stats(SyncPhase::Commit).count, 1);
+        assert_eq!(metrics.stats(SyncPhase::Resize).count, 1);
+    }
+
+    #[test]
+    fn test_rollback_summary() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![]),
+            block(2, 20, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(3, 30, vec![tx(2, vec![], vec![output(b"b", 1)])]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let point = Point::Specific(10, hash::<32>(1).to_vec());
+        let summary = db.roll_backward(&indexers, &point).unwrap();
+        assert_eq!(
+            summary,
+            RollbackSummary {
+                from_slot: 30,
+                to_slot: 10,
+                blocks: 2,
+                txs: 2,
+                datums: 0,
+                indexers: vec!["utxo".to_string()],
+            }
+        );
+        assert_eq!(summary.slots(), 20);
+
+        // Nothing to revert
+        let summary = db.roll_backward(&indexers, &point).unwrap();
+        assert_eq!((summary.blocks, summary.indexers.len()), (0, 0));
+
+        let summary = db.roll_backward(&indexers, &Point::Origin).unwrap();
+        assert_eq!((summary.from_slot, summary.blocks), (10, 1));
+        assert_eq!(
+            db.metrics().rollbacks(),
+            RollbackStats {
+                count: 3,
+                blocks: 3,
+                max_blocks: 2,
+            }
+        );
+    }
+}
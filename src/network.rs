@@ -0,0 +1,87 @@
+/// Cardano network the indexer is following. Ties together the handshake magic, the bech32
+/// prefixes for addresses and the slot -> time conversion, which all differ per network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Network {
+    Mainnet,
+    Preprod,
+    Preview,
+}
+
+impl Network {
+    /// Network magic used during the Node-to-Node handshake
+    pub const fn magic(&self) -> u64 {
+        match self {
+            Network::Mainnet => 764824073,
+            Network::Preprod => 1,
+            Network::Preview => 2,
+        }
+    }
+
+    pub fn from_magic(magic: u64) -> Option<Self> {
+        [Network::Mainnet, Network::Preprod, Network::Preview]
+            .into_iter()
+            .find(|network| network.magic() == magic)
+    }
+
+    /// Network id as encoded in the header byte of shelley addresses
+    pub const fn id(&self) -> u8 {
+        match self {
+            Network::Mainnet => 1,
+            Network::Preprod | Network::Preview => 0,
+        }
+    }
+
+    /// Bech32 human-readable prefix for payment addresses
+    pub const fn address_hrp(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "addr",
+            Network::Preprod | Network::Preview => "addr_test",
+        }
+    }
+
+    /// Bech32 human-readable prefix for reward (stake) addresses
+    pub const fn stake_hrp(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "stake",
+            Network::Preprod | Network::Preview => "stake_test",
+        }
+    }
+
+    /// Converts a shelley-era (or later) slot into a unix timestamp in seconds.
+    /// Slots before the shelley hard-fork were 20s long and aren't supported.
+    pub const fn slot_to_unix_time(&self, slot: u64) -> u64 {
+        let (shelley_slot, shelley_time) = self.shelley_start();
+        shelley_time + slot.saturating_sub(shelley_slot)
+    }
+
+    /// Converts a unix timestamp in seconds into a shelley-era (or later) slot
+    pub const fn unix_time_to_slot(&self, unix_time: u64) -> u64 {
+        let (shelley_slot, shelley_time) = self.shelley_start();
+        shelley_slot + unix_time.saturating_sub(shelley_time)
+    }
+
+    /// (slot, unix time) of the first shelley slot, after which slots are 1s long
+    const fn shelley_start(&self) -> (u64, u64) {
+        match self {
+            Network::Mainnet => (4492800, 1596059091),
+            Network::Preprod => (86400, 1655769600),
+            Network::Preview => (0, 1666656000),
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Preprod => "preprod",
+            Network::Preview => "preview",
+        }
+        .fmt(f)
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use super::*;
+use crate::Network;
+
+/// Payment or stake credential
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[rkyv(compare(PartialEq))]
+pub enum Credential {
+    Key(AddressKeyHash),
+    Script(ScriptHash),
+}
+
+impl Credential {
+    /// Reward account bytes for the credential: a header byte (`0xe_` for key hashes, `0xf_`
+    /// for script hashes, with the network id in the low nibble) followed by the hash
+    pub fn to_reward_account(&self, network: Network) -> Vec<u8> {
+        let (header, hash) = match self {
+            Credential::Key(hash) => (0xe0, hash),
+            Credential::Script(hash) => (0xf0, hash),
+        };
+        let mut bytes = Vec::with_capacity(1 + hash.len());
+        bytes.push(header | network.id());
+        bytes.extend_from_slice(hash.as_slice());
+        bytes
+    }
+
+    /// Bech32 reward address, i.e. `stake1...` or `stake_test1...`
+    pub fn to_stake_bech32(&self, network: Network) -> Result<String> {
+        reward_account_to_bech32(&self.to_reward_account(network))
+    }
+}
+
+/// Extracts the stake credential from reward account bytes, as found in withdrawals and
+/// certificates
+pub fn reward_account_to_credential(bytes: &[u8]) -> Result<Credential> {
+    let (header, hash) = bytes.split_first().context("empty reward account")?;
+    let hash = Hash(
+        hash.try_into()
+            .with_context(|| format!("invalid reward account length: {}", bytes.len()))?,
+    );
+    match header >> 4 {
+        0b1110 => Ok(Credential::Key(hash)),
+        0b1111 => Ok(Credential::Script(hash)),
+        _ => anyhow::bail!("not a reward account, header: {header:#04x}"),
+    }
+}
+
+/// Stake credential of a base address, i.e. the credential its funds are delegated with.
+/// `None` for the other kinds of addresses, which have no stake credential (enterprise and
+/// byron addresses) or only reference one through a pointer.
+pub fn address_stake_credential(address: &[u8]) -> Option<Credential> {
+    let header = *address.first()?;
+    let hash = Hash(address.get(29..57)?.try_into().ok()?);
+    match header >> 4 {
+        0b0000 | 0b0001 => Some(Credential::Key(hash)),
+        0b0010 | 0b0011 => Some(Credential::Script(hash)),
+        _ => None,
+    }
+}
+
+/// Encodes reward account bytes as a bech32 reward address, with the `stake` or `stake_test`
+/// prefix depending on the network id in the header
+pub fn reward_account_to_bech32(bytes: &[u8]) -> Result<String> {
+    reward_account_to_credential(bytes)?;
+    Ok(pallas::ledger::addresses::Address::from_bytes(bytes)?.to_bech32()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CIP-19 test vectors
+    const STAKE_KEY_HASH: &str = "337b62cfff6403a06a3acbc34f8c46003c69fe79a3628cefa9c47251";
+    const SCRIPT_HASH: &str = "c37b1b5dc0669f1d3c61a6fddb2e8fde96be87b881c60bce8e8d542f";
+
+    fn hash(hash_hex: &str) -> Hash<28> {
+        Hash(hex::decode(hash_hex).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn test_reward_account_to_credential() {
+        let vectors = [
+            (
+                format!("e1{STAKE_KEY_HASH}"),
+                Credential::Key(hash(STAKE_KEY_HASH)),
+                Network::Mainnet,
+                "stake1uyehkck0lajq8gr28t9uxnuvgcqrc6070x3k9r8048z8y5gh6ffgw",
+            ),
+            (
+                format!("f1{SCRIPT_HASH}"),
+                Credential::Script(hash(SCRIPT_HASH)),
+                Network::Mainnet,
+                "stake178phkx6acpnf78fuvxn0mkew3l0fd058hzquvz7w36x4gtcccycj5",
+            ),
+            (
+                format!("e0{STAKE_KEY_HASH}"),
+                Credential::Key(hash(STAKE_KEY_HASH)),
+                Network::Preprod,
+                "stake_test1uqehkck0lajq8gr28t9uxnuvgcqrc6070x3k9r8048z8y5gssrtvn",
+            ),
+            (
+                format!("f0{SCRIPT_HASH}"),
+                Credential::Script(hash(SCRIPT_HASH)),
+                Network::Preview,
+                "stake_test17rphkx6acpnf78fuvxn0mkew3l0fd058hzquvz7w36x4gtcljw6kf",
+            ),
+        ];
+        for (reward_account, credential, network, bech32) in vectors {
+            let reward_account = hex::decode(reward_account).unwrap();
+            assert_eq!(
+                reward_account_to_credential(&reward_account).unwrap(),
+                credential
+            );
+            assert_eq!(credential.to_reward_account(network), reward_account);
+            assert_eq!(reward_account_to_bech32(&reward_account).unwrap(), bech32);
+            assert_eq!(credential.to_stake_bech32(network).unwrap(), bech32);
+        }
+    }
+
+    #[test]
+    fn test_address_stake_credential() {
+        let payment = "9493315cd92eb5d8c4304e67b7e16ae36d61d34502694657811a2c8e";
+        let base =
+            |header: &str| hex::decode(format!("{header}{payment}{STAKE_KEY_HASH}")).unwrap();
+        assert_eq!(
+            address_stake_credential(&base("01")),
+            Some(Credential::Key(hash(STAKE_KEY_HASH)))
+        );
+        assert_eq!(
+            address_stake_credential(&base("31")),
+            Some(Credential::Script(hash(STAKE_KEY_HASH)))
+        );
+        let enterprise = hex::decode(format!("61{payment}")).unwrap();
+        assert_eq!(address_stake_credential(&enterprise), None);
+    }
+
+    #[test]
+    fn test_reward_account_rejects_payment_address() {
+        let payment = hex::decode(format!("61{STAKE_KEY_HASH}")).unwrap();
+        assert!(reward_account_to_credential(&payment).is_err());
+        assert!(reward_account_to_credential(&[0xe1]).is_err());
+    }
+}
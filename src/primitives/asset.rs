@@ -6,7 +6,7 @@ use super::*;
 pub type Policy = Hash<28>;
 pub type AssetName = Vec<u8>;
 
-#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[rkyv(compare(PartialEq))]
 pub struct AssetId {
     pub policy: Policy,
@@ -51,22 +51,32 @@ pub struct Mint {
 }
 
 impl Mint {
+    /// Skips (with a warning) assets without a mint amount, which only happens when passed
+    /// output assets, rather than panicking the writer
     pub fn from_assets(assets: Vec<MultiEraPolicyAssets>) -> Vec<Self> {
         assets
             .iter()
             .flat_map(|a| a.assets())
-            .map(|a| Mint {
-                policy: a.policy().into(),
-                name: a.name().to_vec(),
-                quantity: a
-                    .mint_coin()
-                    .expect("missing mint amount in asset. is this an output asset?"),
+            .filter_map(|a| {
+                let Some(quantity) = a.mint_coin() else {
+                    tracing::warn!(
+                        policy = %a.policy(),
+                        name = hex::encode(a.name()),
+                        "missing mint amount in asset, skipping. is this an output asset?"
+                    );
+                    return None;
+                };
+                Some(Mint {
+                    policy: a.policy().into(),
+                    name: a.name().to_vec(),
+                    quantity,
+                })
             })
             .collect()
     }
 }
 
-#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
 #[rkyv(compare(PartialEq))]
 pub struct Asset {
     pub policy: Policy,
@@ -75,17 +85,57 @@ pub struct Asset {
 }
 
 impl Asset {
+    /// Skips (with a warning) assets without an output amount, which only happens when passed
+    /// mint assets, rather than panicking the writer
     pub fn from_assets(assets: Vec<MultiEraPolicyAssets>) -> Vec<Self> {
         assets
             .iter()
             .flat_map(|a| a.assets())
-            .map(|a| Asset {
-                policy: a.policy().into(),
-                name: a.name().to_vec(),
-                quantity: a
-                    .output_coin()
-                    .expect("missing output amount in asset. is this a mint asset?"),
+            .filter_map(|a| {
+                let Some(quantity) = a.output_coin() else {
+                    tracing::warn!(
+                        policy = %a.policy(),
+                        name = hex::encode(a.name()),
+                        "missing output amount in asset, skipping. is this a mint asset?"
+                    );
+                    return None;
+                };
+                Some(Asset {
+                    policy: a.policy().into(),
+                    name: a.name().to_vec(),
+                    quantity,
+                })
             })
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pallas::ledger::traverse::{Era, MultiEraOutput};
+
+    use super::*;
+
+    #[test]
+    fn test_maximal_asset_quantity() {
+        // {0: enterprise address, 1: [1 ada, {policy: {"max": u64::MAX}}]}
+        let cbor = hex::decode(format!(
+            "a200581d61{}01821a000f4240a1581c{}a1436d61781bffffffffffffffff",
+            "00".repeat(28),
+            "01".repeat(28),
+        ))
+        .unwrap();
+        let output = MultiEraOutput::decode(Era::Conway, &cbor).unwrap();
+
+        let assets = Asset::from_assets(output.value().assets());
+        assert_eq!(
+            assets,
+            [Asset {
+                policy: Hash([1; 28]),
+                name: b"max".to_vec(),
+                quantity: u64::MAX,
+            }]
+        );
+        assert!(Mint::from_assets(output.value().assets()).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use pallas::ledger::traverse::MultiEraBlock;
 use rkyv::{Archive, Deserialize, Serialize};
@@ -7,6 +7,29 @@ use super::*;
 
 pub type BlockHash = Hash<32>;
 
+/// Parts of a block that may be skipped while decoding, since extracting them is wasted work
+/// when no indexer reads them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Extract inline datums into [`Block::datums`]. When off, no datums are offered to the
+    /// indexers nor kept for [`Db::datum`](crate::Db::datum).
+    pub datums: bool,
+    /// Extract the witnessed scripts into [`Tx::scripts`] and [`Tx::native_scripts`]
+    pub scripts: bool,
+    /// Extract the auxiliary data metadata into [`Tx::metadata`]
+    pub metadata: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            datums: true,
+            scripts: true,
+            metadata: true,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Archive, Deserialize, Serialize)]
 pub struct Block {
     // TODO: epoch? requires genesis values
@@ -17,15 +40,22 @@ pub struct Block {
     pub size: usize,
 
     pub txs: Vec<Tx>,
-    pub datums: HashMap<DatumHash, Datum>,
+    /// Ordered by hash, so indexers see them in the same order on every run
+    pub datums: BTreeMap<DatumHash, Datum>,
 }
 
 impl From<&MultiEraBlock<'_>> for Block {
     fn from(block: &MultiEraBlock) -> Self {
+        Self::parse(block, ParseOptions::default())
+    }
+}
+
+impl Block {
+    pub fn parse(block: &MultiEraBlock, options: ParseOptions) -> Self {
         let mut txs = Vec::with_capacity(block.txs().len());
-        let mut datums = HashMap::new();
+        let mut datums = BTreeMap::new();
         for raw_tx in block.txs().iter() {
-            let (tx, tx_datums) = Tx::parse(raw_tx);
+            let (tx, tx_datums) = Tx::parse_with(raw_tx, options);
             datums.extend(tx_datums);
             txs.push(tx);
         }
@@ -0,0 +1,71 @@
+use pallas::ledger::primitives::{alonzo, conway};
+use pallas::ledger::traverse::{MultiEraCert, MultiEraTx};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use super::*;
+
+/// Certificate changing where a stake credential's funds are delegated. Registration alone
+/// isn't included, since it doesn't delegate to anything.
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub enum StakeCert {
+    /// Delegates to the pool, including Conway's combined registration and vote certificates
+    Delegation {
+        credential: Credential,
+        pool: PoolKeyHash,
+    },
+    /// Deregisters the credential, which ends its delegation
+    Deregistration { credential: Credential },
+}
+
+impl StakeCert {
+    /// Stake certificates of the tx in order, across all eras. Empty for txs that failed
+    /// phase-2 validation, since their certificates don't apply.
+    pub fn parse_all(tx: &MultiEraTx) -> Vec<Self> {
+        if !tx.is_valid() {
+            return vec![];
+        }
+        tx.certs().iter().filter_map(Self::parse).collect()
+    }
+
+    fn parse(cert: &MultiEraCert) -> Option<Self> {
+        if let Some(cert) = cert.as_alonzo() {
+            return match cert {
+                alonzo::Certificate::StakeDelegation(credential, pool) => Some(Self::Delegation {
+                    credential: credential.into(),
+                    pool: pool.into(),
+                }),
+                alonzo::Certificate::StakeDeregistration(credential) => {
+                    Some(Self::Deregistration {
+                        credential: credential.into(),
+                    })
+                }
+                _ => None,
+            };
+        }
+        match cert.as_conway()? {
+            conway::Certificate::StakeDelegation(credential, pool)
+            | conway::Certificate::StakeVoteDeleg(credential, pool, _)
+            | conway::Certificate::StakeRegDeleg(credential, pool, _)
+            | conway::Certificate::StakeVoteRegDeleg(credential, pool, _, _) => {
+                Some(Self::Delegation {
+                    credential: credential.into(),
+                    pool: pool.into(),
+                })
+            }
+            conway::Certificate::StakeDeregistration(credential)
+            | conway::Certificate::UnReg(credential, _) => Some(Self::Deregistration {
+                credential: credential.into(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn credential(&self) -> &Credential {
+        match self {
+            StakeCert::Delegation { credential, .. } | StakeCert::Deregistration { credential } => {
+                credential
+            }
+        }
+    }
+}
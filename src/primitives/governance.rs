@@ -0,0 +1,341 @@
+use pallas::ledger::primitives::StakeCredential;
+use pallas::ledger::primitives::conway;
+use pallas::ledger::traverse::MultiEraTx;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use super::*;
+
+pub type PoolKeyHash = Hash<28>;
+
+/// Conway governance content of a tx (CIP-1694). Always empty for earlier eras and for txs
+/// that failed phase-2 validation, since their certificates, votes and proposals don't apply.
+#[derive(Clone, Debug, Default, Archive, Deserialize, Serialize)]
+#[rkyv(compare(PartialEq))]
+pub struct Governance {
+    pub drep_certs: Vec<DRepCert>,
+    pub votes: Vec<VoteRecord>,
+    pub proposals: Vec<Proposal>,
+}
+
+impl Governance {
+    pub fn parse(tx: &MultiEraTx) -> Self {
+        let Some(conway_tx) = tx.as_conway() else {
+            return Self::default();
+        };
+        if !tx.is_valid() {
+            return Self::default();
+        }
+        let body = &conway_tx.transaction_body;
+
+        let drep_certs = body
+            .certificates
+            .iter()
+            .flat_map(|certs| certs.iter())
+            .filter_map(DRepCert::parse)
+            .collect();
+
+        let votes = body
+            .voting_procedures
+            .iter()
+            .flat_map(|procedures| procedures.iter())
+            .flat_map(|(voter, votes)| {
+                votes.iter().map(move |(action, procedure)| VoteRecord {
+                    voter: voter.into(),
+                    action: action.into(),
+                    vote: (&procedure.vote).into(),
+                })
+            })
+            .collect();
+
+        let tx_hash: TxHash = tx.hash().into();
+        let proposals = body
+            .proposal_procedures
+            .iter()
+            .flat_map(|proposals| proposals.iter())
+            .enumerate()
+            .map(|(index, proposal)| Proposal {
+                action: GovActionId {
+                    tx_hash: tx_hash.clone(),
+                    index: index as u32,
+                },
+                kind: (&proposal.gov_action).into(),
+                deposit: proposal.deposit,
+                reward_account: proposal.reward_account.to_vec(),
+            })
+            .collect();
+
+        Self {
+            drep_certs,
+            votes,
+            proposals,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.drep_certs.is_empty() && self.votes.is_empty() && self.proposals.is_empty()
+    }
+}
+
+/// Identifies a governance action by the tx that proposed it and its index among the tx's
+/// proposals
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[rkyv(compare(PartialEq))]
+pub struct GovActionId {
+    pub tx_hash: TxHash,
+    pub index: u32,
+}
+
+impl From<&conway::GovActionId> for GovActionId {
+    fn from(id: &conway::GovActionId) -> Self {
+        Self {
+            tx_hash: id.transaction_id.into(),
+            index: id.action_index,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub enum DRepCert {
+    Registration {
+        credential: Credential,
+        deposit: u64,
+    },
+    Deregistration {
+        credential: Credential,
+        refund: u64,
+    },
+    Update {
+        credential: Credential,
+    },
+}
+
+impl DRepCert {
+    fn parse(cert: &conway::Certificate) -> Option<Self> {
+        match cert {
+            conway::Certificate::RegDRepCert(credential, deposit, _) => Some(Self::Registration {
+                credential: credential.into(),
+                deposit: *deposit,
+            }),
+            conway::Certificate::UnRegDRepCert(credential, refund) => Some(Self::Deregistration {
+                credential: credential.into(),
+                refund: *refund,
+            }),
+            conway::Certificate::UpdateDRepCert(credential, _) => Some(Self::Update {
+                credential: credential.into(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn credential(&self) -> &Credential {
+        match self {
+            DRepCert::Registration { credential, .. }
+            | DRepCert::Deregistration { credential, .. }
+            | DRepCert::Update { credential } => credential,
+        }
+    }
+}
+
+impl From<&StakeCredential> for Credential {
+    fn from(credential: &StakeCredential) -> Self {
+        match credential {
+            StakeCredential::AddrKeyhash(hash) => Credential::Key(hash.into()),
+            StakeCredential::ScriptHash(hash) => Credential::Script(hash.into()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub enum Voter {
+    ConstitutionalCommittee(Credential),
+    DRep(Credential),
+    StakePool(PoolKeyHash),
+}
+
+impl From<&conway::Voter> for Voter {
+    fn from(voter: &conway::Voter) -> Self {
+        match voter {
+            conway::Voter::ConstitutionalCommitteeKey(hash) => {
+                Voter::ConstitutionalCommittee(Credential::Key(hash.into()))
+            }
+            conway::Voter::ConstitutionalCommitteeScript(hash) => {
+                Voter::ConstitutionalCommittee(Credential::Script(hash.into()))
+            }
+            conway::Voter::DRepKey(hash) => Voter::DRep(Credential::Key(hash.into())),
+            conway::Voter::DRepScript(hash) => Voter::DRep(Credential::Script(hash.into())),
+            conway::Voter::StakePoolKey(hash) => Voter::StakePool(hash.into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub enum Vote {
+    No,
+    Yes,
+    Abstain,
+}
+
+impl From<&conway::Vote> for Vote {
+    fn from(vote: &conway::Vote) -> Self {
+        match vote {
+            conway::Vote::No => Vote::No,
+            conway::Vote::Yes => Vote::Yes,
+            conway::Vote::Abstain => Vote::Abstain,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub struct VoteRecord {
+    pub voter: Voter,
+    pub action: GovActionId,
+    pub vote: Vote,
+}
+
+#[derive(Clone, Copy, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub enum GovActionKind {
+    ParameterChange,
+    HardForkInitiation,
+    TreasuryWithdrawals,
+    NoConfidence,
+    UpdateCommittee,
+    NewConstitution,
+    Information,
+}
+
+impl From<&conway::GovAction> for GovActionKind {
+    fn from(action: &conway::GovAction) -> Self {
+        match action {
+            conway::GovAction::ParameterChange(..) => GovActionKind::ParameterChange,
+            conway::GovAction::HardForkInitiation(..) => GovActionKind::HardForkInitiation,
+            conway::GovAction::TreasuryWithdrawals(..) => GovActionKind::TreasuryWithdrawals,
+            conway::GovAction::NoConfidence(..) => GovActionKind::NoConfidence,
+            conway::GovAction::UpdateCommittee(..) => GovActionKind::UpdateCommittee,
+            conway::GovAction::NewConstitution(..) => GovActionKind::NewConstitution,
+            conway::GovAction::Information => GovActionKind::Information,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq)]
+#[rkyv(compare(PartialEq))]
+pub struct Proposal {
+    pub action: GovActionId,
+    pub kind: GovActionKind,
+    pub deposit: u64,
+    /// Reward account the deposit is returned to
+    pub reward_account: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::ledger::traverse::Era;
+
+    use super::*;
+    use crate::test_utils::conway_tx;
+
+    #[test]
+    fn test_drep_certs() {
+        let key = format!("8200581c{}", "33".repeat(28));
+        let script = format!("8201581c{}", "44".repeat(28));
+        let stake = format!("8200581c{}", "55".repeat(28));
+        // A stake registration, which isn't a DRep certificate, follows the DRep ones
+        let certs =
+            format!("04 84 8410{key}1a1dcd6500f6 8311{script}1a1dcd6500 8312{key}f6 8200{stake}");
+        let cbor = conway_tx(&[&certs], "f6");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        let governance = Governance::parse(&raw);
+        assert_eq!(
+            governance.drep_certs,
+            [
+                DRepCert::Registration {
+                    credential: Credential::Key(Hash([0x33; 28])),
+                    deposit: 500_000_000,
+                },
+                DRepCert::Deregistration {
+                    credential: Credential::Script(Hash([0x44; 28])),
+                    refund: 500_000_000,
+                },
+                DRepCert::Update {
+                    credential: Credential::Key(Hash([0x33; 28])),
+                },
+            ]
+        );
+        assert!(governance.votes.is_empty() && governance.proposals.is_empty());
+    }
+
+    #[test]
+    fn test_votes() {
+        let action = |index: &str| format!("825820{}{index}", "88".repeat(32));
+        // A DRep voting yes on the first action, a pool voting no on it and abstaining on the
+        // second
+        let votes = format!(
+            "13 a2 8202581c{} a1 {} 8201f6 8204581c{} a2 {} 8200f6 {} 8202f6",
+            "66".repeat(28),
+            action("00"),
+            "77".repeat(28),
+            action("00"),
+            action("01"),
+        );
+        let cbor = conway_tx(&[&votes], "f6");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        let action = |index| GovActionId {
+            tx_hash: Hash([0x88; 32]),
+            index,
+        };
+        assert_eq!(
+            Governance::parse(&raw).votes,
+            [
+                VoteRecord {
+                    voter: Voter::DRep(Credential::Key(Hash([0x66; 28]))),
+                    action: action(0),
+                    vote: Vote::Yes,
+                },
+                VoteRecord {
+                    voter: Voter::StakePool(Hash([0x77; 28])),
+                    action: action(0),
+                    vote: Vote::No,
+                },
+                VoteRecord {
+                    voter: Voter::StakePool(Hash([0x77; 28])),
+                    action: action(1),
+                    vote: Vote::Abstain,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_proposals() {
+        let account = format!("e1{}", "99".repeat(28));
+        let anchor = format!("82 6968747470733a2f2f61 5820{}", "aa".repeat(32));
+        // An info action and a motion of no confidence, 100k ada deposit each
+        let proposals = format!(
+            "14 82 84 1b000000174876e800 581d{account} 8106 {anchor} \
+             84 1b000000174876e800 581d{account} 8203f6 {anchor}"
+        );
+        let cbor = conway_tx(&[&proposals], "f6");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        let proposal = |index, kind| Proposal {
+            action: GovActionId {
+                tx_hash: raw.hash().into(),
+                index,
+            },
+            kind,
+            deposit: 100_000_000_000,
+            reward_account: hex::decode(&account).unwrap(),
+        };
+        assert_eq!(
+            Governance::parse(&raw).proposals,
+            [
+                proposal(0, GovActionKind::Information),
+                proposal(1, GovActionKind::NoConfidence),
+            ]
+        );
+    }
+}
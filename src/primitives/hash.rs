@@ -2,7 +2,7 @@ use std::ops::Deref;
 
 use rkyv::{Archive, Deserialize, Serialize};
 
-#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[rkyv(compare(PartialEq))]
 pub struct Hash<const BYTES: usize>(pub [u8; BYTES]);
 
@@ -65,6 +65,16 @@ impl<const BYTES: usize> PartialEq for ArchivedHash<BYTES> {
     }
 }
 impl<const BYTES: usize> Eq for ArchivedHash<BYTES> {}
+impl<const BYTES: usize> PartialOrd for ArchivedHash<BYTES> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<const BYTES: usize> Ord for ArchivedHash<BYTES> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
 impl<const BYTES: usize> std::hash::Hash for ArchivedHash<BYTES> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         (&self.0).hash(state);
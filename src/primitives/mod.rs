@@ -1,18 +1,27 @@
+mod address;
 mod asset;
 mod block;
+mod certificate;
 mod era;
+mod governance;
 mod hash;
 mod script;
+mod slot;
 mod tx;
 mod tx_output;
+mod value;
 mod volatile_block;
 
+pub use address::*;
 pub use asset::*;
 pub use block::*;
+pub use certificate::*;
 pub use era::*;
+pub use governance::*;
 pub use hash::*;
 pub use script::*;
+pub use slot::*;
 pub use tx::*;
 pub use tx_output::*;
+pub use value::*;
 pub use volatile_block::*;
-
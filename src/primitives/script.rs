@@ -1,4 +1,5 @@
 use pallas::ledger::primitives::PlutusScript;
+use pallas::ledger::primitives::conway::PseudoScript;
 use rkyv::{Archive, Deserialize, Serialize};
 
 use super::*;
@@ -11,6 +12,23 @@ pub enum Script {
     V1(Vec<u8>),
     V2(Vec<u8>),
     V3(Vec<u8>),
+    /// Only as an output's reference script, the native scripts of a tx are in
+    /// [`Tx::native_scripts`]
+    Native(NativeScript),
+}
+
+impl Script {
+    /// Hash the ledger identifies the script by, or `None` for native scripts, whose original
+    /// CBOR isn't kept
+    pub fn hash(&self) -> Option<ScriptHash> {
+        let (tag, bytes) = match self {
+            Script::V1(bytes) => (1, bytes),
+            Script::V2(bytes) => (2, bytes),
+            Script::V3(bytes) => (3, bytes),
+            Script::Native(_) => return None,
+        };
+        Some(pallas::crypto::hash::Hasher::<224>::hash_tagged(bytes, tag).into())
+    }
 }
 
 impl<const VERSION: usize> From<&PlutusScript<VERSION>> for Script {
@@ -25,6 +43,21 @@ impl<const VERSION: usize> From<&PlutusScript<VERSION>> for Script {
     }
 }
 
+/// Reference scripts of outputs, which may be native as well
+impl<T> From<&PseudoScript<T>> for Script
+where
+    T: std::ops::Deref<Target = pallas::ledger::primitives::conway::NativeScript>,
+{
+    fn from(script: &PseudoScript<T>) -> Self {
+        match script {
+            PseudoScript::NativeScript(script) => Script::Native(NativeScript::from(&**script)),
+            PseudoScript::PlutusV1Script(script) => script.into(),
+            PseudoScript::PlutusV2Script(script) => script.into(),
+            PseudoScript::PlutusV3Script(script) => script.into(),
+        }
+    }
+}
+
 pub type AddressKeyHash = Hash<28>;
 
 /// This is a bit mind-numbing because of the recursive types
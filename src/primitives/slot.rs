@@ -0,0 +1,50 @@
+use std::ops::{Bound, RangeBounds};
+
+/// Absolute slot of the chain. Unlike block numbers, slots have gaps since most slots have no
+/// block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Slot(pub u64);
+
+/// Height of a block, counting from the first block
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockNumber(pub u64);
+
+impl From<u64> for Slot {
+    fn from(slot: u64) -> Self {
+        Self(slot)
+    }
+}
+impl From<Slot> for u64 {
+    fn from(slot: Slot) -> Self {
+        slot.0
+    }
+}
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u64> for BlockNumber {
+    fn from(number: u64) -> Self {
+        Self(number)
+    }
+}
+impl From<BlockNumber> for u64 {
+    fn from(number: BlockNumber) -> Self {
+        number.0
+    }
+}
+impl std::fmt::Display for BlockNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Unwraps the bounds of a slot or block number range, for querying tables keyed by `u64`
+pub(crate) fn u64_bounds<T: Copy + Into<u64>>(
+    range: &impl RangeBounds<T>,
+) -> (Bound<u64>, Bound<u64>) {
+    let unwrap = |bound: Bound<&T>| bound.map(|value| (*value).into());
+    (unwrap(range.start_bound()), unwrap(range.end_bound()))
+}
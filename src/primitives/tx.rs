@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use pallas::codec::utils::{KeepRaw, Nullable};
 use pallas::ledger::traverse::MultiEraTx;
 use rkyv::{Archive, Deserialize, Serialize};
 
@@ -20,22 +21,41 @@ pub struct Tx {
     /// https://github.com/input-output-hk/cardano-ledger/commit/a342b74f5db3d3a75eae3e2abe358a169701b1e7
     pub reference_inputs: Vec<TxOutputPointer>,
     pub mints: Vec<Mint>,
+    /// Reward account bytes and the amount withdrawn from it
+    pub withdrawals: Vec<(Address, u64)>,
 
     pub scripts: Vec<Script>,
     pub native_scripts: Vec<NativeScript>,
+    /// Metadata labels and the CBOR of their metadatum, e.g. CIP-25 NFT metadata under label 721.
+    /// The CBOR is as it appears in the tx rather than re-encoded, which may differ from it.
+    pub metadata: Vec<(u64, Vec<u8>)>,
+
+    pub governance: Governance,
+    /// Delegation changes, in the order the tx makes them
+    pub stake_certs: Vec<StakeCert>,
 
     pub valid: bool,
 }
 
 impl Tx {
-    pub fn parse(tx: &MultiEraTx) -> (Self, HashMap<DatumHash, Datum>) {
+    pub fn parse(tx: &MultiEraTx) -> (Self, BTreeMap<DatumHash, Datum>) {
+        Self::parse_with(tx, ParseOptions::default())
+    }
+
+    pub fn parse_with(
+        tx: &MultiEraTx,
+        options: ParseOptions,
+    ) -> (Self, BTreeMap<DatumHash, Datum>) {
         let inputs = tx.inputs_sorted_set().into_iter().map(Into::into).collect();
-        let (outputs, mut datums): (Vec<TxOutput>, Vec<Option<(DatumHash, Datum)>>) =
-            tx.outputs().into_iter().map(|x| TxOutput::parse(x)).unzip();
+        let (outputs, mut datums): (Vec<TxOutput>, Vec<Option<(DatumHash, Datum)>>) = tx
+            .outputs()
+            .into_iter()
+            .map(|x| TxOutput::parse_with(x, options.datums))
+            .unzip();
 
         let collateral = tx.collateral().into_iter().map(Into::into).collect();
         let collateral_return = tx.collateral_return().map(|cr| {
-            let (collateral_return, datum) = TxOutput::parse(cr);
+            let (collateral_return, datum) = TxOutput::parse_with(cr, options.datums);
             if !tx.is_valid() {
                 datums.push(datum);
             }
@@ -44,15 +64,31 @@ impl Tx {
 
         let reference_inputs = tx.reference_inputs().into_iter().map(Into::into).collect();
         let mints = Mint::from_assets(tx.mints_sorted_set());
-
-        let scripts = tx
-            .plutus_v1_scripts()
-            .iter()
-            .map(Into::into)
-            .chain(tx.plutus_v2_scripts().iter().map(Into::into))
-            .chain(tx.plutus_v3_scripts().iter().map(Into::into))
+        let withdrawals = tx
+            .withdrawals_sorted_set()
+            .into_iter()
+            .map(|(account, amount)| (account.to_vec(), amount))
             .collect();
-        let native_scripts = tx.aux_native_scripts().iter().map(Into::into).collect();
+
+        let (scripts, native_scripts) = if options.scripts {
+            let scripts = tx
+                .plutus_v1_scripts()
+                .iter()
+                .map(Into::into)
+                .chain(tx.plutus_v2_scripts().iter().map(Into::into))
+                .chain(tx.plutus_v3_scripts().iter().map(Into::into))
+                .collect();
+            let native_scripts = tx.aux_native_scripts().iter().map(Into::into).collect();
+            (scripts, native_scripts)
+        } else {
+            (vec![], vec![])
+        };
+
+        let metadata = if options.metadata {
+            aux_data_cbor(tx).and_then(raw_metadata).unwrap_or_default()
+        } else {
+            vec![]
+        };
 
         (
             Self {
@@ -65,8 +101,12 @@ impl Tx {
                 collateral_return,
                 reference_inputs,
                 mints,
+                withdrawals,
                 scripts,
                 native_scripts,
+                metadata,
+                governance: Governance::parse(tx),
+                stake_certs: StakeCert::parse_all(tx),
             },
             datums.into_iter().flatten().collect(),
         )
@@ -84,3 +124,112 @@ impl Tx {
         self.outputs.iter().filter(|_| self.valid)
     }
 }
+
+/// Auxiliary data as it appears in the tx, which is kept raw while decoding
+fn aux_data_cbor<'a>(tx: &'a MultiEraTx) -> Option<&'a [u8]> {
+    fn raw<'a, T>(aux_data: &'a Nullable<KeepRaw<'_, T>>) -> Option<&'a [u8]> {
+        match aux_data {
+            Nullable::Some(aux_data) => Some(aux_data.raw_cbor()),
+            _ => None,
+        }
+    }
+    match tx {
+        MultiEraTx::AlonzoCompatible(tx, _) => raw(&tx.auxiliary_data),
+        MultiEraTx::Babbage(tx) => raw(&tx.auxiliary_data),
+        MultiEraTx::Conway(tx) => raw(&tx.auxiliary_data),
+        _ => None,
+    }
+}
+
+/// Metadata read straight from the auxiliary data's CBOR: the whole of Shelley's, the first
+/// element of Shelley-MA's, or key 0 of the tagged map from Alonzo on. Each metadatum is sliced
+/// out as is.
+fn raw_metadata(cbor: &[u8]) -> Option<Vec<(u64, Vec<u8>)>> {
+    use minicbor::data::Type;
+
+    let mut d = minicbor::Decoder::new(cbor);
+    match d.datatype().ok()? {
+        Type::Map | Type::MapIndef => {}
+        Type::Array | Type::ArrayIndef => {
+            d.array().ok()?;
+        }
+        Type::Tag => {
+            d.tag().ok()?;
+            let len = d.map().ok()?;
+            let mut seen = 0;
+            loop {
+                if len == Some(seen) || d.datatype().ok()? == Type::Break {
+                    // No metadata, only scripts
+                    return Some(vec![]);
+                }
+                if d.u64().ok()? == 0 {
+                    break;
+                }
+                d.skip().ok()?;
+                seen += 1;
+            }
+        }
+        _ => return None,
+    }
+
+    let len = d.map().ok()?;
+    let mut metadata = Vec::new();
+    while len != Some(metadata.len() as u64) && d.datatype().ok()? != Type::Break {
+        let label = d.u64().ok()?;
+        let start = d.position();
+        d.skip().ok()?;
+        metadata.push((label, cbor[start..d.position()].to_vec()));
+    }
+    Some(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::ledger::traverse::Era;
+
+    use super::*;
+    use crate::test_utils::conway_tx;
+
+    #[test]
+    fn test_metadata() {
+        // {674: {"msg": ["hi"]}}, the CIP-20 message label
+        let cbor = conway_tx(&[], "a1 1902a2 a1636d736781626869");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        let (tx, _) = Tx::parse(&raw);
+        let metadatum = hex::decode("a1636d736781626869").unwrap();
+        assert_eq!(tx.metadata, [(674, metadatum)]);
+
+        let options = ParseOptions {
+            metadata: false,
+            ..Default::default()
+        };
+        assert!(Tx::parse_with(&raw, options).0.metadata.is_empty());
+
+        let cbor = conway_tx(&[], "f6");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        assert!(Tx::parse(&raw).0.metadata.is_empty());
+
+        // A non-minimal int, which re-encoding would shorten, in Alonzo's tagged aux data
+        let cbor = conway_tx(&[], "d90103 a1 00 a1 1902a2 1a00000001");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        let metadatum = hex::decode("1a00000001").unwrap();
+        assert_eq!(Tx::parse(&raw).0.metadata, [(674, metadatum)]);
+    }
+
+    #[test]
+    fn test_withdrawals() {
+        // 5 ada withdrawn from the reward account of a key hash of 0x22 bytes
+        let account = format!("e1{}", "22".repeat(28));
+        let cbor = conway_tx(&[&format!("05 a1 581d{account} 1a004c4b40")], "f6");
+        let raw = MultiEraTx::decode_for_era(Era::Conway, &cbor).unwrap();
+        let (tx, _) = Tx::parse(&raw);
+        assert_eq!(
+            tx.withdrawals,
+            [(hex::decode(&account).unwrap(), 5_000_000)]
+        );
+        assert_eq!(
+            reward_account_to_credential(&tx.withdrawals[0].0).unwrap(),
+            Credential::Key(Hash([0x22; 28]))
+        );
+    }
+}
@@ -15,29 +15,51 @@ pub type Address = Vec<u8>;
 #[derive(Clone, Debug, Archive, Deserialize, Serialize)]
 #[rkyv(compare(PartialEq))]
 pub struct TxOutput {
+    /// Raw address bytes. Parsing never fails: addresses pallas rejects, like the Shelley-era
+    /// pointer addresses with out of range pointers, are kept as found on chain, or left empty
+    /// if they can't even be read from the output's CBOR.
     pub address: Address,
     pub lovelace: u64,
     pub assets: Vec<Asset>,
     pub datum_hash: Option<DatumHash>,
-    // TODO: script ref
+    /// Reference script, Babbage onwards
+    pub script_ref: Option<Script>,
 }
 
 impl TxOutput {
     pub fn parse(output: MultiEraOutput) -> (Self, Option<(DatumHash, Datum)>) {
-        let address = output.address().expect("failed to decode address").to_vec();
+        Self::parse_with(output, true)
+    }
+
+    /// Like [`TxOutput::parse`], without copying out the inline datum unless `with_datum`
+    pub(crate) fn parse_with(
+        output: MultiEraOutput,
+        with_datum: bool,
+    ) -> (Self, Option<(DatumHash, Datum)>) {
+        let address = match output.address() {
+            Ok(address) => address.to_vec(),
+            Err(err) => {
+                let raw = raw_address(&output);
+                tracing::warn!(
+                    %err,
+                    raw = ?raw.as_deref().map(hex::encode),
+                    "failed to decode output address, keeping the raw bytes"
+                );
+                raw.unwrap_or_default()
+            }
+        };
         let lovelace = output.value().coin();
         let assets = Asset::from_assets(output.value().assets());
-        let datum_hash = output.datum().map(|d| {
-            match d {
-                DatumOption::Hash(x) => x,
-                DatumOption::Data(data) => data.compute_hash(),
+        let (datum_hash, datum) = match output.datum() {
+            None => (None, None),
+            Some(DatumOption::Hash(hash)) => (Some(hash.into()), None),
+            Some(DatumOption::Data(data)) => {
+                let hash: DatumHash = data.compute_hash().into();
+                let datum = with_datum.then(|| (hash.clone(), data.raw_cbor().to_vec()));
+                (Some(hash), datum)
             }
-            .into()
-        });
-        let datum = output.datum().and_then(|d| match d {
-            DatumOption::Hash(_) => None,
-            DatumOption::Data(data) => Some((data.compute_hash().into(), data.raw_cbor().to_vec())),
-        });
+        };
+        let script_ref = output.script_ref().as_ref().map(Script::from);
 
         (
             Self {
@@ -45,13 +67,43 @@ impl TxOutput {
                 lovelace,
                 assets,
                 datum_hash,
+                script_ref,
             },
             datum,
         )
     }
 }
 
-#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+/// Address in hex, then the value, see [`Value`]'s `Display`
+impl std::fmt::Display for TxOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", hex::encode(&self.address), Value::from(self))
+    }
+}
+
+/// Address bytes read straight from the output's CBOR: the first element of a legacy output, or
+/// key 0 of a post-Alonzo one
+fn raw_address(output: &MultiEraOutput) -> Option<Address> {
+    use minicbor::data::Type;
+
+    let cbor = output.encode();
+    let mut d = minicbor::Decoder::new(&cbor);
+    match d.datatype().ok()? {
+        Type::Array | Type::ArrayIndef => {
+            d.array().ok()?;
+        }
+        Type::Map | Type::MapIndef => {
+            d.map().ok()?;
+            if d.u8().ok()? != 0 {
+                return None;
+            }
+        }
+        _ => return None,
+    }
+    d.bytes().ok().map(<[u8]>::to_vec)
+}
+
+#[derive(Clone, Debug, Archive, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[rkyv(compare(PartialEq))]
 pub struct TxOutputPointer {
     pub hash: TxHash,
@@ -77,6 +129,30 @@ impl TxOutputPointer {
         start..=end
     }
 }
+/// `txhash#index`, as used by explorers and the cardano-cli
+impl std::fmt::Display for TxOutputPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{}", self.hash, self.index)
+    }
+}
+impl std::str::FromStr for TxOutputPointer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, index) = s
+            .split_once('#')
+            .ok_or_else(|| anyhow::anyhow!("expected txhash#index, got {s:?}"))?;
+        let hash = <[u8; 32]>::try_from(hex::decode(hash)?)
+            .map_err(|bytes| anyhow::anyhow!("expected a 32 byte tx hash, got {}", bytes.len()))?;
+        let index = index
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid output index {index:?}: {e}"))?;
+        Ok(Self {
+            hash: hash.into(),
+            index,
+        })
+    }
+}
 impl From<MultiEraInput<'_>> for TxOutputPointer {
     fn from(input: MultiEraInput) -> Self {
         let hash = input.hash().into();
@@ -84,3 +160,122 @@ impl From<MultiEraInput<'_>> for TxOutputPointer {
         Self { hash, index }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pallas::ledger::traverse::Era;
+
+    use super::*;
+
+    const TX_HASH: &str = "8f3a2b9c1d4e5f60718293a4b5c6d7e8f9011223344556677889900aabbccdde";
+
+    #[test]
+    fn test_pointer_round_trip() {
+        let pointer: TxOutputPointer = format!("{TX_HASH}#3").parse().unwrap();
+        assert_eq!(pointer.index, 3);
+        assert_eq!(pointer.to_string(), format!("{TX_HASH}#3"));
+
+        let max = format!("{TX_HASH}#{}", u64::MAX);
+        assert_eq!(max.parse::<TxOutputPointer>().unwrap().to_string(), max);
+    }
+
+    #[test]
+    fn test_unparseable_address() {
+        // Header type 9 doesn't exist, as a post-Alonzo output and as a legacy one
+        for cbor in ["a20042900101 1a000f4240", "82429001 1a000f4240"] {
+            let cbor = hex::decode(cbor.replace(' ', "")).unwrap();
+            let output = MultiEraOutput::decode(Era::Conway, &cbor).unwrap();
+            assert!(output.address().is_err());
+
+            let (output, _) = TxOutput::parse(output);
+            assert_eq!(output.address, [0x90, 0x01]);
+            assert_eq!(output.lovelace, 1_000_000);
+        }
+    }
+
+    #[test]
+    fn test_script_ref() {
+        use heed::{BytesDecode, BytesEncode};
+
+        use crate::db::RkyvCodec;
+
+        // Outputs with a Plutus V2 reference script, and a native one valid from slot 100
+        let plutus = "a3 00 429001 01 1a000f4240 03 d81847 8202 4401020304";
+        let native = "a3 00 429001 01 1a000f4240 03 d81846 8200 82041864";
+        for (cbor, expected) in [
+            (plutus, Script::V2(vec![1, 2, 3, 4])),
+            (native, Script::Native(NativeScript::InvalidBefore(100))),
+        ] {
+            let cbor = hex::decode(cbor.replace(' ', "")).unwrap();
+            let output = MultiEraOutput::decode(Era::Conway, &cbor).unwrap();
+            let (output, _) = TxOutput::parse(output);
+
+            let bytes = RkyvCodec::<TxOutput>::bytes_encode(&output).unwrap();
+            let archived = RkyvCodec::<TxOutput>::bytes_decode(&bytes).unwrap();
+            let decoded = rkyv::deserialize::<TxOutput, rkyv::rancor::Error>(archived).unwrap();
+            let archived = archived.script_ref.as_ref().unwrap();
+            assert!(*archived == expected);
+            assert!(*archived == decoded.script_ref.unwrap());
+        }
+
+        let legacy = hex::decode("82 429001 1a000f4240".replace(' ', "")).unwrap();
+        let output = MultiEraOutput::decode(Era::Conway, &legacy).unwrap();
+        assert!(TxOutput::parse(output).0.script_ref.is_none());
+    }
+
+    #[test]
+    fn test_datums_across_eras() {
+        // Enterprise address, 1 ada and a datum: a hash of 0xaa bytes, or the inline integer 42
+        let address = format!("581d61{}", "11".repeat(28));
+        let hash = "5820".to_string() + &"aa".repeat(32);
+        let alonzo = format!("83 {address} 1a000f4240 {hash}");
+        let babbage_hash = format!("a3 00 {address} 01 1a000f4240 02 8200 {hash}");
+        let inline = format!("a3 00 {address} 01 1a000f4240 02 8201 d81842 182a");
+        let inline_hash: DatumHash =
+            pallas::crypto::hash::Hasher::<256>::hash(&[0x18, 0x2a]).into();
+
+        for (era, cbor, datum) in [
+            (Era::Alonzo, &alonzo, None),
+            (Era::Babbage, &babbage_hash, None),
+            (Era::Conway, &babbage_hash, None),
+            (Era::Babbage, &inline, Some(vec![0x18, 0x2a])),
+            (Era::Conway, &inline, Some(vec![0x18, 0x2a])),
+        ] {
+            let cbor = hex::decode(cbor.replace(' ', "")).unwrap();
+            let expected_hash = match datum {
+                Some(_) => inline_hash.clone(),
+                None => Hash([0xaa; 32]),
+            };
+
+            let (output, parsed) = TxOutput::parse(MultiEraOutput::decode(era, &cbor).unwrap());
+            assert_eq!(
+                output.address,
+                hex::decode(&address[4..]).unwrap(),
+                "{era:?}"
+            );
+            assert_eq!(output.lovelace, 1_000_000, "{era:?}");
+            assert_eq!(output.datum_hash, Some(expected_hash.clone()), "{era:?}");
+            assert_eq!(parsed, datum.map(|datum| (expected_hash, datum)), "{era:?}");
+
+            // The hash is kept even when the inline datum isn't wanted
+            let output = MultiEraOutput::decode(era, &cbor).unwrap();
+            let (output, parsed) = TxOutput::parse_with(output, false);
+            assert!(output.datum_hash.is_some(), "{era:?}");
+            assert!(parsed.is_none(), "{era:?}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_pointers() {
+        for invalid in [
+            TX_HASH.to_string(),
+            format!("{}#0", &TX_HASH[2..]),
+            format!("zz{}#0", &TX_HASH[2..]),
+            format!("{TX_HASH}#"),
+            format!("{TX_HASH}#-1"),
+            format!("{TX_HASH}#18446744073709551616"),
+        ] {
+            assert!(invalid.parse::<TxOutputPointer>().is_err(), "{invalid}");
+        }
+    }
+}
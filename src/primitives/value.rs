@@ -0,0 +1,212 @@
+use super::*;
+
+/// Amount of lovelace, displayed in ada with all 6 decimals, e.g. `1.234567 ₳`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lovelace(pub u64);
+
+impl std::fmt::Display for Lovelace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{:06} ₳", self.0 / 1_000_000, self.0 % 1_000_000)
+    }
+}
+
+/// Lovelace and native assets held by one or more outputs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Value {
+    pub lovelace: u64,
+    /// Sorted by policy then name, without zero quantities
+    assets: Vec<Asset>,
+}
+
+impl Value {
+    pub fn new(lovelace: u64) -> Self {
+        Self {
+            lovelace,
+            assets: vec![],
+        }
+    }
+
+    pub fn assets(&self) -> &[Asset] {
+        &self.assets
+    }
+
+    pub fn quantity_of(&self, policy: &Policy, name: &[u8]) -> u64 {
+        self.position(policy, name)
+            .map(|i| self.assets[i].quantity)
+            .unwrap_or_default()
+    }
+
+    pub fn add_asset(&mut self, policy: Policy, name: AssetName, quantity: u64) {
+        if quantity == 0 {
+            return;
+        }
+        match self.position(&policy, &name) {
+            Ok(i) => self.assets[i].quantity = self.assets[i].quantity.saturating_add(quantity),
+            Err(i) => self.assets.insert(
+                i,
+                Asset {
+                    policy,
+                    name,
+                    quantity,
+                },
+            ),
+        }
+    }
+
+    pub fn add(&mut self, other: &Value) {
+        self.lovelace = self.lovelace.saturating_add(other.lovelace);
+        for asset in other.assets.iter() {
+            self.add_asset(asset.policy.clone(), asset.name.clone(), asset.quantity);
+        }
+    }
+
+    /// Whether this value holds at least the lovelace and each asset quantity of `target`
+    pub fn covers(&self, target: &Value) -> bool {
+        self.lovelace >= target.lovelace
+            && target
+                .assets
+                .iter()
+                .all(|asset| self.quantity_of(&asset.policy, &asset.name) >= asset.quantity)
+    }
+
+    /// Subtracts `other` from this value, returning `None` if the lovelace or any asset would
+    /// underflow
+    pub fn minus(&self, other: &Value) -> Option<Value> {
+        let mut value = self.clone();
+        value.lovelace = value.lovelace.checked_sub(other.lovelace)?;
+        for asset in other.assets.iter() {
+            let i = value.position(&asset.policy, &asset.name).ok()?;
+            value.assets[i].quantity = value.assets[i].quantity.checked_sub(asset.quantity)?;
+            if value.assets[i].quantity == 0 {
+                value.assets.remove(i);
+            }
+        }
+        Some(value)
+    }
+
+    fn position(&self, policy: &Policy, name: &[u8]) -> Result<usize, usize> {
+        self.assets
+            .binary_search_by(|a| (&a.policy.0, a.name.as_slice()).cmp(&(&policy.0, name)))
+    }
+}
+
+/// Ada, then each asset as `quantity policy.name`, with the name as text if it's printable
+/// UTF-8 and in hex otherwise
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Lovelace(self.lovelace).fmt(f)?;
+        for asset in self.assets.iter() {
+            write!(f, ", {} {}", asset.quantity, asset.policy)?;
+            if asset.name.is_empty() {
+                continue;
+            }
+            match std::str::from_utf8(&asset.name) {
+                Ok(name) if !name.chars().any(char::is_control) => write!(f, ".{name}")?,
+                _ => write!(f, ".{}", hex::encode(&asset.name))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&TxOutput> for Value {
+    fn from(output: &TxOutput) -> Self {
+        let mut value = Value::new(output.lovelace);
+        for asset in output.assets.iter() {
+            value.add_asset(asset.policy.clone(), asset.name.clone(), asset.quantity);
+        }
+        value
+    }
+}
+
+impl<'a> FromIterator<&'a TxOutput> for Value {
+    fn from_iter<I: IntoIterator<Item = &'a TxOutput>>(outputs: I) -> Self {
+        outputs
+            .into_iter()
+            .fold(Value::default(), |mut value, output| {
+                value.add(&output.into());
+                value
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift so failures are reproducible
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+
+        fn value(&mut self) -> Value {
+            let mut value = Value::new(self.next(1_000));
+            for _ in 0..self.next(4) {
+                let policy = Hash([self.next(3) as u8; 28]);
+                let name = vec![self.next(3) as u8];
+                value.add_asset(policy, name, self.next(100));
+            }
+            value
+        }
+    }
+
+    #[test]
+    fn test_add_then_minus_round_trips() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..1_000 {
+            let (a, b) = (rng.value(), rng.value());
+            let mut sum = a.clone();
+            sum.add(&b);
+            assert!(sum.covers(&a) && sum.covers(&b));
+            assert_eq!(sum.minus(&b), Some(a));
+        }
+    }
+
+    #[test]
+    fn test_covers_iff_minus_succeeds() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..1_000 {
+            let (a, b) = (rng.value(), rng.value());
+            assert_eq!(a.covers(&b), a.minus(&b).is_some(), "{a:?} - {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Lovelace(1_234_567).to_string(), "1.234567 ₳");
+        assert_eq!(Lovelace(5).to_string(), "0.000005 ₳");
+
+        let policy = Hash([1; 28]);
+        let mut value = Value::new(2_000_000);
+        value.add_asset(policy.clone(), b"LQ".to_vec(), 5);
+        value.add_asset(policy.clone(), vec![0, 0xff], 1);
+        value.add_asset(policy.clone(), vec![], 3);
+        let policy = "01".repeat(28);
+        assert_eq!(
+            value.to_string(),
+            format!("2.000000 ₳, 3 {policy}, 1 {policy}.00ff, 5 {policy}.LQ")
+        );
+    }
+
+    #[test]
+    fn test_minus_underflows_per_asset() {
+        let policy = Hash([1; 28]);
+        let mut have = Value::new(10);
+        have.add_asset(policy.clone(), b"a".to_vec(), 5);
+        let mut want = Value::new(1);
+        want.add_asset(policy.clone(), b"b".to_vec(), 1);
+
+        // Plenty of ada and of asset "a" doesn't cover a missing asset "b"
+        assert!(!have.covers(&want));
+        assert_eq!(have.minus(&want), None);
+
+        want = Value::new(10);
+        want.add_asset(policy.clone(), b"a".to_vec(), 5);
+        assert_eq!(have.minus(&want), Some(Value::new(0)));
+    }
+}
@@ -1,6 +1,3 @@
-use pallas::ledger::traverse::{
-      MultiEraBlock,
-};
 use rkyv::{Archive, Deserialize, Serialize};
 
 use super::*;
@@ -11,18 +8,40 @@ pub struct VolatileBlock {
     pub hash: BlockHash,
     pub number: u64,
     pub slot: u64,
+    /// Txs inserted by at least one indexer
     pub txs: Vec<TxHash>,
+    /// Number of txs in the block, including those no indexer inserted
+    pub tx_count: u64,
+    /// Datums inserted by at least one indexer
     pub datums: Vec<DatumHash>,
+    /// Datums witnessed by the block that the db's datum table holds a reference to while the
+    /// block is within the volatile window: all of them if an active indexer wanted datums
+    /// when it was applied, none otherwise
+    pub witnessed_datums: Vec<DatumHash>,
 }
 
 impl VolatileBlock {
-    pub fn parse(block: &MultiEraBlock, txs: Vec<Hash<32>>, datums: Vec<Hash<32>>) -> Self {
+    pub fn parse(
+        block: &Block,
+        txs: Vec<Hash<32>>,
+        datums: Vec<Hash<32>>,
+        witnessed_datums: Vec<Hash<32>>,
+    ) -> Self {
         Self {
-            hash: block.hash().into(),
-            number: block.number(),
-            slot: block.slot(),
+            hash: block.hash.clone(),
+            number: block.number,
+            slot: block.slot,
+            tx_count: block.txs.len() as u64,
             txs,
             datums,
+            witnessed_datums,
         }
     }
 }
+
+/// Datum referenced by one or more blocks within the volatile window
+#[derive(Clone, Debug, Archive, Deserialize, Serialize)]
+pub(crate) struct VolatileDatum {
+    pub refs: u32,
+    pub datum: Datum,
+}
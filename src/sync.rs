@@ -8,11 +8,15 @@ use pallas::network::miniprotocols::chainsync::{HeaderContent, NextResponse, Tip
 use tokio::time::sleep;
 use tracing::info;
 
+use crate::archive::ArchiveSink;
 use crate::db::Db;
 use crate::indexer::IndexerList;
-use crate::writer::Writer;
+use crate::network::Network;
+use crate::writer::{Writer, WriterHandle};
 
 const BLOCKFETCH_CONCURRENCY: usize = 200;
+/// Size assumed for blocks until a larger one is fetched, about the maximum block size
+const EXPECTED_BLOCK_SIZE: usize = 90 * 1024;
 
 #[derive(Debug)]
 pub enum SyncEvent {
@@ -26,20 +30,42 @@ pub struct Sync {
     node: PeerClient,
     writer: Writer,
     pending_fetches: Vec<(Point, Tip)>,
+    /// Largest block fetched so far, for estimating the size of a batch before fetching it
+    max_block_size: usize,
 }
 
 impl Sync {
-    pub async fn new(mut node: PeerClient, db: &Db, indexer: &IndexerList) -> Result<Self> {
-        let tip = db.tip()?;
-        match db.tip()? {
-            Point::Origin => {
+    /// Starts syncing from the database's tip. `network` must be the one whose magic was used
+    /// to connect to the node; the database refuses to sync against a different network than
+    /// it was first synced with, see [`Db::assert_network`].
+    pub async fn new(
+        node: PeerClient,
+        network: Network,
+        db: &Db,
+        indexer: &IndexerList,
+    ) -> Result<Self> {
+        Self::with_archive(node, network, db, indexer, None).await
+    }
+
+    /// Like [`Sync::new`], also archiving the raw blocks and rollbacks, see [`ArchiveSink`]
+    pub async fn with_archive(
+        mut node: PeerClient,
+        network: Network,
+        db: &Db,
+        indexer: &IndexerList,
+        archive: Option<ArchiveSink>,
+    ) -> Result<Self> {
+        db.assert_network(network)?;
+
+        match Self::intersection(db)? {
+            None => {
                 info!("No tip, starting from origin");
                 node.chainsync()
                     .intersect_origin()
                     .await
                     .context("failed to start from origin")?;
             }
-            Point::Specific(_, _) => {
+            Some(tip) => {
                 info!(?tip, "Requesting intersection");
                 node.chainsync()
                     .find_intersect(vec![tip])
@@ -50,11 +76,20 @@ impl Sync {
 
         Ok(Self {
             node,
-            writer: Writer::new(db, indexer),
+            writer: Writer::with_archive(db, indexer, archive),
             pending_fetches: vec![],
+            max_block_size: EXPECTED_BLOCK_SIZE,
         })
     }
 
+    /// Point to request the intersection at, or `None` to start from the origin
+    fn intersection(db: &Db) -> Result<Option<Point>> {
+        match db.tip()? {
+            Point::Origin => Ok(None),
+            tip => Ok(Some(tip)),
+        }
+    }
+
     pub async fn next(&mut self) -> Result<NextResponse<HeaderContent>> {
         let next = {
             let chainsync = self.node.chainsync();
@@ -108,15 +143,29 @@ impl Sync {
         }
     }
 
+    /// Fetches the pending blocks as a single range and queues them for the writer one by one.
+    /// Nothing tracks the progress of the batch itself: the writer commits blocks individually
+    /// (or every `commit_interval` blocks, see [`DbOptions`](crate::db::DbOptions)), and each
+    /// commit advances the tip, which is where a restarted sync intersects. So after a crash
+    /// partway through a batch, only the blocks after the last commit are fetched again, not
+    /// the whole batch.
+    ///
+    /// The database is opened without fsyncing each commit, which survives the process
+    /// crashing but not the machine: after an OS crash or power loss, restore it from a
+    /// snapshot or resync it, see [`Db::persist`](crate::Db::persist).
     async fn flush_pending_fetches(&mut self) -> Result<()> {
         if let Some((start, _)) = self.pending_fetches.first()
             && let Some((end, tip)) = self.pending_fetches.last()
         {
+            // Wait for the writer to make room, rather than holding a batch it can't take yet
+            let estimate = self.pending_fetches.len() * self.max_block_size;
+            let reservation = self.writer.reserve(estimate).await?;
             let blocks = self
                 .node
                 .blockfetch()
                 .fetch_range((start.clone(), end.clone()))
                 .await?;
+            drop(reservation);
             if blocks.len() != self.pending_fetches.len() {
                 return Err(anyhow::anyhow!(
                     "fetched {} blocks, expected {}",
@@ -125,6 +174,7 @@ impl Sync {
                 ));
             }
             for block in blocks {
+                self.max_block_size = self.max_block_size.max(block.len());
                 self.writer
                     .send(SyncEvent::RollForward(block, tip.clone()))
                     .await?;
@@ -134,8 +184,84 @@ impl Sync {
         Ok(())
     }
 
+    /// Handle for pausing and resuming the writer while sync is running
+    pub fn writer_handle(&self) -> WriterHandle {
+        self.writer.handle()
+    }
+
     pub async fn stop(self) -> Result<()> {
         self.node.abort().await;
         self.writer.stop().await.context("error while writing")
     }
+
+    /// Like [`Sync::stop`], but gives up on the writer after `timeout`, see
+    /// [`Writer::stop_timeout`]
+    pub async fn stop_timeout(self, timeout: Duration) -> Result<()> {
+        self.node.abort().await;
+        self.writer
+            .stop_timeout(timeout)
+            .await
+            .context("error while writing")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbOptions;
+    use crate::test_utils::{
+        MAX_ROLLBACK_BLOCKS, block, hash, output, pointer, temp_db, tx, utxo_addresses,
+        utxo_indexer,
+    };
+
+    #[test]
+    fn test_resume_from_restored_snapshot() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(2, 20, vec![tx(2, vec![], vec![output(b"b", 1)])]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+        let snapshot = dir.path().join("snapshot.mdb");
+        db.snapshot(&snapshot, false).unwrap();
+
+        let path = dir.path().join("restored");
+        let path = path.to_str().unwrap();
+        let options = DbOptions::new(MAX_ROLLBACK_BLOCKS);
+        let restored = Db::restore_from_snapshot(&snapshot, path, options.clone()).unwrap();
+        assert!(Db::restore_from_snapshot(&snapshot, path, options).is_err());
+
+        let tip = Point::Specific(20, hash::<32>(2).to_vec());
+        assert_eq!(Sync::intersection(&restored).unwrap(), Some(tip));
+        let (_empty_dir, empty) = temp_db();
+        assert_eq!(Sync::intersection(&empty).unwrap(), None);
+
+        // Same indexer ids, so syncing continues on top of the restored state
+        let (utxo, indexers) = utxo_indexer(&restored);
+        let spend = tx(3, vec![pointer(1, 0)], vec![output(b"c", 1)]);
+        restored
+            .roll_forward(&indexers, &[block(3, 30, vec![spend])])
+            .unwrap();
+        assert_eq!(utxo_addresses(&utxo), [b"b", b"c"]);
+    }
+
+    #[test]
+    fn test_resume_mid_batch() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        // Only the first 3 blocks of a batch of 5 were written before the process died
+        let batch = (1..=5)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        for block in &batch[..3] {
+            db.roll_forward(&indexers, std::slice::from_ref(block))
+                .unwrap();
+        }
+        drop((db, indexers));
+
+        let db = Db::new(dir.path().to_str().unwrap(), MAX_ROLLBACK_BLOCKS).unwrap();
+        let tip = Point::Specific(30, hash::<32>(3).to_vec());
+        assert_eq!(Sync::intersection(&db).unwrap(), Some(tip));
+    }
 }
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use pallas::network::miniprotocols::Point;
+
+use crate::db::Db;
+use crate::indexer::{Indexer, IndexerList};
+use crate::primitives::{Block, Era, Governance, Hash, Tx, TxOutput, TxOutputPointer};
+use crate::{UtxoIndexer, UtxoIndexerBuilder};
+
+pub(crate) const MAX_ROLLBACK_BLOCKS: usize = 10;
+
+/// Opens a fresh database in a temporary directory, which is removed when the guard is dropped
+pub(crate) fn temp_db() -> (tempfile::TempDir, Db) {
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let db = Db::new(
+        dir.path().to_str().expect("non utf-8 temp dir"),
+        MAX_ROLLBACK_BLOCKS,
+    )
+    .expect("failed to open db");
+    (dir, db)
+}
+
+/// Builds an unfiltered utxo indexer, returning it alongside an indexer list containing it
+pub(crate) fn utxo_indexer(db: &Db) -> (Arc<Mutex<UtxoIndexer>>, IndexerList) {
+    let indexer = UtxoIndexerBuilder::new("utxo")
+        .build(&db.env)
+        .expect("failed to build utxo indexer");
+    indexer_list(indexer)
+}
+
+/// Shares the indexer, returning it alongside an indexer list containing it
+pub(crate) fn indexer_list<I: Indexer + Send + 'static>(
+    indexer: I,
+) -> (Arc<Mutex<I>>, IndexerList) {
+    let indexer = Arc::new(Mutex::new(indexer));
+    let indexers: IndexerList = vec![indexer.clone() as Arc<Mutex<dyn Indexer + Send>>];
+    (indexer, indexers)
+}
+
+/// Rolls the indexer forward over block 1 at slot 10 and block 2 at slot 20, holding the given
+/// txs, calls `forward` on it with both applied, then rolls back to block 1. Returns it
+/// alongside its indexer list, for checking the rolled back state or rolling back further.
+pub(crate) fn roll_forward_and_back<I: Indexer + Send + 'static>(
+    db: &Db,
+    indexer: I,
+    [first, second]: [Vec<Tx>; 2],
+    forward: impl FnOnce(&I),
+) -> (Arc<Mutex<I>>, IndexerList) {
+    let (indexer, indexers) = indexer_list(indexer);
+    db.roll_forward(&indexers, &[block(1, 10, first), block(2, 20, second)])
+        .expect("failed to roll forward");
+    forward(&indexer.lock().expect("indexer mutex poisoned"));
+    db.roll_backward(&indexers, &Point::Specific(10, hash::<32>(1).to_vec()))
+        .expect("failed to roll back");
+    (indexer, indexers)
+}
+
+/// Addresses of the indexer's utxos, ordered by pointer
+pub(crate) fn utxo_addresses(indexer: &Mutex<UtxoIndexer>) -> Vec<Vec<u8>> {
+    let indexer = indexer.lock().expect("indexer mutex poisoned");
+    let utxos = indexer.utxos().expect("failed to read utxos");
+    utxos
+        .into_iter()
+        .map(|(_, output)| output.address)
+        .collect()
+}
+
+/// Deterministic hash derived from an id
+pub(crate) fn hash<const BYTES: usize>(id: u64) -> Hash<BYTES> {
+    let mut bytes = [0; BYTES];
+    bytes[..8].copy_from_slice(&id.to_be_bytes());
+    Hash(bytes)
+}
+
+pub(crate) fn block(number: u64, slot: u64, txs: Vec<Tx>) -> Block {
+    Block {
+        era: Era::Conway,
+        hash: hash(number),
+        number,
+        slot,
+        size: 0,
+        txs,
+        datums: BTreeMap::new(),
+    }
+}
+
+pub(crate) fn tx(id: u64, inputs: Vec<TxOutputPointer>, outputs: Vec<TxOutput>) -> Tx {
+    Tx {
+        hash: hash(id),
+        inputs,
+        outputs,
+        collateral: vec![],
+        collateral_return: None,
+        reference_inputs: vec![],
+        mints: vec![],
+        withdrawals: vec![],
+        scripts: vec![],
+        native_scripts: vec![],
+        metadata: vec![],
+        governance: Governance::default(),
+        stake_certs: vec![],
+        valid: true,
+    }
+}
+
+pub(crate) fn output(address: &[u8], lovelace: u64) -> TxOutput {
+    TxOutput {
+        address: address.to_vec(),
+        lovelace,
+        assets: vec![],
+        datum_hash: None,
+        script_ref: None,
+    }
+}
+
+pub(crate) fn pointer(tx_id: u64, index: usize) -> TxOutputPointer {
+    TxOutputPointer::new(hash(tx_id), index)
+}
+
+/// CBOR of a Conway tx spending one input into a 1 ada output, with extra body fields (in key
+/// order, after the fee) and the auxiliary data given in hex
+pub(crate) fn conway_tx(extra_fields: &[&str], aux_data: &str) -> Vec<u8> {
+    let input = format!("825820{}00", "01".repeat(32));
+    let output = format!("82581d61{}1a000f4240", "11".repeat(28));
+    let header = format!("{:02x}", 0xa3 + extra_fields.len());
+    let body = format!(
+        "{header} 00 81 {input} 01 81 {output} 02 1903e8 {}",
+        extra_fields.concat()
+    );
+    hex::decode(format!("84 {body} a0 f5 {aux_data}").replace(' ', "")).expect("invalid hex")
+}
@@ -0,0 +1,322 @@
+use pallas::network::miniprotocols::Point;
+use tonic::{Request, Response, Status};
+use utxorpc_spec::utxorpc::v1alpha::cardano;
+use utxorpc_spec::utxorpc::v1alpha::query::{
+    AnyUtxoData, AnyUtxoPattern, ChainPoint, ReadDataRequest, ReadDataResponse, ReadParamsRequest,
+    ReadParamsResponse, ReadUtxosRequest, ReadUtxosResponse, SearchUtxosRequest,
+    SearchUtxosResponse, TxoRef, UtxoPredicate, any_utxo_data, any_utxo_pattern,
+    query_service_server,
+};
+
+use crate::db::Db;
+use crate::indexer::utxo::UtxoIndexer;
+use crate::primitives::{AssetId, Hash, TxOutput, TxOutputPointer};
+
+/// Serves the read queries of the utxorpc `QueryService` from a [`UtxoIndexer`]: UTxOs by
+/// reference, searching them by exact address or by asset, and the ledger tip. Only what the
+/// indexer holds is visible, so with address or asset filters the searches only cover those.
+///
+/// The raw CBOR of outputs isn't kept, so `native_bytes` is always empty and clients have to
+/// use the parsed Cardano output. Protocol parameters and datums aren't indexed either, those
+/// queries return `UNIMPLEMENTED`.
+#[derive(Clone)]
+pub struct UtxoRpcService {
+    db: Db,
+    utxo: UtxoIndexer,
+}
+
+impl UtxoRpcService {
+    /// `utxo` is typically a clone of the indexer handed to [`Sync`](crate::Sync): clones share
+    /// the tables, so queries see each block once it's committed.
+    pub fn new(db: &Db, utxo: &UtxoIndexer) -> Self {
+        Self {
+            db: db.clone(),
+            utxo: utxo.clone(),
+        }
+    }
+
+    /// Wraps the service for adding to a `tonic` server
+    pub fn into_server(self) -> query_service_server::QueryServiceServer<Self> {
+        query_service_server::QueryServiceServer::new(self)
+    }
+
+    fn ledger_tip(&self) -> Result<Option<ChainPoint>, Status> {
+        match self.db.tip().map_err(internal)? {
+            Point::Origin => Ok(None),
+            Point::Specific(slot, hash) => {
+                let height = self
+                    .db
+                    .block_by_slot(slot.into())
+                    .map_err(internal)?
+                    .map(|block| block.number)
+                    .unwrap_or_default();
+                Ok(Some(ChainPoint {
+                    slot,
+                    hash: hash.into(),
+                    height,
+                    ..Default::default()
+                }))
+            }
+        }
+    }
+
+    /// UTxOs matching the predicate. Only a single address or asset pattern is supported, since
+    /// each maps onto one of the indexer's secondary indexes.
+    fn search(&self, predicate: UtxoPredicate) -> Result<Vec<(TxOutputPointer, TxOutput)>, Status> {
+        let unsupported = || Status::unimplemented("only a single address or asset is supported");
+        if !predicate.not.is_empty() || !predicate.all_of.is_empty() || !predicate.any_of.is_empty()
+        {
+            return Err(unsupported());
+        }
+        let Some(AnyUtxoPattern {
+            utxo_pattern: Some(any_utxo_pattern::UtxoPattern::Cardano(pattern)),
+        }) = predicate.r#match
+        else {
+            return Err(unsupported());
+        };
+
+        match (pattern.address, pattern.asset) {
+            (Some(address), None)
+                if address.payment_part.is_empty() && address.delegation_part.is_empty() =>
+            {
+                self.utxo
+                    .utxos_by_address(&address.exact_address.to_vec())
+                    .map_err(internal)
+            }
+            (None, Some(asset)) => {
+                let policy = <[u8; 28]>::try_from(&asset.policy_id[..])
+                    .map_err(|_| Status::invalid_argument("policy id must be 28 bytes"))?;
+                let name = (!asset.asset_name.is_empty()).then(|| asset.asset_name.to_vec());
+                self.utxo
+                    .utxos_with_all_assets(&[AssetId::new(Hash(policy), name)])
+                    .map_err(internal)
+            }
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl query_service_server::QueryService for UtxoRpcService {
+    async fn read_params(
+        &self,
+        _: Request<ReadParamsRequest>,
+    ) -> Result<Response<ReadParamsResponse>, Status> {
+        Err(Status::unimplemented("protocol parameters aren't indexed"))
+    }
+
+    async fn read_utxos(
+        &self,
+        request: Request<ReadUtxosRequest>,
+    ) -> Result<Response<ReadUtxosResponse>, Status> {
+        let mut items = vec![];
+        for key in request.into_inner().keys {
+            let hash = <[u8; 32]>::try_from(&key.hash[..])
+                .map_err(|_| Status::invalid_argument("tx hash must be 32 bytes"))?;
+            let pointer = TxOutputPointer {
+                hash: Hash(hash),
+                index: key.index.into(),
+            };
+            // Like other utxorpc servers, spent or unknown references are left out
+            if let Some(output) = self.utxo.utxo(&pointer).map_err(internal)? {
+                items.push(utxo_data(&pointer, &output));
+            }
+        }
+        Ok(Response::new(ReadUtxosResponse {
+            items,
+            ledger_tip: self.ledger_tip()?,
+            ..Default::default()
+        }))
+    }
+
+    /// Pages are offsets into the results ordered by output reference, so a page may skip or
+    /// repeat UTxOs if the set changes between requests
+    async fn search_utxos(
+        &self,
+        request: Request<SearchUtxosRequest>,
+    ) -> Result<Response<SearchUtxosResponse>, Status> {
+        let request = request.into_inner();
+        let predicate = request
+            .predicate
+            .ok_or_else(|| Status::invalid_argument("missing predicate"))?;
+        let mut utxos = self.search(predicate)?;
+        utxos.sort_by(|(a, _), (b, _)| (&a.hash[..], a.index).cmp(&(&b.hash[..], b.index)));
+
+        let start = match request.start_token.as_str() {
+            "" => 0,
+            token => token
+                .parse::<usize>()
+                .map_err(|_| Status::invalid_argument("invalid start token"))?,
+        };
+        let end = match usize::try_from(request.max_items) {
+            Ok(max_items) if max_items > 0 => start.saturating_add(max_items).min(utxos.len()),
+            _ => utxos.len(),
+        };
+        let next_token = match end < utxos.len() {
+            true => end.to_string(),
+            false => String::new(),
+        };
+        let items = utxos
+            .get(start.min(end)..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|(pointer, output)| utxo_data(pointer, output))
+            .collect();
+        Ok(Response::new(SearchUtxosResponse {
+            items,
+            ledger_tip: self.ledger_tip()?,
+            next_token,
+            ..Default::default()
+        }))
+    }
+
+    async fn read_data(
+        &self,
+        _: Request<ReadDataRequest>,
+    ) -> Result<Response<ReadDataResponse>, Status> {
+        Err(Status::unimplemented("datums aren't indexed"))
+    }
+}
+
+fn utxo_data(pointer: &TxOutputPointer, output: &TxOutput) -> AnyUtxoData {
+    AnyUtxoData {
+        txo_ref: Some(TxoRef {
+            hash: pointer.hash.to_vec().into(),
+            index: pointer.index as u32,
+            ..Default::default()
+        }),
+        parsed_state: Some(any_utxo_data::ParsedState::Cardano(cardano_output(output))),
+        ..Default::default()
+    }
+}
+
+fn cardano_output(output: &TxOutput) -> cardano::TxOutput {
+    // Assets are stored individually, utxorpc groups them by policy
+    let mut multiassets: Vec<cardano::Multiasset> = vec![];
+    for asset in output.assets.iter() {
+        let policy_id = asset.policy.to_vec();
+        let asset = cardano::Asset {
+            name: asset.name.clone().into(),
+            output_coin: asset.quantity,
+            ..Default::default()
+        };
+        match multiassets
+            .iter_mut()
+            .find(|m| m.policy_id[..] == policy_id[..])
+        {
+            Some(multiasset) => multiasset.assets.push(asset),
+            None => multiassets.push(cardano::Multiasset {
+                policy_id: policy_id.into(),
+                assets: vec![asset],
+                ..Default::default()
+            }),
+        }
+    }
+    cardano::TxOutput {
+        address: output.address.clone().into(),
+        coin: output.lovelace,
+        assets: multiassets,
+        datum: output.datum_hash.as_ref().map(|hash| cardano::Datum {
+            hash: hash.to_vec().into(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn internal(err: anyhow::Error) -> Status {
+    Status::internal(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use utxorpc_spec::utxorpc::v1alpha::query::query_service_server::QueryService;
+
+    use super::*;
+    use crate::UtxoIndexerBuilder;
+    use crate::indexer::{Indexer, IndexerList};
+    use crate::primitives::Asset;
+    use crate::test_utils::{block, hash, output, temp_db, tx};
+
+    #[tokio::test]
+    async fn test_utxorpc_queries() {
+        let (_dir, db) = temp_db();
+        let utxo = UtxoIndexerBuilder::new("utxo").build(&db.env).unwrap();
+        let indexers: IndexerList =
+            vec![Arc::new(Mutex::new(utxo.clone())) as Arc<Mutex<dyn Indexer + Send>>];
+        let mut with_asset = output(b"b", 2);
+        with_asset.assets = vec![Asset {
+            policy: hash(7),
+            name: b"token".to_vec(),
+            quantity: 3,
+        }];
+        let outputs = vec![output(b"a", 1), with_asset, output(b"a", 5)];
+        db.roll_forward(&indexers, &[block(1, 10, vec![tx(1, vec![], outputs)])])
+            .unwrap();
+        let service = UtxoRpcService::new(&db, &utxo);
+
+        let key = |index| TxoRef {
+            hash: hash::<32>(1).to_vec().into(),
+            index,
+            ..Default::default()
+        };
+        let read = service
+            .read_utxos(Request::new(ReadUtxosRequest {
+                keys: vec![key(1), key(9)],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(read.items.len(), 1);
+        let Some(any_utxo_data::ParsedState::Cardano(parsed)) = &read.items[0].parsed_state else {
+            panic!("expected a cardano output");
+        };
+        assert_eq!(parsed.coin, 2);
+        assert_eq!(parsed.assets[0].assets[0].output_coin, 3);
+        assert_eq!(read.ledger_tip.unwrap().slot, 10);
+
+        let search = |pattern: cardano::TxOutputPattern, max_items, start_token: &str| {
+            service.search_utxos(Request::new(SearchUtxosRequest {
+                predicate: Some(UtxoPredicate {
+                    r#match: Some(AnyUtxoPattern {
+                        utxo_pattern: Some(any_utxo_pattern::UtxoPattern::Cardano(pattern)),
+                    }),
+                    ..Default::default()
+                }),
+                max_items,
+                start_token: start_token.to_string(),
+                ..Default::default()
+            }))
+        };
+        let by_address = cardano::TxOutputPattern {
+            address: Some(cardano::AddressPattern {
+                exact_address: b"a".to_vec().into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let page = search(by_address.clone(), 1, "")
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.next_token, "1");
+        let page = search(by_address, 1, "1").await.unwrap().into_inner();
+        assert_eq!(page.items[0].txo_ref.as_ref().unwrap().index, 2);
+        assert_eq!(page.next_token, "");
+
+        let by_asset = cardano::TxOutputPattern {
+            asset: Some(cardano::AssetPattern {
+                policy_id: hash::<28>(7).to_vec().into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let page = search(by_asset, 0, "").await.unwrap().into_inner();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].txo_ref.as_ref().unwrap().index, 1);
+    }
+}
@@ -1,106 +1,740 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
 use anyhow::{Context, Result};
 use pallas::ledger::traverse::MultiEraBlock;
 use pallas::network::miniprotocols::Point;
-use tokio::sync::mpsc;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore, mpsc, watch};
 
-use crate::db::Db;
-use crate::indexer::IndexerList;
+use crate::archive::ArchiveSink;
+use crate::db::{Db, WriteRateLimit};
+use crate::indexer::{IndexerList, parse_options};
+use crate::metrics::SyncPhase;
+use crate::primitives::{Block, ParseOptions};
 use crate::sync::SyncEvent;
 
-const BUFFER_SIZE: usize = 2000;
-
 pub struct Writer {
-    tx: mpsc::Sender<SyncEvent>,
+    /// Events along with the share of the byte budget they hold until committed
+    tx: mpsc::UnboundedSender<(SyncEvent, OwnedSemaphorePermit)>,
+    /// Bytes of block CBOR that may be buffered, see `DbOptions::write_buffer_bytes`. Blocks
+    /// hold their share until committed, so decoded blocks waiting for the commit interval
+    /// count against it too.
+    buffer: Arc<Semaphore>,
+    buffer_bytes: usize,
     shutdown_tx: mpsc::Sender<()>,
     task: tokio::task::JoinHandle<Result<()>>,
+    /// Events sent but not yet committed
+    in_flight: Arc<AtomicUsize>,
+    flush: Arc<FlushRequests>,
+    handle: WriterHandle,
+}
+
+/// Callers waiting for the writer to commit the blocks it applied without waiting for the
+/// commit interval: [`Writer::wait_until_flushed`], and [`Writer::reserve`] while those blocks
+/// hold the byte budget. The writer commits them once it runs out of events to apply.
+#[derive(Default)]
+struct FlushRequests {
+    waiters: AtomicUsize,
+    /// Wakes the writer if it's already out of events
+    notify: Notify,
+}
+
+impl FlushRequests {
+    /// Registers a waiter until the guard is dropped
+    fn request(&self) -> FlushRequest<'_> {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        self.notify.notify_one();
+        FlushRequest(self)
+    }
+
+    fn requested(&self) -> bool {
+        self.waiters.load(Ordering::SeqCst) > 0
+    }
+}
+
+struct FlushRequest<'a>(&'a FlushRequests);
+
+impl Drop for FlushRequest<'_> {
+    fn drop(&mut self) {
+        self.0.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Block applied by [`Writer::write_event`], for pacing the writer
+struct Applied {
+    bytes: usize,
+    /// Whether the pending blocks were committed along with it
+    committed: bool,
+    near_tip: bool,
+}
+
+/// Paces the writer to a [`WriteRateLimit`], see
+/// [`DbOptions::write_rate_limit`](crate::db::DbOptions::write_rate_limit). Runs on tokio's
+/// clock, which the writer sleeps on.
+struct Throttle {
+    limit: WriteRateLimit,
+    since: tokio::time::Instant,
+    /// Bytes or commits written since `since`
+    written: u64,
+}
+
+impl Throttle {
+    fn new(limit: WriteRateLimit) -> Self {
+        Self {
+            limit,
+            since: tokio::time::Instant::now(),
+            written: 0,
+        }
+    }
+
+    /// Records the block, returning how long to wait for the writes so far to keep to the limit
+    fn record(&mut self, applied: &Applied) -> Option<Duration> {
+        if applied.near_tip {
+            // Starts over once sync falls behind again, rather than catching up in a burst
+            self.since = tokio::time::Instant::now();
+            self.written = 0;
+            return None;
+        }
+        let (written, rate) = match self.limit {
+            WriteRateLimit::BytesPerSec(rate) => (applied.bytes as u64, rate),
+            WriteRateLimit::CommitsPerSec(rate) => (applied.committed as u64, rate),
+        };
+        self.written += written;
+        let target = Duration::from_secs_f64(self.written as f64 / rate.max(1) as f64);
+        let elapsed = self.since.elapsed();
+        match target.checked_sub(elapsed) {
+            Some(delay) if !delay.is_zero() => Some(delay),
+            _ => {
+                // Behind the limit, e.g. after the node was slow to serve blocks. Idle time
+                // mustn't turn into a burst later on.
+                if elapsed - target > Duration::from_secs(1) {
+                    self.since = tokio::time::Instant::now();
+                    self.written = 0;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Cloneable handle for pausing the writer while sync is running, e.g. to take a snapshot of a
+/// quiescent database
+#[derive(Clone)]
+pub struct WriterHandle {
+    paused: Arc<watch::Sender<bool>>,
+    quiescent: watch::Receiver<bool>,
+}
+
+impl WriterHandle {
+    /// Stops draining the event channel. Sync keeps running until the channel fills up, at which
+    /// point it blocks until the writer is resumed.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Waits until the writer is paused and has committed all the blocks it applied, such that
+    /// nothing writes to the database until it's resumed. Events still queued in the channel
+    /// are written after resuming.
+    pub async fn paused_and_flushed(&self) -> Result<()> {
+        let mut quiescent = self.quiescent.clone();
+        quiescent
+            .wait_for(|quiescent| *quiescent)
+            .await
+            .context("writer stopped")?;
+        Ok(())
+    }
+
+    /// Snapshots the database between block commits, see [`Db::snapshot`]. Pauses the writer
+    /// until the copy is written, so it's also consistent with whatever the indexers flushed
+    /// outside the database on commit. Resumes the writer afterwards even if snapshotting
+    /// failed, so don't combine it with a manual [`WriterHandle::pause`].
+    pub async fn snapshot(
+        &self,
+        db: &Db,
+        path: impl AsRef<std::path::Path>,
+        overwrite: bool,
+    ) -> Result<()> {
+        self.pause();
+        let res = match self.paused_and_flushed().await {
+            Ok(()) => db.snapshot(path, overwrite),
+            Err(e) => Err(e),
+        };
+        self.resume();
+        res
+    }
 }
 
 impl Writer {
     pub fn new(db: &Db, indexers: &IndexerList) -> Self {
-        let (tx, mut rx) = mpsc::channel::<SyncEvent>(BUFFER_SIZE);
+        Self::with_archive(db, indexers, None)
+    }
+
+    /// Like [`Writer::new`], also appending every event to `archive`, see [`ArchiveSink`] for
+    /// how it's kept in step with the database
+    pub fn with_archive(db: &Db, indexers: &IndexerList, mut archive: Option<ArchiveSink>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(SyncEvent, OwnedSemaphorePermit)>();
+        // Permits are acquired in u32 chunks
+        let buffer_bytes = db.write_buffer_bytes.clamp(1, u32::MAX as usize);
+        let buffer = Arc::new(Semaphore::new(buffer_bytes));
         let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+        let (paused_tx, mut paused_rx) = watch::channel(false);
+        let (quiescent_tx, quiescent_rx) = watch::channel(false);
+        let in_flight = Arc::new(AtomicUsize::new(0));
 
         let db = db.clone();
         let indexers = indexers.clone();
+        let parse_options = parse_options(&indexers);
+        let mut throttle = db.write_rate_limit.map(Throttle::new);
+        let task_in_flight = in_flight.clone();
+        let task_buffer = buffer.clone();
+        let flush = Arc::new(FlushRequests::default());
+        let task_flush = flush.clone();
+        let seal = db.env.seal_tables();
         let task = tokio::spawn(async move {
+            // Released when the task ends, including when it's aborted
+            let _seal = seal;
+            // Drops whatever the archive holds past the tip, e.g. after a crash
+            if let Some(archive) = archive.as_mut() {
+                archive.roll_backward(&db.tip()?)?;
+            }
+            // Blocks applied but not yet committed, see `DbOptions::commit_interval`, and the
+            // permits of the events they came from
+            let mut pending = Vec::with_capacity(db.commit_interval);
+            let mut uncommitted = Vec::with_capacity(db.commit_interval);
+            // Until when the throttle holds off the next event. Waited for alongside the other
+            // branches, so stopping or pausing doesn't wait out the delay.
+            let mut throttled_until = None;
             loop {
+                if *paused_rx.borrow_and_update() {
+                    Writer::commit(
+                        &db,
+                        &indexers,
+                        &mut pending,
+                        &mut uncommitted,
+                        &task_in_flight,
+                    )?;
+
+                    quiescent_tx.send_replace(true);
+                    tokio::select! {
+                        _ = shutdown_rx.recv() => break,
+                        res = async { paused_rx.wait_for(|paused| !paused).await.map(|_| ()) } => {
+                            if res.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    quiescent_tx.send_replace(false);
+                    continue;
+                }
+
                 tokio::select! {
                     _ = shutdown_rx.recv() => {
                         break;
                     }
-                    Some(event) = rx.recv() => {
-                        let buffer_usage = (BUFFER_SIZE - rx.capacity()) as f64 / BUFFER_SIZE as f64 * 100.;
-                        Writer::write_event(event, &indexers, &db, buffer_usage)?;
+                    _ = paused_rx.changed() => continue,
+                    _ = task_flush.notify.notified() => {}
+                    _ = tokio::time::sleep_until(throttled_until.unwrap_or_else(tokio::time::Instant::now)), if throttled_until.is_some() => {
+                        throttled_until = None;
+                    }
+                    Some((event, permit)) = rx.recv(), if throttled_until.is_none() => {
+                        let buffered = buffer_bytes - task_buffer.available_permits();
+                        let buffer_usage = buffered as f64 / buffer_bytes as f64 * 100.;
+                        let applied = Writer::write_event(event, &indexers, &db, &mut archive, parse_options, &mut pending, buffer_usage)?;
+                        uncommitted.push(permit);
+                        if pending.is_empty() {
+                            // Committed along with the event
+                            Writer::release(&mut uncommitted, &task_in_flight);
+                        }
+                        if let (Some(throttle), Some(applied)) = (throttle.as_mut(), applied)
+                            && let Some(delay) = throttle.record(&applied)
+                        {
+                            throttled_until = Some(tokio::time::Instant::now() + delay);
+                        }
                     }
                     else => break,
                 }
+                if task_flush.requested() && rx.is_empty() && !pending.is_empty() {
+                    Writer::commit(
+                        &db,
+                        &indexers,
+                        &mut pending,
+                        &mut uncommitted,
+                        &task_in_flight,
+                    )?;
+                }
+            }
+            Writer::commit(
+                &db,
+                &indexers,
+                &mut pending,
+                &mut uncommitted,
+                &task_in_flight,
+            )?;
+            if let Some(archive) = archive.as_mut() {
+                archive.flush()?;
             }
             Ok(())
         });
         Self {
             tx,
+            buffer,
+            buffer_bytes,
             shutdown_tx,
             task,
+            in_flight,
+            flush,
+            handle: WriterHandle {
+                paused: Arc::new(paused_tx),
+                quiescent: quiescent_rx,
+            },
         }
     }
 
+    /// Queues the event, waiting while the buffered blocks, queued or applied but not yet
+    /// committed, exceed the byte budget. A block larger than the whole budget waits for the
+    /// buffer to drain, then goes through on its own.
     pub async fn send(&self, event: SyncEvent) -> Result<()> {
-        self.tx.send(event).await.context("writer channel closed")?;
+        let bytes = match &event {
+            SyncEvent::RollForward(cbor, _) => cbor.len(),
+            SyncEvent::RollBackward(_) => 0,
+        };
+        let permit = self.reserve(bytes).await?;
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if self.tx.send((event, permit)).is_err() {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            anyhow::bail!("writer channel closed");
+        }
         Ok(())
     }
 
+    /// Waits until `bytes` of the byte budget are free and holds them until the permit is
+    /// dropped, e.g. to make room for a batch of blocks before fetching it
+    pub async fn reserve(&self, bytes: usize) -> Result<OwnedSemaphorePermit> {
+        let bytes = bytes.min(self.buffer_bytes) as u32;
+        if let Ok(permit) = self.buffer.clone().try_acquire_many_owned(bytes) {
+            return Ok(permit);
+        }
+        // Blocks waiting for the commit interval may be holding the budget
+        let _request = self.flush.request();
+        self.buffer
+            .clone()
+            .acquire_many_owned(bytes)
+            .await
+            .context("writer buffer closed")
+    }
+
+    /// Waits until every event sent so far has been committed, or the writer stopped. Blocks
+    /// waiting for the commit interval are committed as soon as the writer runs out of events.
     pub async fn wait_until_flushed(&self) -> Result<()> {
-        // spin until all pending events have been flushed
-        while self.tx.strong_count() > 0 {
+        let _request = self.flush.request();
+        // spin until all pending events have been committed, or the writer stopped
+        while self.in_flight.load(Ordering::SeqCst) > 0 && !self.task.is_finished() {
             tokio::time::sleep(tokio::time::Duration::from_micros(10)).await;
         }
         Ok(())
     }
 
+    pub fn handle(&self) -> WriterHandle {
+        self.handle.clone()
+    }
+
+    pub fn pause(&self) {
+        self.handle.pause()
+    }
+
+    pub fn resume(&self) {
+        self.handle.resume()
+    }
+
+    pub async fn paused_and_flushed(&self) -> Result<()> {
+        self.handle.paused_and_flushed().await
+    }
+
+    /// Commits the pending blocks and waits for the writer to finish, however long that takes
     pub async fn stop(self) -> Result<()> {
-        drop(self.tx);
-        if let Err(e) = self.shutdown_tx.send(()).await {
+        Self::signal_shutdown(self.tx, &self.shutdown_tx).await;
+        self.task.await?
+    }
+
+    /// Like [`Writer::stop`], but gives up after `timeout`, e.g. when an indexer deadlocked. The
+    /// writer task is then aborted, though an indexer stuck in blocking code keeps its thread
+    /// busy until it returns. Blocks pending at that point are lost, but the committed state is
+    /// consistent, so the caller can still persist the database and exit.
+    pub async fn stop_timeout(self, timeout: Duration) -> Result<()> {
+        Self::signal_shutdown(self.tx, &self.shutdown_tx).await;
+        let abort = self.task.abort_handle();
+        match tokio::time::timeout(timeout, self.task).await {
+            Ok(res) => res?,
+            Err(_) => {
+                tracing::warn!(?timeout, "writer didn't stop in time, aborting it");
+                abort.abort();
+                Err(anyhow::anyhow!("writer didn't stop within {timeout:?}"))
+            }
+        }
+    }
+
+    async fn signal_shutdown(
+        tx: mpsc::UnboundedSender<(SyncEvent, OwnedSemaphorePermit)>,
+        shutdown_tx: &mpsc::Sender<()>,
+    ) {
+        drop(tx);
+        if let Err(e) = shutdown_tx.send(()).await {
             tracing::error!(error = ?e, "error while sending shutdown signal to writer");
         }
-        self.task.await?
+    }
+
+    /// Commits the pending blocks, then releases the events they came from
+    fn commit(
+        db: &Db,
+        indexers: &IndexerList,
+        pending: &mut Vec<Block>,
+        uncommitted: &mut Vec<OwnedSemaphorePermit>,
+        in_flight: &AtomicUsize,
+    ) -> Result<()> {
+        db.roll_forward(indexers, pending)?;
+        pending.clear();
+        Self::release(uncommitted, in_flight);
+        Ok(())
+    }
+
+    /// Returns the byte budget of committed events and counts them as no longer in flight
+    fn release(uncommitted: &mut Vec<OwnedSemaphorePermit>, in_flight: &AtomicUsize) {
+        in_flight.fetch_sub(uncommitted.len(), Ordering::SeqCst);
+        uncommitted.clear();
     }
 
     fn write_event(
         event: SyncEvent,
         indexers: &IndexerList,
         db: &Db,
+        archive: &mut Option<ArchiveSink>,
+        parse_options: ParseOptions,
+        pending: &mut Vec<Block>,
         buffer_usage: f64,
-    ) -> Result<()> {
+    ) -> Result<Option<Applied>> {
         match event {
             SyncEvent::RollForward(cbor, tip) => {
-                let block = MultiEraBlock::decode(&cbor)?;
-                db.roll_forward(indexers, &block)?;
+                let block = db.timed(SyncPhase::Decode, || -> Result<_> {
+                    Ok(Block::parse(&MultiEraBlock::decode(&cbor)?, parse_options))
+                })?;
+                db.validate_block(&block);
+                let (number, slot) = (block.number, block.slot);
+                pending.push(block);
+                if let Some(archive) = archive.as_mut() {
+                    archive.roll_forward(slot, &cbor)?;
+                }
 
                 let tip_slot = tip.0.slot_or_default();
-                let near_tip = tip_slot.saturating_sub(200) <= block.slot();
-                if near_tip || block.number() % 10000 == 0 {
-                    db.trim_volatile()?;
-                    db.persist()?;
-
+                let near_tip = tip_slot.saturating_sub(200) <= slot;
+                let checkpoint = near_tip || number % 10000 == 0;
+                let committed = checkpoint || pending.len() >= db.commit_interval;
+                if committed {
+                    db.roll_forward(indexers, pending)?;
+                    pending.clear();
+                }
+                if checkpoint {
+                    Self::checkpoint(db, archive)?;
                     tracing::info!(
-                        block = block.number(),
-                        slot = block.slot(),
-                        slots_to_tip = tip_slot.saturating_sub(block.slot()),
+                        block = number,
+                        slot,
+                        slots_to_tip = tip_slot.saturating_sub(slot),
                         buffer_usage = format!("{buffer_usage:.2}%"),
                         "RollForward"
                     );
                 }
+                Ok(Some(Applied {
+                    bytes: cbor.len(),
+                    committed,
+                    near_tip,
+                }))
             }
             SyncEvent::RollBackward(point) => {
-                db.roll_backward(indexers, &point)?;
-                match &point {
-                    Point::Origin => tracing::info!(slot = 0, origin = true, "RollBackward"),
-                    Point::Specific(slot, _) => {
-                        tracing::info!(?slot, origin = false, "RollBackward")
-                    }
-                };
+                db.roll_forward(indexers, pending)?;
+                pending.clear();
+
+                let summary = db.roll_backward(indexers, &point)?;
+                if let Some(archive) = archive.as_mut() {
+                    archive.roll_backward(&point)?;
+                }
+                tracing::info!(
+                    slot = summary.to_slot,
+                    origin = matches!(point, Point::Origin),
+                    depth_blocks = summary.blocks,
+                    depth_slots = summary.slots(),
+                    txs = summary.txs,
+                    datums = summary.datums,
+                    indexers = ?summary.indexers,
+                    "RollBackward"
+                );
+                Ok(None)
             }
         }
+    }
+
+    /// Trims the volatile window and persists the database. Both run on the writer task rather
+    /// than in the background: trimming needs the write txn, which LMDB only hands to one
+    /// thread at a time, so the next batch would wait for it anyway.
+    fn checkpoint(db: &Db, archive: &mut Option<ArchiveSink>) -> Result<()> {
+        let start = Instant::now();
+        db.timed(SyncPhase::Trim, || db.trim_volatile())?;
+        let trim = start.elapsed();
+        db.timed(SyncPhase::Persist, || -> Result<()> {
+            // The archive must never be behind the durable state of the database
+            if let Some(archive) = archive.as_mut() {
+                archive.flush()?;
+            }
+            db.persist()
+        })?;
+        let persist = start.elapsed() - trim;
+        tracing::debug!(?trim, ?persist, "Checkpoint");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicBool;
+
+    use pallas::network::miniprotocols::chainsync::Tip;
+
+    use super::*;
+    use crate::AssetHistoryIndexer;
+    use crate::archive::ArchiveRecord;
+    use crate::db::DbOptions;
+    use crate::indexer::Indexer;
+    use crate::test_utils::{block, hash, output, temp_db, tx, utxo_indexer};
+
+    /// Hangs when clearing, as if deadlocked
+    struct StuckIndexer(Arc<AtomicBool>);
+
+    impl Indexer for StuckIndexer {
+        fn id(&self) -> &str {
+            "stuck"
+        }
+
+        fn clear(&self, _: &mut heed::RwTxn) -> Result<()> {
+            self.0.store(true, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_secs(1));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_late_table_creation() {
+        let (_dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let writer = Writer::new(&db, &indexers);
+        let err = match AssetHistoryIndexer::new("late", &db.env, vec![]) {
+            Ok(_) => panic!("created a table while the writer is running"),
+            Err(err) => err,
+        };
+        assert_eq!(
+            err.to_string(),
+            "cannot create table late_asset_history while sync is running, create it before starting sync"
+        );
+
+        writer.stop().await.unwrap();
+        AssetHistoryIndexer::new("late", &db.env, vec![]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_archive_rollback_markers() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        db.roll_forward(&indexers, &[block(1, 10, vec![]), block(2, 20, vec![])])
+            .unwrap();
+
+        let archive_dir = dir.path().join("archive");
+        let archive = ArchiveSink::files(&archive_dir, crate::ArchiveRotation::Never).unwrap();
+        let writer = Writer::with_archive(&db, &indexers, Some(archive));
+        let point = Point::Specific(10, hash::<32>(1).to_vec());
+        writer
+            .send(SyncEvent::RollBackward(point.clone()))
+            .await
+            .unwrap();
+        writer.stop().await.unwrap();
+
+        let mut file = std::fs::File::open(crate::archive::segment_path(&archive_dir, 0)).unwrap();
+        let mut records = vec![];
+        while let Some(record) = ArchiveRecord::read(&mut file).unwrap() {
+            records.push(record);
+        }
+        // The tip when the writer started, then the rollback
+        assert_eq!(
+            records,
+            [
+                ArchiveRecord::RollBackward(Point::Specific(20, hash::<32>(2).to_vec())),
+                ArchiveRecord::RollBackward(point),
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_stop_timeout() {
+        let (_dir, db) = temp_db();
+        let stuck = Arc::new(AtomicBool::new(false));
+        let indexers: IndexerList = vec![
+            Arc::new(Mutex::new(StuckIndexer(stuck.clone()))) as Arc<Mutex<dyn Indexer + Send>>
+        ];
+        let writer = Writer::new(&db, &indexers);
+        writer
+            .send(SyncEvent::RollBackward(Point::Origin))
+            .await
+            .unwrap();
+        while !stuck.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let start = std::time::Instant::now();
+        assert!(
+            writer
+                .stop_timeout(Duration::from_millis(50))
+                .await
+                .is_err()
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_checkpoint_timings() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DbOptions::new(10).timings(true);
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = (1..=12)
+            .map(|number| block(number, number * 10, vec![]))
+            .collect::<Vec<_>>();
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        Writer::checkpoint(&db, &mut None).unwrap();
+        assert_eq!(db.describe().unwrap().volatile_blocks, 10);
+        let metrics = db.metrics();
+        assert_eq!(metrics.stats(SyncPhase::Trim).count, 1);
+        assert_eq!(metrics.stats(SyncPhase::Persist).count, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle() {
+        let applied = |bytes, committed, near_tip| Applied {
+            bytes,
+            committed,
+            near_tip,
+        };
+        // Rates are powers of two so the delays come out exact
+        let ms = Duration::from_millis;
+        let mut throttle = Throttle::new(WriteRateLimit::BytesPerSec(1024));
+        assert_eq!(throttle.record(&applied(512, false, false)), Some(ms(500)));
+        tokio::time::advance(ms(250)).await;
+        assert_eq!(throttle.record(&applied(256, false, false)), Some(ms(500)));
+        // Not throttled near the tip, and starting over afterwards
+        assert_eq!(throttle.record(&applied(512, true, true)), None);
+        assert_eq!(throttle.record(&applied(128, false, false)), Some(ms(125)));
+        // Idle for long enough to fall behind, which doesn't allow a burst later on
+        tokio::time::advance(ms(2000)).await;
+        assert_eq!(throttle.record(&applied(128, false, false)), None);
+        assert_eq!(throttle.record(&applied(128, false, false)), Some(ms(125)));
+
+        let mut throttle = Throttle::new(WriteRateLimit::CommitsPerSec(4));
+        assert_eq!(throttle.record(&applied(512, false, false)), None);
+        assert_eq!(throttle.record(&applied(512, true, false)), Some(ms(250)));
+    }
+
+    #[tokio::test]
+    async fn test_buffer_byte_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = DbOptions::new(10).write_buffer_bytes(3 * 1024 * 1024);
+        let db = Db::with_options(dir.path().to_str().unwrap(), options).unwrap();
+        let (_, indexers) = utxo_indexer(&db);
+        let writer = Writer::new(&db, &indexers);
+        // Nothing is written while paused, so the blocks stay buffered
+        writer.pause();
+        writer.paused_and_flushed().await.unwrap();
+
+        let block = || {
+            let tip = Tip(Point::Origin, 0);
+            SyncEvent::RollForward(vec![0; 1024 * 1024], tip)
+        };
+        for _ in 0..3 {
+            writer.send(block()).await.unwrap();
+        }
+        let full = tokio::time::timeout(Duration::from_millis(50), writer.send(block())).await;
+        assert!(full.is_err());
+        // Rollbacks carry no blocks, so they aren't held back
+        writer
+            .send(SyncEvent::RollBackward(Point::Origin))
+            .await
+            .unwrap();
+        writer.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause_snapshot_resume() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [
+            block(1, 10, vec![tx(1, vec![], vec![output(b"a", 1)])]),
+            block(2, 20, vec![]),
+        ];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let writer = Writer::new(&db, &indexers);
+        writer.pause();
+        writer.paused_and_flushed().await.unwrap();
+
+        // Queued while paused, so it must not be applied until resumed
+        let point = Point::Specific(10, hash::<32>(1).to_vec());
+        writer
+            .send(SyncEvent::RollBackward(point.clone()))
+            .await
+            .unwrap();
+
+        let snapshot = dir.path().join("snapshot");
+        db.snapshot(snapshot.join("data.mdb"), false).unwrap();
+        let copy = Db::new(snapshot.to_str().unwrap(), 10).unwrap();
+        assert_eq!(
+            copy.tip().unwrap(),
+            Point::Specific(20, hash::<32>(2).to_vec())
+        );
+
+        writer.resume();
+        writer.wait_until_flushed().await.unwrap();
+        assert_eq!(db.tip().unwrap(), point);
+        writer.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_through_handle() {
+        let (dir, db) = temp_db();
+        let (_, indexers) = utxo_indexer(&db);
+        let blocks = [block(1, 10, vec![]), block(2, 20, vec![])];
+        db.roll_forward(&indexers, &blocks).unwrap();
+
+        let writer = Writer::new(&db, &indexers);
+        let point = Point::Specific(10, hash::<32>(1).to_vec());
+        writer
+            .send(SyncEvent::RollBackward(point.clone()))
+            .await
+            .unwrap();
+        let snapshot = dir.path().join("snapshot");
+        writer
+            .handle()
+            .snapshot(&db, snapshot.join("data.mdb"), false)
+            .await
+            .unwrap();
+
+        // Resumed afterwards
+        writer.wait_until_flushed().await.unwrap();
+        assert_eq!(db.tip().unwrap(), point);
+        writer.stop().await.unwrap();
+
+        let copy = Db::open_read_only(snapshot.to_str().unwrap()).unwrap();
+        let tip = copy.tip().unwrap();
+        assert!(tip == point || tip == Point::Specific(20, hash::<32>(2).to_vec()));
+    }
+}